@@ -0,0 +1,78 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// The cost of encoding a record, e.g. a node logging at debug.
+//
+// Run with `cargo bench --bench encoded`; prints the time and the
+// allocations per record of each encoder.
+
+use cita_logger::encode::{BinaryEncoder, CefEncoder, CsvEncoder, Encoder, JsonEncoder};
+use log::{Level, Record};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const ITERATIONS: u32 = 1_000_000;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn bench(name: &str, encoder: &dyn Encoder) {
+    let mut buf = Vec::with_capacity(4096);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for height in 0..ITERATIONS {
+        buf.clear();
+        encoder
+            .encode(
+                &mut buf,
+                &Record::builder()
+                    .level(Level::Debug)
+                    .target("cita_executor")
+                    .line(Some(42))
+                    .args(format_args!(
+                        "executed tx {} of block {}",
+                        black_box(height),
+                        "0xabc"
+                    ))
+                    .build(),
+            )
+            .unwrap();
+        black_box(&buf);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{}: {:.0} ns per record, {:.2} allocations",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(ITERATIONS),
+        allocations as f64 / f64::from(ITERATIONS)
+    );
+}
+
+fn main() {
+    bench("json", &JsonEncoder::new());
+    bench("csv", &CsvEncoder::new());
+    bench("cef", &CefEncoder::new("Cryptape", "CITA", "1.0"));
+    bench("binary", &BinaryEncoder::new());
+}
@@ -0,0 +1,67 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// The cost of records below the level, e.g. `trace!` in block execution.
+//
+// Run with `cargo bench --bench filtered`; fails if a filtered-out record
+// allocates.
+
+use cita_logger::{trace, Builder, LogFavour};
+use log::LevelFilter;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const ITERATIONS: u32 = 10_000_000;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn bench(name: &str) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for height in 0..ITERATIONS {
+        trace!("executed tx {} of block {}", black_box(height), "0xabc");
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{}: {:.2} ns per record, {} allocations",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(ITERATIONS),
+        allocations
+    );
+    assert_eq!(allocations, 0, "filtered-out records allocated");
+}
+
+fn main() {
+    Builder::new(&LogFavour::Stdout("bench"))
+        .directive("", LevelFilter::Info)
+        .init();
+    bench("below the max level");
+
+    // Another module at trace raises the max level, so records reach the
+    // front-end filter
+    cita_logger::set_module_level("cita_executor", LevelFilter::Trace);
+    bench("below the module level");
+}
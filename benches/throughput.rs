@@ -0,0 +1,105 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// The cost of the records written to the log file: encoding them, then
+// encoding and writing them with a preset.
+//
+// Run with `cargo bench --features bench --bench throughput`, which writes
+// the log files in a temporary directory, even in a container. `CITA_LOG_PRESET` picks the
+// preset, `high-throughput` by default. Save a baseline with
+// `-- --save-baseline main` and compare a change against it with
+// `-- --baseline main`, criterion reports the regressions.
+
+use cita_logger::encode::{BinaryEncoder, CsvEncoder, Encoder, JsonEncoder};
+use cita_logger::{info, init_preset, Preset};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use log::{Level, Record};
+use std::env;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: u64 = 4;
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(1));
+    let encoders: [(&str, Box<dyn Encoder>); 3] = [
+        ("binary", Box::new(BinaryEncoder::new())),
+        ("json", Box::new(JsonEncoder::new())),
+        ("csv", Box::new(CsvEncoder::new())),
+    ];
+    for (name, encoder) in &encoders {
+        let mut buf = Vec::with_capacity(4096);
+        let mut height = 0u64;
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                buf.clear();
+                height += 1;
+                encoder
+                    .encode(
+                        &mut buf,
+                        &Record::builder()
+                            .level(Level::Info)
+                            .target("cita_executor")
+                            .args(format_args!("executed block {}", black_box(height)))
+                            .build(),
+                    )
+                    .unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn write(c: &mut Criterion) {
+    let dir = env::temp_dir().join(format!("cita-logger-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    env::set_current_dir(&dir).unwrap();
+    // Write the file even in a container
+    env::set_var("CITA_LOG_TO_STDOUT", "0");
+    init_preset("bench", Preset::HighThroughput);
+
+    let mut group = c.benchmark_group("write");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("1 thread", |b| {
+        let mut height = 0u64;
+        b.iter(|| {
+            height += 1;
+            info!("executed block {}", black_box(height));
+        })
+    });
+    group.throughput(Throughput::Elements(THREADS));
+    group.bench_function(format!("{} threads", THREADS), |b| {
+        b.iter_custom(|iterations| {
+            let start = Instant::now();
+            let threads: Vec<_> = (0..THREADS)
+                .map(|thread| {
+                    thread::spawn(move || {
+                        for height in 0..iterations {
+                            info!("thread {} executed block {}", thread, black_box(height));
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            start.elapsed()
+        })
+    });
+    group.finish();
+    log::logger().flush();
+    let _ = fs::remove_dir_all(&dir);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(3));
+    targets = encode, write
+}
+criterion_main!(benches);
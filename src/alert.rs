@@ -0,0 +1,121 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Alerts on the rate of error records.
+
+use parking_lot::{const_rwlock, Mutex, RwLock};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Passed to the callback registered with `set_error_rate_alert`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorRateAlert {
+    /// Error records within `window`.
+    pub errors: usize,
+    pub window: Duration,
+}
+
+type Callback = Box<dyn Fn(&ErrorRateAlert) + Send + Sync>;
+
+struct Alert {
+    threshold: usize,
+    window: Duration,
+    callback: Callback,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    errors: VecDeque<Instant>,
+    fired: Option<Instant>,
+}
+
+static ALERT: RwLock<Option<Arc<Alert>>> = const_rwlock(None);
+
+/// Registers a callback invoked when more than `threshold` error records are
+/// emitted within `window`, e.g. 50 errors in 10 seconds.
+///
+/// The callback fires at most once per `window`. Replaces the previous
+/// callback.
+pub fn set_error_rate_alert<F>(threshold: usize, window: Duration, callback: F)
+where
+    F: Fn(&ErrorRateAlert) + Send + Sync + 'static,
+{
+    *ALERT.write() = Some(Arc::new(Alert {
+        threshold,
+        window,
+        callback: Box::new(callback),
+        state: Mutex::new(State::default()),
+    }));
+}
+
+// Called for every emitted error record.
+pub(crate) fn count_error() {
+    let alert = match &*ALERT.read() {
+        Some(alert) => alert.clone(),
+        None => return,
+    };
+    if let Some(errors) = alert.count(Instant::now()) {
+        // Called without any lock held, the callback may log itself.
+        (alert.callback)(&ErrorRateAlert {
+            errors,
+            window: alert.window,
+        });
+    }
+}
+
+impl Alert {
+    // Returns the number of errors in the window if the alert fires.
+    fn count(&self, now: Instant) -> Option<usize> {
+        let mut state = self.state.lock();
+        state.errors.push_back(now);
+        while let Some(&first) = state.errors.front() {
+            if now.duration_since(first) <= self.window {
+                break;
+            }
+            state.errors.pop_front();
+        }
+
+        if state.errors.len() <= self.threshold {
+            return None;
+        }
+        if let Some(fired) = state.fired {
+            if now.duration_since(fired) < self.window {
+                return None;
+            }
+        }
+        state.fired = Some(now);
+        Some(state.errors.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Alert, State};
+    use parking_lot::Mutex;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn fire_once_per_window() {
+        let alert = Alert {
+            threshold: 2,
+            window: Duration::from_secs(10),
+            callback: Box::new(|_| {}),
+            state: Mutex::new(State::default()),
+        };
+        let start = Instant::now();
+
+        assert_eq!(alert.count(start), None);
+        assert_eq!(alert.count(start), None);
+        assert_eq!(alert.count(start + Duration::from_secs(1)), Some(3));
+        assert_eq!(alert.count(start + Duration::from_secs(2)), None);
+        // The first errors left the window
+        assert_eq!(alert.count(start + Duration::from_secs(12)), None);
+        assert_eq!(alert.count(start + Duration::from_secs(12)), Some(3));
+    }
+}
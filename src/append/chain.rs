@@ -0,0 +1,285 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::file::SyncPolicy;
+use super::Appender;
+use chrono::Local;
+use hmac::{Hmac, Mac};
+use log::Record;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Digest "before" the first line of a log.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// Separates a line from its digest.
+const SEPARATOR: &str = " #";
+// How often `SyncPolicy::Interval` syncs the log.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An append-only log whose lines are hash-linked, so that editing,
+/// removing or reordering lines can be detected with `verify_chain`.
+///
+/// Each line ends with the SHA-256 digest (or the HMAC-SHA256, if a key is
+/// given) of the previous line's digest and the line itself. Newlines in
+/// messages are escaped, so a record is always a single line.
+///
+/// Every line is synced to disk before `append` returns, unless another
+/// policy is set with `sync`.
+pub struct ChainAppender {
+    path: PathBuf,
+    key: Option<Vec<u8>>,
+    sync: SyncPolicy,
+    // The file, the digest of its last line and when it was last synced
+    state: Mutex<(File, String, Instant)>,
+}
+
+impl fmt::Debug for ChainAppender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ChainAppender")
+            .field("path", &self.path)
+            .field("hmac", &self.key.is_some())
+            .field("sync", &self.sync)
+            .finish()
+    }
+}
+
+impl ChainAppender {
+    /// Opens the log at `path`, continuing the chain of its existing lines.
+    ///
+    /// With a `key`, lines are linked with HMAC-SHA256, so that a forged log
+    /// can't be passed off as genuine without knowing the key.
+    pub fn open<P: AsRef<Path>>(path: P, key: Option<&[u8]>) -> io::Result<ChainAppender> {
+        ChainAppender::open_after(path, key, GENESIS)
+    }
+
+    /// Opens the log at `path` like `open`, but links the first line of a
+    /// new log to `previous`, the `last_digest` of the log before it, so that
+    /// the chain runs on across rotated files (see `verify_chains`).
+    pub fn open_after<P: AsRef<Path>>(
+        path: P,
+        key: Option<&[u8]>,
+        previous: &str,
+    ) -> io::Result<ChainAppender> {
+        let path = path.as_ref().to_owned();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let last = last_digest(&path)?.unwrap_or_else(|| previous.to_string());
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        Ok(ChainAppender {
+            path,
+            key: key.map(<[u8]>::to_vec),
+            sync: SyncPolicy::Always,
+            state: Mutex::new((file, last, Instant::now())),
+        })
+    }
+
+    /// Sets when the log is synced to disk. With `SyncPolicy::Interval` a
+    /// line is synced along with the first one written a second after the
+    /// last sync.
+    pub fn sync(mut self, sync: SyncPolicy) -> ChainAppender {
+        self.sync = sync;
+        self
+    }
+
+    /// The digest of the last line written, or of the line before them.
+    pub fn last_digest(&self) -> String {
+        self.state.lock().1.clone()
+    }
+}
+
+// The digest of the last line of the log at `path`, if it has any.
+pub(crate) fn last_digest(path: &Path) -> io::Result<Option<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        if let Some((_, digest)) = line?.rsplit_once(SEPARATOR) {
+            last = Some(digest.to_string());
+        }
+    }
+    Ok(last)
+}
+
+impl Appender for ChainAppender {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let line = format!(
+            "{} {:5} {} - {}",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.6f%:z"),
+            record.level(),
+            record.target(),
+            escape(&record.args().to_string())
+        );
+        let mut state = self.state.lock();
+        let digest = digest(self.key.as_deref(), &state.1, &line);
+        writeln!(state.0, "{}{}{}", line, SEPARATOR, digest)?;
+        let due = match self.sync {
+            SyncPolicy::Always => true,
+            SyncPolicy::Interval => state.2.elapsed() >= SYNC_INTERVAL,
+            SyncPolicy::Never => false,
+        };
+        if due {
+            state.0.sync_data()?;
+            state.2 = Instant::now();
+        }
+        state.1 = digest;
+        Ok(())
+    }
+}
+
+/// Why a hash-linked log failed verification.
+#[derive(Debug)]
+pub enum ChainError {
+    Io(io::Error),
+    /// The line (counted from 1) doesn't match its digest: it or an earlier
+    /// line was modified, removed or reordered.
+    Tampered(usize),
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainError::Io(e) => write!(f, "{}", e),
+            ChainError::Tampered(line) => write!(f, "log tampered with at line {}", line),
+        }
+    }
+}
+
+impl Error for ChainError {}
+
+impl From<io::Error> for ChainError {
+    fn from(e: io::Error) -> ChainError {
+        ChainError::Io(e)
+    }
+}
+
+/// Verifies a log written by `ChainAppender` with the same `key`, returning
+/// its number of lines.
+///
+/// Only the tail of a log can be cut off unnoticed, so keep the line count
+/// or last digest elsewhere when that matters.
+pub fn verify_chain<P: AsRef<Path>>(path: P, key: Option<&[u8]>) -> Result<usize, ChainError> {
+    let mut last = Some(GENESIS.to_string());
+    let mut count = 0;
+    verify_lines(path.as_ref(), key, &mut last, &mut count)?;
+    Ok(count)
+}
+
+/// Verifies logs written one after the other with `open_after`, oldest
+/// first, e.g. rotated files and the current log, returning their number of
+/// lines, counted across the logs.
+///
+/// Each log must continue the chain of the one before it. The logs before
+/// the first may be gone, so the first line of the first log is taken as it
+/// is.
+pub fn verify_chains<P: AsRef<Path>>(paths: &[P], key: Option<&[u8]>) -> Result<usize, ChainError> {
+    let mut last = None;
+    let mut count = 0;
+    for path in paths {
+        verify_lines(path.as_ref(), key, &mut last, &mut count)?;
+    }
+    Ok(count)
+}
+
+// Checks the lines of the log at `path` against `last`, the digest before
+// them, if known.
+fn verify_lines(
+    path: &Path,
+    key: Option<&[u8]>,
+    last: &mut Option<String>,
+    count: &mut usize,
+) -> Result<(), ChainError> {
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        *count += 1;
+        match line.rsplit_once(SEPARATOR) {
+            Some((line, digest))
+                if last
+                    .as_ref()
+                    .is_none_or(|last| digest == self::digest(key, last, line)) =>
+            {
+                *last = Some(digest.to_string());
+            }
+            _ => return Err(ChainError::Tampered(*count)),
+        }
+    }
+    Ok(())
+}
+
+fn digest(key: Option<&[u8]>, previous: &str, line: &str) -> String {
+    let bytes = match key {
+        Some(key) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(previous.as_bytes());
+            mac.update(b"\n");
+            mac.update(line.as_bytes());
+            mac.finalize().into_bytes()
+        }
+        None => {
+            let mut hasher = Sha256::new();
+            hasher.update(previous.as_bytes());
+            hasher.update(b"\n");
+            hasher.update(line.as_bytes());
+            hasher.finalize()
+        }
+    };
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn escape(message: &str) -> String {
+    message
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_chain, ChainAppender, ChainError};
+    use crate::append::Appender;
+    use log::Record;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn detects_tampering() {
+        let path = env::temp_dir().join(format!("cita-logger-chain-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let key = Some(&b"secret"[..]);
+
+        let appender = ChainAppender::open(&path, key).unwrap();
+        appender
+            .append(&Record::builder().args(format_args!("first")).build())
+            .unwrap();
+        drop(appender);
+        // Reopening continues the chain
+        let appender = ChainAppender::open(&path, key).unwrap();
+        appender
+            .append(&Record::builder().args(format_args!("second\nline")).build())
+            .unwrap();
+        assert_eq!(verify_chain(&path, key).unwrap(), 2);
+        assert!(verify_chain(&path, None).is_err());
+
+        let log = fs::read_to_string(&path).unwrap();
+        fs::write(&path, log.replacen("first", "frist", 1)).unwrap();
+        match verify_chain(&path, key) {
+            Err(ChainError::Tampered(1)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+}
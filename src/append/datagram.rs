@@ -0,0 +1,135 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::Appender;
+use crate::encode::Encoder;
+use chrono::Local;
+use log::Record;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Sends each record as one datagram to a Unix socket, e.g. of a local
+/// collector, which gets the framing for free.
+///
+/// Sends don't block: while the collector is down or its queue is full,
+/// records are dropped (see `dropped`).
+///
+/// ```no_run
+/// use cita_logger::{register_appender, DatagramAppender};
+///
+/// register_appender("collector", |_| {
+///     Box::new(DatagramAppender::new("/run/collector.sock").unwrap())
+/// });
+/// ```
+pub struct DatagramAppender {
+    path: PathBuf,
+    encoder: Option<Box<dyn Encoder>>,
+    socket: UnixDatagram,
+    dropped: AtomicU64,
+}
+
+impl fmt::Debug for DatagramAppender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DatagramAppender")
+            .field("path", &self.path)
+            .field("encoder", &self.encoder)
+            .finish()
+    }
+}
+
+impl DatagramAppender {
+    /// Sends to the socket bound at `path`, which needn't exist yet.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<DatagramAppender> {
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+        Ok(DatagramAppender {
+            path: path.as_ref().to_owned(),
+            encoder: None,
+            socket,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Formats the records with `encoder` instead of plain lines.
+    pub fn encoder(mut self, encoder: Box<dyn Encoder>) -> DatagramAppender {
+        self.encoder = Some(encoder);
+        self
+    }
+
+    /// Records dropped for want of a collector or of room in its queue.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Appender for DatagramAppender {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut buf = Vec::new();
+        match &self.encoder {
+            Some(encoder) => encoder.encode(&mut buf, record)?,
+            None => write!(
+                buf,
+                "{} {:5} {} - {}",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                record.level(),
+                record.target(),
+                record.args()
+            )?,
+        }
+        match self.socket.send_to(&buf, &self.path) {
+            Ok(_) => Ok(()),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::NotFound
+                        | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DatagramAppender;
+    use crate::append::Appender;
+    use log::Record;
+    use std::fs;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn send_records() {
+        let dir = std::env::temp_dir().join(format!("cita-logger-dgram-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("collector.sock");
+        let appender = DatagramAppender::new(&path).unwrap();
+        let record = Record::builder().args(format_args!("block 7")).build();
+
+        // Without a collector
+        appender.append(&record).unwrap();
+        assert_eq!(appender.dropped(), 1);
+
+        let collector = UnixDatagram::bind(&path).unwrap();
+        appender.append(&record).unwrap();
+        appender.append(&record).unwrap();
+        let mut buf = [0; 256];
+        let n = collector.recv(&mut buf).unwrap();
+        assert!(buf[..n].ends_with(b" - block 7"));
+        assert_eq!(collector.recv(&mut buf).unwrap(), n);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::Appender;
+use log::{Level, Record};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+    EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+};
+
+/// Reports records to the Windows Event Log, with the service name as the
+/// event source.
+///
+/// Errors and warnings keep their type, the other levels are reported as
+/// information. No message file is registered, so Event Viewer prefixes
+/// the message with a note that the event ID has no description.
+///
+/// ```no_run
+/// use cita_logger::{register_appender, EventLogAppender};
+///
+/// register_appender("eventlog", |context| {
+///     Box::new(EventLogAppender::new(context.service_name()).unwrap())
+/// });
+/// ```
+pub struct EventLogAppender {
+    source: String,
+    handle: HANDLE,
+}
+
+// Event source handles may be used from any thread
+unsafe impl Send for EventLogAppender {}
+unsafe impl Sync for EventLogAppender {}
+
+impl fmt::Debug for EventLogAppender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventLogAppender")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl EventLogAppender {
+    /// Registers `source` as an event source of the local computer.
+    pub fn new(source: &str) -> io::Result<EventLogAppender> {
+        let name = wide(source);
+        // Safe: `name` is a NUL-terminated UTF-16 string
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), name.as_ptr()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(EventLogAppender {
+            source: source.to_string(),
+            handle,
+        })
+    }
+}
+
+impl Appender for EventLogAppender {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let message = wide(&format!("{} - {}", record.target(), record.args()));
+        let strings = [message.as_ptr()];
+        // Safe: the handle is open and `strings` holds one valid string
+        let reported = unsafe {
+            ReportEventW(
+                self.handle,
+                event_type(record.level()),
+                0,
+                0,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                ptr::null(),
+            )
+        };
+        if reported == 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EventLogAppender {
+    fn drop(&mut self) {
+        // Safe: the handle is open and not used afterwards
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
+
+fn event_type(level: Level) -> REPORT_EVENT_TYPE {
+    match level {
+        Level::Error => EVENTLOG_ERROR_TYPE,
+        Level::Warn => EVENTLOG_WARNING_TYPE,
+        _ => EVENTLOG_INFORMATION_TYPE,
+    }
+}
+
+// A NUL-terminated UTF-16 string
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
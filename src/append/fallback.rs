@@ -0,0 +1,171 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use crate::error::{self, InternalErrorKind};
+use log::{Level, Record};
+use log4rs::append::Append;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+// Consecutive errors of the primary appender before failing over.
+const MAX_FAILURES: usize = 3;
+
+static LOST_RECORDS: AtomicU64 = AtomicU64::new(0);
+// Live fallback appenders which failed over, e.g. the file appender of the
+// logger and of an instance.
+static FAILED_OVER: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of records which could not be written by any appender.
+pub fn lost_records() -> u64 {
+    LOST_RECORDS.load(Ordering::Relaxed)
+}
+
+pub(crate) fn failed_over() -> bool {
+    failed_over_appenders() > 0
+}
+
+fn failed_over_appenders() -> usize {
+    FAILED_OVER.load(Ordering::Relaxed)
+}
+
+// An appender which switches from `primary` to `fallback` for good once
+// `primary` keeps failing (read-only filesystem, ENOSPC, ...).
+//
+// Records failed before the switch are counted in `lost_records`.
+#[derive(Debug)]
+pub struct FallbackAppender {
+    primary: Box<dyn Append>,
+    fallback: Box<dyn Append>,
+    failures: AtomicUsize,
+    failed_over: AtomicBool,
+}
+
+impl FallbackAppender {
+    pub fn new(primary: Box<dyn Append>, fallback: Box<dyn Append>) -> FallbackAppender {
+        FallbackAppender {
+            primary,
+            fallback,
+            failures: AtomicUsize::new(0),
+            failed_over: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Append for FallbackAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        if self.failed_over.load(Ordering::Acquire) {
+            return self.fallback.append(record);
+        }
+
+        let e = match self.primary.append(record) {
+            Ok(()) => {
+                self.failures.store(0, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        if self.failures.fetch_add(1, Ordering::Relaxed) + 1 < MAX_FAILURES {
+            LOST_RECORDS.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
+
+        if !self.failed_over.swap(true, Ordering::AcqRel) {
+            FAILED_OVER.fetch_add(1, Ordering::Relaxed);
+            error::report(
+                InternalErrorKind::Append,
+                &format!("falling back after {}", e),
+            );
+            let lost = lost_records();
+            let _ = self.fallback.append(
+                &Record::builder()
+                    .level(Level::Error)
+                    .target(module_path!())
+                    .args(format_args!(
+                        "log appender failed persistently ({}), falling back, {} records lost",
+                        e, lost
+                    ))
+                    .build(),
+            );
+        }
+        self.fallback.append(record)
+    }
+
+    fn flush(&self) {
+        self.primary.flush();
+        self.fallback.flush();
+    }
+}
+
+// The state goes with the appender, its replacement (e.g. after a log
+// rotation) trying the primary again.
+impl Drop for FallbackAppender {
+    fn drop(&mut self) {
+        if *self.failed_over.get_mut() {
+            FAILED_OVER.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{failed_over_appenders, lost_records, FallbackAppender};
+    use log::Record;
+    use log4rs::append::Append;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Failing;
+
+    impl Append for Failing {
+        fn append(&self, _: &Record) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("read-only file system"))
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[derive(Debug, Default)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl Append for Collect {
+        fn append(&self, record: &Record) -> anyhow::Result<()> {
+            self.0.lock().push(record.args().to_string());
+            Ok(())
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn fail_over_after_persistent_errors() {
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let appender =
+            FallbackAppender::new(Box::new(Failing), Box::new(Collect(collected.clone())));
+        let lost = lost_records();
+
+        for _ in 0..4 {
+            let record = Record::builder().args(format_args!("record")).build();
+            let _ = appender.append(&record);
+        }
+
+        assert!(lost_records() >= lost + 2);
+        let collected = collected.lock();
+        assert_eq!(collected.len(), 3);
+        assert!(collected[0].starts_with("log appender failed persistently"));
+        assert_eq!(collected[1], "record");
+        assert_eq!(collected[2], "record");
+
+        // Another appender, e.g. of a reconfiguration, keeps the state
+        let failed_over = failed_over_appenders();
+        assert!(failed_over >= 1);
+        let other = FallbackAppender::new(Box::new(Failing), Box::new(Collect::default()));
+        assert_eq!(failed_over_appenders(), failed_over);
+        drop(other);
+        drop(appender);
+        assert!(failed_over_appenders() < failed_over);
+    }
+}
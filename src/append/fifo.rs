@@ -0,0 +1,182 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::Appender;
+use crate::encode::Encoder;
+use chrono::Local;
+use log::Record;
+use parking_lot::Mutex;
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Writes records to a named pipe, e.g. to feed a log agent of the host
+/// without touching the disk.
+///
+/// The pipe is opened without blocking: while no reader is attached, or
+/// when the reader lags behind and the pipe is full, records are dropped
+/// (see `dropped`) instead of stalling the logger. Records up to
+/// `PIPE_BUF` (4 KiB on Linux) are written atomically.
+///
+/// ```no_run
+/// use cita_logger::{register_appender, FifoAppender};
+///
+/// register_appender("agent", |_| Box::new(FifoAppender::create("/run/cita/chain.log").unwrap()));
+/// ```
+pub struct FifoAppender {
+    path: PathBuf,
+    encoder: Option<Box<dyn Encoder>>,
+    // Closed while no reader is attached
+    pipe: Mutex<Option<File>>,
+    dropped: AtomicU64,
+}
+
+impl fmt::Debug for FifoAppender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FifoAppender")
+            .field("path", &self.path)
+            .field("encoder", &self.encoder)
+            .finish()
+    }
+}
+
+impl FifoAppender {
+    /// Writes to the pipe at `path`, creating it if it doesn't exist.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<FifoAppender> {
+        let path = path.as_ref().to_owned();
+        match path.metadata() {
+            Ok(metadata) if !metadata.file_type().is_fifo() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is not a named pipe", path.display()),
+                ));
+            }
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => mkfifo(&path)?,
+            Err(e) => return Err(e),
+        }
+        Ok(FifoAppender {
+            path,
+            encoder: None,
+            pipe: Mutex::new(None),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Formats the records with `encoder` instead of plain lines.
+    pub fn encoder(mut self, encoder: Box<dyn Encoder>) -> FifoAppender {
+        self.encoder = Some(encoder);
+        self
+    }
+
+    /// Records dropped for want of a reader or of room in the pipe.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn encode(&self, record: &Record) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        match &self.encoder {
+            Some(encoder) => encoder.encode(&mut buf, record)?,
+            None => writeln!(
+                buf,
+                "{} {:5} {} - {}",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                record.level(),
+                record.target(),
+                record.args()
+            )?,
+        }
+        Ok(buf)
+    }
+
+    fn drop_record(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Appender for FifoAppender {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let buf = self.encode(record)?;
+        let mut pipe = self.pipe.lock();
+        if pipe.is_none() {
+            match OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&self.path)
+            {
+                Ok(file) => *pipe = Some(file),
+                // No reader
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return self.drop_record(),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let file = pipe.as_mut().expect("opened above");
+        match file.write(&buf) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.drop_record(),
+            // The reader went away, reopen for the next one
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                *pipe = None;
+                self.drop_record()
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn mkfifo(path: &Path) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // Safe: `path` is a valid C string
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FifoAppender;
+    use crate::append::Appender;
+    use log::Record;
+    use std::fs::{self, OpenOptions};
+    use std::io::Read;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    #[test]
+    fn write_to_reader() {
+        let dir = std::env::temp_dir().join(format!("cita-logger-fifo-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("agent.pipe");
+        let appender = FifoAppender::create(&path).unwrap();
+        let record = Record::builder().args(format_args!("block 7")).build();
+
+        // Without a reader
+        appender.append(&record).unwrap();
+        assert_eq!(appender.dropped(), 1);
+
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .unwrap();
+        appender.append(&record).unwrap();
+        let mut buf = [0; 256];
+        let n = reader.read(&mut buf).unwrap();
+        assert!(buf[..n].ends_with(b" - block 7\n"));
+        assert_eq!(appender.dropped(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
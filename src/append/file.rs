@@ -0,0 +1,701 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::writer::Queue;
+use crate::error::{self, InternalErrorKind};
+use crate::handle;
+use crate::index::IndexWriter;
+use log::{LevelFilter, Record};
+use log4rs::append::Append;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::Encode;
+use parking_lot::Mutex;
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// When the log file is synced to disk with `fsync`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum SyncPolicy {
+    /// After every record.
+    Always,
+    /// On every background flush, or once a second when writes are not
+    /// buffered.
+    Interval,
+    /// Leave it to the operating system.
+    Never,
+}
+
+// The longest a batched record waits for the rest of its batch by default
+const DEFAULT_BATCH_LATENCY: Duration = Duration::from_millis(50);
+
+// Used by `SyncPolicy::Interval` when no flush interval is configured.
+const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+// An appender which writes to a file through a buffer, appending to what
+// the file holds already.
+//
+// Unlike the log4rs `FileAppender`, it can keep records in memory and only
+// flush urgent ones (see `flush_level`) right away. The rest is flushed by a
+// background thread every `flush_interval`.
+//
+// Past `max_size` bytes it requests a log rotation, which replaces it.
+// With an `index`, it notes the offsets of some records in a sidecar (see
+// `index`).
+//
+// When `queued`, the records are encoded by the logging threads and written
+// by a writer thread, which they reach through a lock-free queue instead of
+// contending for the file, optionally in batches (see `writer`). Write
+// errors are then only reported, so the appender does not fail over to the
+// console.
+pub struct FileAppender {
+    path: PathBuf,
+    file: Arc<Mutex<BufWriter<File>>>,
+    encoder: Box<dyn Encode>,
+    buffered: bool,
+    flush_level: LevelFilter,
+    sync: SyncPolicy,
+    max_size: Option<u64>,
+    size: AtomicU64,
+    // The rotation round it requested a rotation for, plus 1
+    rotation_requested: AtomicU64,
+    index: Option<Arc<IndexWriter>>,
+    queue: Option<Queue>,
+}
+
+impl fmt::Debug for FileAppender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileAppender")
+            .field("path", &self.path)
+            .field("encoder", &self.encoder)
+            .field("buffered", &self.buffered)
+            .field("flush_level", &self.flush_level)
+            .field("sync", &self.sync)
+            .field("max_size", &self.max_size)
+            .field("indexed", &self.index.is_some())
+            .field("queued", &self.queue.is_some())
+            .finish()
+    }
+}
+
+impl FileAppender {
+    pub fn builder() -> FileAppenderBuilder {
+        FileAppenderBuilder {
+            encoder: None,
+            flush_interval: None,
+            flush_level: LevelFilter::Warn,
+            sync: SyncPolicy::Never,
+            max_size: None,
+            index: None,
+            mode: None,
+            group: None,
+            queued: false,
+            batch_size: 1,
+            batch_latency: DEFAULT_BATCH_LATENCY,
+        }
+    }
+
+    // Counts a record of `written` bytes. When queued, the writer thread
+    // indexes the records instead.
+    fn check_size(&self, written: usize) {
+        let offset = self.size.fetch_add(written as u64, Ordering::Relaxed);
+        if let Some(index) = self.index.as_ref().filter(|_| self.queue.is_none()) {
+            if let Err(e) = index.record(offset) {
+                error::report(InternalErrorKind::Append, &format!("index: {}", e));
+            }
+        }
+        // Once per round of the rotation thread, so again if it failed
+        if self
+            .max_size
+            .is_none_or(|max_size| offset + written as u64 <= max_size)
+        {
+            return;
+        }
+        let round = handle::rotation_round() + 1;
+        if self.rotation_requested.load(Ordering::Relaxed) != round && handle::request_rotation() {
+            self.rotation_requested.store(round, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Append for FileAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        if let Some(queue) = &self.queue {
+            let urgent = record.level() <= self.flush_level;
+            let encode =
+                |bytes: &mut Vec<u8>| self.encoder.encode(&mut SimpleWriter(bytes), record);
+            self.check_size(queue.push(encode, urgent)?);
+            return Ok(());
+        }
+
+        let mut file = self.file.lock();
+        let mut writer = SimpleWriter(Counting {
+            inner: &mut *file,
+            count: 0,
+        });
+        self.encoder.encode(&mut writer, record)?;
+        self.check_size(writer.0.count);
+        // Records at or above the flush level must reach the file at once,
+        // so the tail of the log is intact if the process crashes.
+        if self.sync == SyncPolicy::Always {
+            file.flush()?;
+            file.get_ref().sync_data()?;
+        } else if !self.buffered || record.level() <= self.flush_level {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) {
+        if let Some(queue) = &self.queue {
+            return queue.flush();
+        }
+        let _ = self.file.lock().flush();
+    }
+}
+
+// Counts the bytes written through it.
+struct Counting<'a, W> {
+    inner: &'a mut W,
+    count: usize,
+}
+
+impl<W: Write> Write for Counting<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct FileAppenderBuilder {
+    encoder: Option<Box<dyn Encode>>,
+    flush_interval: Option<Duration>,
+    flush_level: LevelFilter,
+    sync: SyncPolicy,
+    max_size: Option<u64>,
+    index: Option<u64>,
+    mode: Option<u32>,
+    group: Option<u32>,
+    queued: bool,
+    batch_size: usize,
+    batch_latency: Duration,
+}
+
+impl FileAppenderBuilder {
+    pub fn encoder(mut self, encoder: Box<dyn Encode>) -> FileAppenderBuilder {
+        self.encoder = Some(encoder);
+        self
+    }
+
+    // Keep records in memory and flush them every `interval` instead of
+    // after each one.
+    pub fn flush_interval(mut self, interval: Duration) -> FileAppenderBuilder {
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    // Records at or above this level are flushed even when buffered.
+    pub fn flush_level(mut self, level: LevelFilter) -> FileAppenderBuilder {
+        self.flush_level = level;
+        self
+    }
+
+    pub fn sync(mut self, sync: SyncPolicy) -> FileAppenderBuilder {
+        self.sync = sync;
+        self
+    }
+
+    // Request a rotation once the file grows beyond `bytes`.
+    pub fn max_size(mut self, bytes: Option<u64>) -> FileAppenderBuilder {
+        self.max_size = bytes;
+        self
+    }
+
+    // Note the offset of one record in `every` in the index sidecar.
+    pub fn index(mut self, every: Option<u64>) -> FileAppenderBuilder {
+        self.index = every;
+        self
+    }
+
+    // The mode bits of the file, e.g. 0o640, instead of the umask's. Its
+    // directory gets them too, executable where readable.
+    pub fn mode(mut self, mode: Option<u32>) -> FileAppenderBuilder {
+        self.mode = mode;
+        self
+    }
+
+    // The group owning the file and its directory.
+    pub fn group(mut self, gid: Option<u32>) -> FileAppenderBuilder {
+        self.group = gid;
+        self
+    }
+
+    // Hand the records to a writer thread instead of writing them from the
+    // logging threads.
+    pub fn queued(mut self, enabled: bool) -> FileAppenderBuilder {
+        self.queued = enabled;
+        self
+    }
+
+    // When queued, gather up to `size` records per thread, sent together at
+    // the latest after `latency`.
+    pub fn batch(mut self, size: usize, latency: Duration) -> FileAppenderBuilder {
+        self.batch_size = size;
+        self.batch_latency = latency;
+        self
+    }
+
+    pub fn build<P: AsRef<Path>>(self, path: P) -> io::Result<FileAppender> {
+        let path = path.as_ref().to_owned();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+            set_owner(parent, self.mode.map(dir_mode), self.group)?;
+        }
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        set_owner(&path, self.mode, self.group)?;
+        let size = file.metadata()?.len();
+        let index = self
+            .index
+            .map(|every| IndexWriter::open(&path, every, size).map(Arc::new))
+            .transpose()?;
+        if index.is_some() {
+            set_owner(&crate::index::path(&path), self.mode, self.group)?;
+        }
+        let file = Arc::new(Mutex::new(BufWriter::new(file)));
+
+        let interval = match (self.flush_interval, self.sync) {
+            (Some(interval), _) => Some(interval),
+            (None, SyncPolicy::Interval) => Some(DEFAULT_SYNC_INTERVAL),
+            (None, _) => None,
+        };
+        if let Some(interval) = interval {
+            let file = Arc::downgrade(&file);
+            let sync = self.sync == SyncPolicy::Interval;
+            thread::Builder::new()
+                .name("cita-logger-flush".to_string())
+                .spawn(move || flush_periodically(file, interval, sync))?;
+        }
+
+        let buffered = self.flush_interval.is_some();
+        let queue = match self.queued {
+            true => Some(Queue::spawn(
+                file.clone(),
+                size,
+                index.clone(),
+                self.sync,
+                buffered,
+                self.batch_size,
+                self.batch_latency,
+            )?),
+            false => None,
+        };
+
+        Ok(FileAppender {
+            path,
+            file,
+            encoder: self
+                .encoder
+                .unwrap_or_else(|| Box::new(PatternEncoder::default())),
+            buffered,
+            flush_level: self.flush_level,
+            sync: self.sync,
+            max_size: self.max_size,
+            size: AtomicU64::new(size),
+            rotation_requested: AtomicU64::new(0),
+            index,
+            queue,
+        })
+    }
+}
+
+// Runs until the appender is dropped, e.g. replaced after a log rotation.
+fn flush_periodically(file: Weak<Mutex<BufWriter<File>>>, interval: Duration, sync: bool) {
+    loop {
+        thread::sleep(interval);
+        match file.upgrade() {
+            Some(file) => {
+                let mut file = file.lock();
+                if file.flush().is_ok() && sync {
+                    let _ = file.get_ref().sync_data();
+                }
+            }
+            None => break,
+        }
+    }
+}
+
+// Sets the mode bits and the group of `path`, where given.
+#[cfg(unix)]
+pub fn set_owner(path: &Path, mode: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    if gid.is_some() {
+        std::os::unix::fs::chown(path, None, gid)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_owner(_path: &Path, mode: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    match mode.is_some() || gid.is_some() {
+        true => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "file modes and groups need Unix",
+        )),
+        false => Ok(()),
+    }
+}
+
+// Directories need the execute bit to be listed.
+fn dir_mode(mode: u32) -> u32 {
+    mode | (mode & 0o444) >> 2
+}
+
+// The id of a group, given by name or number.
+#[cfg(unix)]
+pub fn group_id(group: &str) -> io::Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut entry: libc::group = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    // Safe: the buffers outlive the call and their sizes are passed along
+    let errno = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if errno != 0 {
+        return Err(io::Error::from_raw_os_error(errno));
+    }
+    if result.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no group {}", group),
+        ));
+    }
+    Ok(entry.gr_gid)
+}
+
+#[cfg(not(unix))]
+pub fn group_id(group: &str) -> io::Result<u32> {
+    group
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::Unsupported, "group names need Unix"))
+}
+
+// Syncs a directory, so that renames and newly created files inside it
+// survive a power loss.
+pub fn sync_dir<P: AsRef<Path>>(dir: P) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use super::group_id;
+    use super::FileAppender;
+    use log::{Level, LevelFilter, Record};
+    use log4rs::append::Append;
+    use log4rs::encode::pattern::PatternEncoder;
+    use std::convert::TryInto;
+    use std::env;
+    use std::fs;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn buffered_flushes_on_flush_level() {
+        let path = env::temp_dir().join(format!("cita-logger-flush-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let appender = FileAppender::builder()
+            .encoder(Box::new(PatternEncoder::new("{m}{n}")))
+            .flush_interval(Duration::from_secs(3600))
+            .flush_level(LevelFilter::Warn)
+            .build(&path)
+            .unwrap();
+
+        let info = Record::builder()
+            .level(Level::Info)
+            .args(format_args!("info"))
+            .build();
+        appender.append(&info).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        let warn = Record::builder()
+            .level(Level::Warn)
+            .args(format_args!("warn"))
+            .build();
+        appender.append(&warn).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "info\nwarn\n");
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn buffered_flushes_on_interval() {
+        let path = env::temp_dir().join(format!("cita-logger-interval-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let appender = FileAppender::builder()
+            .encoder(Box::new(PatternEncoder::new("{m}{n}")))
+            .flush_interval(Duration::from_millis(20))
+            .flush_level(LevelFilter::Off)
+            .build(&path)
+            .unwrap();
+
+        let info = Record::builder()
+            .level(Level::Info)
+            .args(format_args!("info"))
+            .build();
+        appender.append(&info).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "info\n");
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn mode() {
+        let dir = env::temp_dir().join(format!("cita-logger-mode-{}", std::process::id()));
+        let path = dir.join("chain.log");
+        FileAppender::builder()
+            .mode(Some(0o640))
+            .build(&path)
+            .unwrap();
+        let mode = |path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o640);
+        assert_eq!(mode(&dir), 0o750);
+        assert_eq!(group_id("0").unwrap(), 0);
+        assert!(group_id("no-such-group-cita").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_requested_again() {
+        let (rotate, requests) = crossbeam_channel::bounded(10);
+        crate::handle::install_rotation(rotate);
+        let path = env::temp_dir().join(format!("cita-logger-full-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let appender = FileAppender::builder()
+            .encoder(Box::new(PatternEncoder::new("{m}{n}")))
+            .max_size(Some(10))
+            .build(&path)
+            .unwrap();
+        let append = || {
+            let record = Record::builder()
+                .level(Level::Info)
+                .args(format_args!("full"))
+                .build();
+            appender.append(&record).unwrap()
+        };
+
+        for _ in 0..5 {
+            append();
+        }
+        assert_eq!(requests.try_iter().count(), 1);
+        // The rotation failed, the file is still full
+        crate::handle::rotation_done();
+        append();
+        append();
+        assert_eq!(requests.try_iter().count(), 1);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn indexed() {
+        let path = env::temp_dir().join(format!("cita-logger-indexed-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let appender = FileAppender::builder()
+            .encoder(Box::new(PatternEncoder::new("{m}{n}")))
+            .index(Some(2))
+            .build(&path)
+            .unwrap();
+        for message in ["one", "two", "three"] {
+            appender
+                .append(
+                    &Record::builder()
+                        .level(Level::Info)
+                        .args(format_args!("{}", message))
+                        .build(),
+                )
+                .unwrap();
+        }
+        let index = fs::read(crate::index::path(&path)).unwrap();
+        assert_eq!(index.len(), 32);
+        assert_eq!(index[16..24], 8u64.to_le_bytes());
+
+        let _ = fs::remove_file(crate::index::path(&path));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn queued() {
+        let path = env::temp_dir().join(format!("cita-logger-queued-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let appender = Arc::new(
+            FileAppender::builder()
+                .encoder(Box::new(PatternEncoder::new("{m}{n}")))
+                .queued(true)
+                .build(&path)
+                .unwrap(),
+        );
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let appender = appender.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let record = Record::builder()
+                            .level(Level::Info)
+                            .args(format_args!("queued"))
+                            .build();
+                        appender.append(&record).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        appender.flush();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "queued\n".repeat(400));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn queued_and_indexed() {
+        for batch in [1, 7] {
+            let path = env::temp_dir().join(format!(
+                "cita-logger-queued-indexed-{}-{}.log",
+                batch,
+                std::process::id()
+            ));
+            let _ = fs::remove_file(&path);
+            let appender = Arc::new(
+                FileAppender::builder()
+                    .encoder(Box::new(PatternEncoder::new("{m}{n}")))
+                    .index(Some(1))
+                    .queued(true)
+                    .batch(batch, Duration::from_millis(5))
+                    .build(&path)
+                    .unwrap(),
+            );
+            let start = Arc::new(Barrier::new(4));
+            let threads: Vec<_> = (0..4)
+                .map(|thread| {
+                    let appender = appender.clone();
+                    let start = start.clone();
+                    thread::spawn(move || {
+                        start.wait();
+                        for i in 0..500 {
+                            let message = "x".repeat(thread * 10 + i % 7);
+                            appender
+                                .append(
+                                    &Record::builder()
+                                        .level(Level::Info)
+                                        .args(format_args!("{}", message))
+                                        .build(),
+                                )
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            appender.flush();
+
+            let log = fs::read(&path).unwrap();
+            let index = fs::read(crate::index::path(&path)).unwrap();
+            assert_eq!(index.len(), 2000 * 16);
+            for entry in index.chunks(16) {
+                let offset = u64::from_le_bytes(entry[..8].try_into().unwrap()) as usize;
+                assert!(offset < log.len());
+                assert!(offset == 0 || log[offset - 1] == b'\n', "{}", offset);
+            }
+
+            drop(appender);
+            let _ = fs::remove_file(crate::index::path(&path));
+            let _ = fs::remove_file(&path);
+        }
+    }
+
+    #[test]
+    fn batched() {
+        let path = env::temp_dir().join(format!("cita-logger-batched-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let appender = FileAppender::builder()
+            .encoder(Box::new(PatternEncoder::new("{m}{n}")))
+            .flush_interval(Duration::from_secs(3600))
+            .queued(true)
+            .batch(100, Duration::from_millis(20))
+            .build(&path)
+            .unwrap();
+
+        let info = Record::builder()
+            .level(Level::Info)
+            .args(format_args!("info"))
+            .build();
+        appender.append(&info).unwrap();
+        // Neither the batch nor the file buffer is full
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        appender.flush();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "info\n");
+
+        let warn = Record::builder()
+            .level(Level::Warn)
+            .args(format_args!("warn"))
+            .build();
+        appender.append(&info).unwrap();
+        appender.append(&warn).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "info\ninfo\nwarn\n");
+
+        let _ = fs::remove_file(&path);
+    }
+}
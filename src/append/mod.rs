@@ -0,0 +1,148 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Appenders used by the generated log4rs configs, and the plugin interface
+// for appenders of other crates.
+
+#[cfg(feature = "amqp")]
+pub mod amqp;
+pub mod chain;
+#[cfg(unix)]
+pub mod datagram;
+#[cfg(feature = "smtp")]
+pub mod email;
+#[cfg(windows)]
+pub mod eventlog;
+pub mod fallback;
+#[cfg(unix)]
+pub mod fifo;
+pub mod file;
+#[cfg(target_os = "macos")]
+pub mod oslog;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+#[cfg(any(feature = "amqp", feature = "smtp", feature = "webhook"))]
+mod worker;
+pub(crate) mod writer;
+
+use log::Record;
+use parking_lot::{const_rwlock, RwLock};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A sink for log records, which can be plugged into the logger with
+/// `register_appender`.
+pub trait Appender: fmt::Debug + Send + Sync + 'static {
+    /// Writes a record. Errors are passed to the internal error handler.
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Flushes buffered records.
+    fn flush(&self) {}
+}
+
+/// What a registered appender factory gets to know about the logger.
+#[derive(Debug)]
+pub struct AppenderContext<'a> {
+    service_name: &'a str,
+    log_dir: &'a Path,
+}
+
+impl<'a> AppenderContext<'a> {
+    pub(crate) fn new(service_name: &'a str, log_dir: &'a Path) -> AppenderContext<'a> {
+        AppenderContext {
+            service_name,
+            log_dir,
+        }
+    }
+
+    pub fn service_name(&self) -> &str {
+        self.service_name
+    }
+
+    /// The directory of the log files, even when logging to the console.
+    pub fn log_dir(&self) -> &Path {
+        self.log_dir
+    }
+}
+
+type Factory = dyn Fn(&AppenderContext) -> Box<dyn Appender> + Send + Sync;
+
+static REGISTRY: RwLock<Vec<(String, Arc<Factory>)>> = const_rwlock(Vec::new());
+
+/// Registers an appender which receives the same records as the built-in
+/// ones.
+///
+/// `factory` is called whenever the logger is configured, i.e. at `init` and
+/// after each log rotation, so register appenders before the logger is
+/// installed. Registering a `name` again replaces its factory.
+pub fn register_appender<F>(name: &str, factory: F)
+where
+    F: Fn(&AppenderContext) -> Box<dyn Appender> + Send + Sync + 'static,
+{
+    let mut registry = REGISTRY.write();
+    registry.retain(|(registered, _)| registered != name);
+    registry.push((name.to_string(), Arc::new(factory)));
+}
+
+// Instances of the registered appenders, with their names.
+pub(crate) fn registered(context: &AppenderContext) -> Vec<(String, Plugin)> {
+    REGISTRY
+        .read()
+        .iter()
+        .map(|(name, factory)| (name.clone(), Plugin(factory(context))))
+        .collect()
+}
+
+// Adapts a registered appender to log4rs.
+#[derive(Debug)]
+pub struct Plugin(Box<dyn Appender>);
+
+impl log4rs::append::Append for Plugin {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        self.0.append(record).map_err(|e| anyhow::anyhow!(e))
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{register_appender, registered, Appender, AppenderContext};
+    use log::Record;
+    use std::error::Error;
+    use std::path::Path;
+
+    #[derive(Debug)]
+    struct Named(String);
+
+    impl Appender for Named {
+        fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Send + Sync>> {
+            println!("{}: {}", self.0, record.args());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn register_and_replace() {
+        register_appender("registry_test", |_| Box::new(Named("old".to_string())));
+        register_appender("registry_test", |context| {
+            Box::new(Named(context.service_name().to_string()))
+        });
+
+        let context = AppenderContext::new("chain", Path::new("logs"));
+        let plugins: Vec<_> = registered(&context)
+            .into_iter()
+            .filter(|(name, _)| name == "registry_test")
+            .collect();
+        assert_eq!(plugins.len(), 1);
+        assert_eq!(format!("{:?}", plugins[0].1), "Plugin(Named(\"chain\"))");
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::Appender;
+use log::{Level, Record};
+use oslog::OsLog;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// Sends records to the unified logging system of macOS, to browse them in
+/// Console.app or with `log stream`.
+///
+/// The category of a record is the crate of its target. Errors are logged
+/// as errors, warnings with the default type, info and debug as info and
+/// debug, and trace as debug.
+///
+/// ```no_run
+/// use cita_logger::{register_appender, OsLogAppender};
+///
+/// register_appender("oslog", |context| {
+///     Box::new(OsLogAppender::new(&format!("org.cita.{}", context.service_name())))
+/// });
+/// ```
+pub struct OsLogAppender {
+    subsystem: String,
+    categories: Mutex<HashMap<String, Arc<OsLog>>>,
+}
+
+impl fmt::Debug for OsLogAppender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OsLogAppender")
+            .field("subsystem", &self.subsystem)
+            .finish()
+    }
+}
+
+impl OsLogAppender {
+    /// Logs in `subsystem`, by convention in reverse DNS notation.
+    pub fn new(subsystem: &str) -> OsLogAppender {
+        OsLogAppender {
+            subsystem: subsystem.to_string(),
+            categories: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn log(&self, target: &str) -> Arc<OsLog> {
+        let category = target.split("::").next().unwrap_or(target);
+        self.categories
+            .lock()
+            .entry(category.to_string())
+            .or_insert_with(|| Arc::new(OsLog::new(&self.subsystem, category)))
+            .clone()
+    }
+}
+
+impl Appender for OsLogAppender {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let level = match record.level() {
+            Level::Error => oslog::Level::Error,
+            Level::Warn => oslog::Level::Default,
+            Level::Info => oslog::Level::Info,
+            Level::Debug | Level::Trace => oslog::Level::Debug,
+        };
+        self.log(record.target())
+            .with_level(level, &record.args().to_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// An appender which posts records to a webhook (Slack, DingTalk, ...).
+//
+// Requires the `webhook` feature.
+
+use super::worker::{Inbox, WorkerQueue};
+use crate::error::{self, InternalErrorKind};
+use chrono::Local;
+use crossbeam_channel::RecvTimeoutError;
+use log::{LevelFilter, Record};
+use log4rs::append::Append;
+use std::io;
+use std::time::{Duration, Instant};
+
+// Records queued for the webhook, more are dropped.
+const QUEUE_SIZE: usize = 1024;
+// Records listed in one post, more are only counted.
+const MAX_BATCH: usize = 20;
+
+/// The payload layout expected by the webhook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookFormat {
+    /// `{"text": "..."}`, also understood by Mattermost and Rocket.Chat.
+    Slack,
+    /// `{"msgtype": "text", "text": {"content": "..."}}`
+    DingTalk,
+    /// `{"service": "...", "records": [{"time", "level", "target", "message"}]}`
+    Generic,
+}
+
+/// Settings of the webhook alert appender, see `Builder::webhook`.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    format: WebhookFormat,
+    level: LevelFilter,
+    interval: Duration,
+}
+
+impl Webhook {
+    /// Posts warn and error records to `url` in the generic format, at
+    /// most once every 10 seconds.
+    pub fn new(url: &str) -> Webhook {
+        Webhook {
+            url: url.to_string(),
+            format: WebhookFormat::Generic,
+            level: LevelFilter::Warn,
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    pub fn format(mut self, format: WebhookFormat) -> Webhook {
+        self.format = format;
+        self
+    }
+
+    /// The least severe level which is posted. Defaults to warn.
+    pub fn level(mut self, level: LevelFilter) -> Webhook {
+        self.level = level;
+        self
+    }
+
+    /// Records within `interval` are batched into one post.
+    pub fn interval(mut self, interval: Duration) -> Webhook {
+        self.interval = interval;
+        self
+    }
+
+    pub(crate) fn level_filter(&self) -> LevelFilter {
+        self.level
+    }
+}
+
+struct Entry {
+    time: String,
+    level: String,
+    target: String,
+    message: String,
+}
+
+#[derive(Debug)]
+pub struct WebhookAppender {
+    queue: WorkerQueue<Entry>,
+}
+
+impl WebhookAppender {
+    pub fn new(webhook: Webhook, service_name: &str) -> io::Result<WebhookAppender> {
+        let service_name = service_name.to_string();
+        let queue = WorkerQueue::spawn("webhook", QUEUE_SIZE, move |inbox| {
+            Worker {
+                webhook,
+                service_name,
+                inbox,
+            }
+            .run()
+        })?;
+        Ok(WebhookAppender { queue })
+    }
+}
+
+impl Append for WebhookAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let entry = Entry {
+            time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            level: record.level().to_string(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        self.queue.push(entry);
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+struct Worker {
+    webhook: Webhook,
+    service_name: String,
+    inbox: Inbox<Entry>,
+}
+
+impl Worker {
+    fn run(self) {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(10)))
+            .build()
+            .into();
+        let mut last_post: Option<Instant> = None;
+
+        // Wait for the first record of a batch
+        while let Ok(entry) = self.inbox.recv() {
+            let mut batch = vec![entry];
+            let mut more = 0;
+            // Collect the rest of the batch, and keep to the rate limit
+            let deadline = match last_post {
+                Some(last_post) => last_post + self.webhook.interval,
+                None => Instant::now() + Duration::from_secs(1),
+            };
+            let mut disconnected = false;
+            loop {
+                match self.inbox.recv_deadline(deadline) {
+                    Ok(entry) if batch.len() < MAX_BATCH => batch.push(entry),
+                    Ok(_) => more += 1,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        disconnected = true;
+                        break;
+                    }
+                }
+            }
+            more += self.inbox.dropped();
+
+            let body = payload(self.webhook.format, &self.service_name, &batch, more);
+            if let Err(e) = agent
+                .post(&self.webhook.url)
+                .header("Content-Type", "application/json")
+                .send(body)
+            {
+                error::report(InternalErrorKind::Append, &format!("webhook: {}", e));
+            }
+            last_post = Some(Instant::now());
+            if disconnected {
+                break;
+            }
+        }
+    }
+}
+
+fn payload(format: WebhookFormat, service_name: &str, batch: &[Entry], more: usize) -> String {
+    let text = || {
+        let mut text = String::new();
+        for entry in batch {
+            text += &format!(
+                "[{}] {} {} {} - {}\n",
+                service_name, entry.time, entry.level, entry.target, entry.message
+            );
+        }
+        if more > 0 {
+            text += &format!("... and {} more records\n", more);
+        }
+        text
+    };
+
+    let value = match format {
+        WebhookFormat::Slack => serde_json::json!({ "text": text() }),
+        WebhookFormat::DingTalk => serde_json::json!({
+            "msgtype": "text",
+            "text": { "content": text() },
+        }),
+        WebhookFormat::Generic => serde_json::json!({
+            "service": service_name,
+            "records": batch
+                .iter()
+                .map(|entry| {
+                    serde_json::json!({
+                        "time": entry.time,
+                        "level": entry.level,
+                        "target": entry.target,
+                        "message": entry.message,
+                    })
+                })
+                .collect::<Vec<_>>(),
+            "more": more,
+        }),
+    };
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{payload, Entry, WebhookFormat};
+
+    fn entry(message: &str) -> Entry {
+        Entry {
+            time: "2019-05-16 10:20:30".to_string(),
+            level: "ERROR".to_string(),
+            target: "chain".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn slack_payload() {
+        let body = payload(WebhookFormat::Slack, "chain", &[entry("boom")], 2);
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            value["text"],
+            "[chain] 2019-05-16 10:20:30 ERROR chain - boom\n... and 2 more records\n"
+        );
+    }
+
+    #[test]
+    fn generic_payload() {
+        let body = payload(
+            WebhookFormat::Generic,
+            "chain",
+            &[entry("a"), entry("b")],
+            0,
+        );
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(value["service"], "chain");
+        assert_eq!(value["records"][1]["message"], "b");
+        assert_eq!(value["more"], 0);
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// The queue between an appender and its worker thread, which sends the
+// records over the network so that the logging threads never wait for it.
+//
+// Records are dropped while the queue is full, and counted for the worker
+// to report with the next ones. The worker stops once the appender is
+// dropped, after the records left in the queue.
+
+#[cfg(any(feature = "smtp", feature = "webhook"))]
+use crossbeam_channel::RecvTimeoutError;
+use crossbeam_channel::{bounded, Receiver, RecvError, Sender, TrySendError};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+#[cfg(any(feature = "smtp", feature = "webhook"))]
+use std::time::Instant;
+
+pub(crate) struct WorkerQueue<T> {
+    sender: Sender<T>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl<T> fmt::Debug for WorkerQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WorkerQueue")
+            .field("queued", &self.sender.len())
+            .field("dropped", &self.dropped.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl<T: Send + 'static> WorkerQueue<T> {
+    // Spawns the thread `cita-logger-{name}` running `work` on the records
+    // of a queue of `capacity`.
+    pub(crate) fn spawn<F>(name: &str, capacity: usize, work: F) -> io::Result<WorkerQueue<T>>
+    where
+        F: FnOnce(Inbox<T>) + Send + 'static,
+    {
+        let (sender, receiver) = bounded(capacity);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let inbox = Inbox {
+            receiver,
+            dropped: dropped.clone(),
+        };
+        thread::Builder::new()
+            .name(format!("cita-logger-{}", name))
+            .spawn(move || work(inbox))?;
+        Ok(WorkerQueue { sender, dropped })
+    }
+
+    pub(crate) fn push(&self, record: T) {
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(record) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// The end of the queue of the worker thread.
+pub(crate) struct Inbox<T> {
+    receiver: Receiver<T>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl<T> Inbox<T> {
+    // Fails once the appender is gone and the queue empty.
+    pub(crate) fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.recv()
+    }
+
+    // Gathers the records of a batch.
+    #[cfg(any(feature = "smtp", feature = "webhook"))]
+    pub(crate) fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.receiver.recv_deadline(deadline)
+    }
+
+    // The records dropped since the last call.
+    pub(crate) fn dropped(&self) -> usize {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WorkerQueue;
+    use crossbeam_channel::bounded;
+
+    #[test]
+    fn drops_counted() {
+        let (started, start) = bounded(0);
+        let (done, finished) = bounded(1);
+        let queue = WorkerQueue::spawn("worker-test", 2, move |inbox| {
+            start.recv().unwrap();
+            let mut records = Vec::new();
+            while let Ok(record) = inbox.recv() {
+                records.push(record);
+            }
+            done.send((records, inbox.dropped())).unwrap();
+        })
+        .unwrap();
+
+        // The worker waits, so the queue is full after two records
+        for record in 0..5 {
+            queue.push(record);
+        }
+        started.send(()).unwrap();
+        drop(queue);
+        assert_eq!(finished.recv().unwrap(), (vec![0, 1], 3));
+    }
+}
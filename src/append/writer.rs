@@ -0,0 +1,407 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// The writer thread of a queued `FileAppender`.
+//
+// The logging threads encode their records and send them to the writer
+// thread through a lock-free queue. With batching, each thread first gathers
+// `size` records, sent together; the writer thread picks up the batches left
+// for `latency`, so that a quiet thread does not hold its records back.
+//
+// The writer thread notes the records in the index, at the offsets they land
+// at, which only it knows.
+
+use super::file::SyncPolicy;
+use crate::error::{self, InternalErrorKind};
+use crate::index::IndexWriter;
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use parking_lot::{const_mutex, Mutex};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use thread_local::ThreadLocal;
+
+// Messages waiting for the writer thread, beyond which producers block
+const QUEUE_CAPACITY: usize = 8192;
+
+// Buffers written by the writer thread and kept for the next records, and
+// the bytes above which one is freed instead
+const SPARE_BUFFERS: usize = 64;
+const MAX_SPARE_CAPACITY: usize = 1024 * 1024;
+
+// The queues of the live appenders, by id, for `queue_depth`
+static QUEUES: Mutex<Vec<(u64, Receiver<Message>)>> = const_mutex(Vec::new());
+static NEXT_QUEUE: AtomicU64 = AtomicU64::new(1);
+
+// Messages waiting for the writer threads: records, or batches of them.
+pub(crate) fn queue_depth() -> usize {
+    QUEUES.lock().iter().map(|(_, queue)| queue.len()).sum()
+}
+
+enum Message {
+    // Encoded records, where each starts when batched and indexed, and
+    // whether they must be flushed at once
+    Records(Vec<u8>, Vec<usize>, bool),
+    Flush(Sender<()>),
+}
+
+// The records of a thread not sent yet
+#[derive(Default)]
+struct Batch {
+    bytes: Vec<u8>,
+    starts: Vec<usize>,
+    records: usize,
+    since: Option<Instant>,
+}
+
+impl Batch {
+    fn take(&mut self, spare: Vec<u8>) -> (Vec<u8>, Vec<usize>) {
+        self.records = 0;
+        self.since = None;
+        (
+            mem::replace(&mut self.bytes, spare),
+            mem::take(&mut self.starts),
+        )
+    }
+}
+
+struct Batches {
+    threads: ThreadLocal<Mutex<Batch>>,
+    size: usize,
+    latency: Duration,
+    spare: Receiver<Vec<u8>>,
+    indexed: bool,
+}
+
+impl Batches {
+    fn spare(&self) -> Vec<u8> {
+        self.spare.try_recv().unwrap_or_default()
+    }
+}
+
+pub(crate) struct Queue {
+    id: u64,
+    sender: Option<Sender<Message>>,
+    batches: Arc<Batches>,
+    buffered: bool,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Queue {
+    // Batches of `size` records flush only at or above the flush level
+    // when `buffered`. The records go to `index`, if any, from `file` holding
+    // `len` bytes.
+    pub(crate) fn spawn(
+        file: Arc<Mutex<BufWriter<File>>>,
+        len: u64,
+        index: Option<Arc<IndexWriter>>,
+        sync: SyncPolicy,
+        buffered: bool,
+        size: usize,
+        latency: Duration,
+    ) -> io::Result<Queue> {
+        let (sender, receiver) = bounded(QUEUE_CAPACITY);
+        let id = NEXT_QUEUE.fetch_add(1, Ordering::Relaxed);
+        QUEUES.lock().push((id, receiver.clone()));
+        let (recycle, spare) = bounded(SPARE_BUFFERS);
+        let batches = Arc::new(Batches {
+            threads: ThreadLocal::new(),
+            size: size.max(1),
+            latency,
+            spare,
+            indexed: index.is_some(),
+        });
+        let writer = Writer {
+            file,
+            queue: receiver,
+            batches: batches.clone(),
+            recycle,
+            sync,
+            buffered,
+            index,
+            offset: Cell::new(len),
+        };
+        let writer = thread::Builder::new()
+            .name("cita-logger-writer".to_string())
+            .spawn(move || writer.run());
+        let writer = match writer {
+            Ok(writer) => writer,
+            Err(e) => {
+                QUEUES.lock().retain(|(queue, _)| *queue != id);
+                return Err(e);
+            }
+        };
+        Ok(Queue {
+            id,
+            sender: Some(sender),
+            batches,
+            buffered,
+            writer: Some(writer),
+        })
+    }
+
+    // Encodes a record with `encode`, returning its length. An `urgent`
+    // record is sent at once with the rest of its batch.
+    pub(crate) fn push<F>(&self, encode: F, urgent: bool) -> anyhow::Result<usize>
+    where
+        F: FnOnce(&mut Vec<u8>) -> anyhow::Result<()>,
+    {
+        let flush = urgent || !self.buffered;
+        if self.batches.size == 1 {
+            let mut bytes = self.batches.spare();
+            encode(&mut bytes)?;
+            let len = bytes.len();
+            self.send(Message::Records(bytes, Vec::new(), flush))?;
+            return Ok(len);
+        }
+
+        let mut batch = self.batches.threads.get_or_default().lock();
+        let start = batch.bytes.len();
+        if let Err(e) = encode(&mut batch.bytes) {
+            batch.bytes.truncate(start);
+            return Err(e);
+        }
+        let len = batch.bytes.len() - start;
+        if self.batches.indexed {
+            batch.starts.push(start);
+        }
+        batch.records += 1;
+        batch.since.get_or_insert_with(Instant::now);
+        // Sent under the lock, so the writer thread never writes a later
+        // batch of this thread first
+        if urgent || batch.records >= self.batches.size {
+            let (bytes, starts) = batch.take(self.batches.spare());
+            self.send(Message::Records(bytes, starts, flush))?;
+        }
+        Ok(len)
+    }
+
+    // Waits for the records pushed so far to be written.
+    pub(crate) fn flush(&self) {
+        let (ack, done) = bounded(1);
+        if self.send(Message::Flush(ack)).is_ok() {
+            let _ = done.recv();
+        }
+    }
+
+    fn send(&self, message: Message) -> anyhow::Result<()> {
+        match &self.sender {
+            Some(sender) => Ok(sender.send(message)?),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Queue {
+    // Let the writer thread finish, e.g. before the rotated file is handed
+    // to the rotation hooks.
+    fn drop(&mut self) {
+        QUEUES.lock().retain(|(queue, _)| *queue != self.id);
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+struct Writer {
+    file: Arc<Mutex<BufWriter<File>>>,
+    queue: Receiver<Message>,
+    batches: Arc<Batches>,
+    // Hands the written buffers back to the logging threads
+    recycle: Sender<Vec<u8>>,
+    sync: SyncPolicy,
+    buffered: bool,
+    index: Option<Arc<IndexWriter>>,
+    // The length of the file, where the next record lands
+    offset: Cell<u64>,
+}
+
+// What a round of writes must do at its end
+#[derive(Default)]
+struct Round {
+    flush: bool,
+    // Take every batch, not only the late ones
+    all: bool,
+    acks: Vec<Sender<()>>,
+}
+
+impl Writer {
+    fn run(self) {
+        loop {
+            let first = if self.batches.size > 1 {
+                match self.queue.recv_timeout(self.batches.latency) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                match self.queue.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => break,
+                }
+            };
+
+            let mut file = self.file.lock();
+            let mut round = Round::default();
+            let queued = self.queue.try_iter().take(QUEUE_CAPACITY);
+            for message in first.into_iter().chain(queued) {
+                self.handle(&mut file, message, &mut round);
+            }
+            self.sweep(&mut file, &mut round);
+            self.finish(&mut file, round.flush);
+            drop(file);
+            for ack in round.acks {
+                let _ = ack.send(());
+            }
+        }
+
+        // The appender is gone, write what its threads left
+        let mut file = self.file.lock();
+        let mut round = Round {
+            all: true,
+            ..Round::default()
+        };
+        self.sweep(&mut file, &mut round);
+        self.finish(&mut file, true);
+    }
+
+    fn handle(&self, file: &mut BufWriter<File>, message: Message, round: &mut Round) {
+        match message {
+            Message::Records(bytes, starts, flush) => {
+                self.write(file, &bytes, &starts);
+                round.flush |= flush;
+                self.recycle(bytes);
+            }
+            Message::Flush(ack) => {
+                round.flush = true;
+                round.all = true;
+                round.acks.push(ack);
+            }
+        }
+    }
+
+    // Writes the batches which waited for `latency`. A thread busy logging
+    // sends its batch soon anyway.
+    fn sweep(&self, file: &mut BufWriter<File>, round: &mut Round) {
+        if self.batches.size == 1 {
+            return;
+        }
+        for batch in self.batches.threads.iter() {
+            let mut batch = match batch.try_lock() {
+                Some(batch) => batch,
+                None => continue,
+            };
+            let due = batch
+                .since
+                .is_some_and(|since| round.all || since.elapsed() >= self.batches.latency);
+            if !due {
+                continue;
+            }
+            // What the thread sent before goes first
+            for message in self.queue.try_iter().take(QUEUE_CAPACITY) {
+                self.handle(file, message, round);
+            }
+            let (bytes, starts) = batch.take(self.batches.spare());
+            self.write(file, &bytes, &starts);
+            self.recycle(bytes);
+            round.flush |= !self.buffered;
+        }
+    }
+
+    // Writes records, noting the offsets of those at `starts` in the index,
+    // or of the only one when unbatched.
+    fn write(&self, file: &mut BufWriter<File>, bytes: &[u8], starts: &[usize]) {
+        let offset = self.offset.get();
+        if let Err(e) = file.write_all(bytes) {
+            error::report(InternalErrorKind::Append, &e);
+            // Part of the records may have been written
+            let len = file.get_ref().metadata().map_or(offset, |m| m.len());
+            self.offset.set(len + file.buffer().len() as u64);
+            return;
+        }
+        self.offset.set(offset + bytes.len() as u64);
+        let index = match &self.index {
+            Some(index) => index,
+            None => return,
+        };
+        let result = match self.batches.size {
+            1 => index.record(offset),
+            _ => starts
+                .iter()
+                .try_for_each(|start| index.record(offset + *start as u64)),
+        };
+        if let Err(e) = result {
+            error::report(InternalErrorKind::Append, &format!("index: {}", e));
+        }
+    }
+
+    fn recycle(&self, mut bytes: Vec<u8>) {
+        if bytes.capacity() <= MAX_SPARE_CAPACITY {
+            bytes.clear();
+            let _ = self.recycle.try_send(bytes);
+        }
+    }
+
+    fn finish(&self, file: &mut BufWriter<File>, flush: bool) {
+        if !flush && self.sync != SyncPolicy::Always {
+            return;
+        }
+        let mut result = file.flush();
+        if self.sync == SyncPolicy::Always {
+            result = result.and_then(|()| file.get_ref().sync_data());
+        }
+        if let Err(e) = result {
+            error::report(InternalErrorKind::Append, &e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{queue_depth, Queue};
+    use crate::append::file::SyncPolicy;
+    use parking_lot::Mutex;
+    use std::fs::{self, File};
+    use std::io::{BufWriter, Write};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn depth_of_a_stalled_writer() {
+        let path = std::env::temp_dir().join(format!("cita-logger-writer-{}", std::process::id()));
+        let file = Arc::new(Mutex::new(BufWriter::new(File::create(&path).unwrap())));
+        let queue = Queue::spawn(
+            file.clone(),
+            0,
+            None,
+            SyncPolicy::Never,
+            false,
+            1,
+            Duration::ZERO,
+        )
+        .unwrap();
+
+        // The writer thread waits for the file while the records queue up
+        let stalled = file.lock();
+        for _ in 0..10 {
+            let encode = |buf: &mut Vec<u8>| Ok(writeln!(buf, "block")?);
+            queue.push(encode, false).unwrap();
+        }
+        assert!(queue_depth() >= 9, "{}", queue_depth());
+        drop(stalled);
+        queue.flush();
+        drop(queue);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "block\n".repeat(10));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Archival of rotated log files to an S3-compatible bucket, signed with
+// AWS Signature Version 4.
+
+use crate::index;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Uploads rotated log files to an S3-compatible bucket (AWS S3, MinIO,
+/// ...) and deletes them locally once uploaded, see `Builder::s3_archive`.
+///
+/// Objects are named `{prefix}{file name}` and addressed path-style, i.e.
+/// `{endpoint}/{bucket}/{key}`.
+///
+/// Requires the `s3` feature.
+#[derive(Clone)]
+pub struct S3Archive {
+    endpoint: String,
+    region: String,
+    bucket: String,
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+    keep_local: bool,
+}
+
+impl fmt::Debug for S3Archive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("S3Archive")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .field("access_key", &self.access_key)
+            .field("keep_local", &self.keep_local)
+            .finish()
+    }
+}
+
+impl S3Archive {
+    /// Uploads to `bucket` at `endpoint`, e.g. `https://s3.amazonaws.com`,
+    /// with the credentials of `AWS_ACCESS_KEY_ID` and
+    /// `AWS_SECRET_ACCESS_KEY`, if set.
+    pub fn new(endpoint: &str, region: &str, bucket: &str) -> S3Archive {
+        S3Archive {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            region: region.to_string(),
+            bucket: bucket.to_string(),
+            prefix: String::new(),
+            access_key: env::var("AWS_ACCESS_KEY_ID").unwrap_or_default(),
+            secret_key: env::var("AWS_SECRET_ACCESS_KEY").unwrap_or_default(),
+            keep_local: false,
+        }
+    }
+
+    /// Prepends `prefix` to the object keys, e.g. `chain/node0/`.
+    pub fn prefix(mut self, prefix: &str) -> S3Archive {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn credentials(mut self, access_key: &str, secret_key: &str) -> S3Archive {
+        self.access_key = access_key.to_string();
+        self.secret_key = secret_key.to_string();
+        self
+    }
+
+    /// Keeps the files locally after uploading them.
+    pub fn keep_local(mut self, enabled: bool) -> S3Archive {
+        self.keep_local = enabled;
+        self
+    }
+
+    // Uploads `path`, then deletes it unless `keep_local` is set.
+    pub(crate) fn archive(&self, path: &Path) -> io::Result<()> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"))?;
+        let body = fs::read(path)?;
+        self.put(&format!("{}{}", self.prefix, name), &body)?;
+        if !self.keep_local {
+            fs::remove_file(path)?;
+            index::remove(path);
+        }
+        Ok(())
+    }
+
+    fn put(&self, key: &str, body: &[u8]) -> io::Result<()> {
+        let host = self
+            .endpoint
+            .split_once("://")
+            .map_or(self.endpoint.as_str(), |(_, rest)| rest);
+        let host = host.split('/').next().unwrap_or(host);
+        let uri = format!("/{}/{}", encode(&self.bucket), encode(key));
+        let now = Utc::now();
+        let time = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload = hex(&Sha256::digest(body));
+
+        let headers = format!(
+            "host:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n",
+            host, payload, time
+        );
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let request = format!(
+            "PUT\n{}\n\n{}\n{}\n{}",
+            uri, headers, signed_headers, payload
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            time,
+            scope,
+            hex(&Sha256::digest(request.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, &date, &self.region, "s3");
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        );
+
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(300)))
+            .build()
+            .into();
+        agent
+            .put(&format!("{}{}", self.endpoint, uri))
+            .header("Authorization", &authorization)
+            .header("x-amz-content-sha256", &payload)
+            .header("x-amz-date", &time)
+            .send(body)
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// URI-encodes a path, keeping its slashes.
+fn encode(path: &str) -> String {
+    let mut out = String::new();
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(b as char)
+            }
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, hex, signing_key};
+
+    #[test]
+    fn signing() {
+        // The example of the AWS documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+        assert_eq!(
+            encode("chain/node 0/chain_2019-05-16.log"),
+            "chain/node%200/chain_2019-05-16.log"
+        );
+    }
+}
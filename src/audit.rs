@@ -0,0 +1,259 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Audit events, written by `audit!` to their own hash-linked log.
+//
+// Audit events don't have a level and bypass the level filters, the
+// appenders and the rotation of the diagnostic log.
+
+use crate::append::chain::{self, verify_chains, ChainAppender, ChainError};
+use crate::append::file::{self, SyncPolicy};
+use crate::append::Appender;
+use crate::error::{self, InternalErrorKind};
+use crate::logger;
+use log::{Level, Record};
+use parking_lot::{const_rwlock, Mutex, RwLock};
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Writes an audit event: who did what to which resource, and the outcome.
+///
+/// Events go to the audit log set up with `Builder::audit`, or to the
+/// appenders of the regular logger, whatever its level, if there is none.
+///
+/// ```no_run
+/// cita_logger::audit!("admin", "add_node", "node-3", "ok");
+/// ```
+#[macro_export]
+macro_rules! audit {
+    ($actor:expr, $action:expr, $resource:expr, $outcome:expr $(,)?) => {
+        $crate::__audit(&$actor, &$action, &$resource, &$outcome)
+    };
+}
+
+/// Settings of the audit log, see `Builder::audit`.
+#[derive(Debug, Clone)]
+pub struct Audit {
+    max_size: u64,
+    retention: usize,
+    key: Option<Vec<u8>>,
+    sync: SyncPolicy,
+}
+
+impl Default for Audit {
+    fn default() -> Audit {
+        Audit::new()
+    }
+}
+
+impl Audit {
+    /// Rotates the audit log at 16 MiB, keeps 10 rotated files and syncs
+    /// every event to disk.
+    pub fn new() -> Audit {
+        Audit {
+            max_size: 16 << 20,
+            retention: 10,
+            key: None,
+            sync: SyncPolicy::Always,
+        }
+    }
+
+    /// Rotates the audit log once it reaches `bytes`.
+    pub fn max_size(mut self, bytes: u64) -> Audit {
+        self.max_size = bytes;
+        self
+    }
+
+    /// The number of rotated files kept, named `{service}.audit.log.1`
+    /// (newest) to `.{retention}`.
+    pub fn retention(mut self, files: usize) -> Audit {
+        self.retention = files;
+        self
+    }
+
+    /// Links the lines with HMAC-SHA256 under `key` instead of plain
+    /// SHA-256, see `ChainAppender`.
+    pub fn hmac_key(mut self, key: &[u8]) -> Audit {
+        self.key = Some(key.to_vec());
+        self
+    }
+
+    /// Sets when the audit log is synced to disk, independently of the
+    /// other log files. With any policy but `SyncPolicy::Never` its
+    /// directory is synced after each rotation too.
+    pub fn sync(mut self, sync: SyncPolicy) -> Audit {
+        self.sync = sync;
+        self
+    }
+
+    /// Verifies the audit log at `path` and its rotated files as one chain
+    /// (see `verify_chains`), returning their number of events.
+    pub fn verify<P: AsRef<Path>>(&self, path: P) -> Result<usize, ChainError> {
+        let path = path.as_ref();
+        let mut paths: Vec<PathBuf> = (1..=self.retention)
+            .rev()
+            .map(|n| rotated(path, n))
+            .filter(|path| path.exists())
+            .collect();
+        paths.push(path.to_owned());
+        verify_chains(&paths, self.key.as_deref())
+    }
+
+    // Opens the log at `path`, continuing the chain of `previous`, the
+    // digest of the last line of the log before it.
+    fn open(&self, path: &Path, previous: Option<&str>) -> io::Result<ChainAppender> {
+        let file = match previous {
+            Some(previous) => ChainAppender::open_after(path, self.key.as_deref(), previous)?,
+            None => ChainAppender::open(path, self.key.as_deref())?,
+        };
+        Ok(file.sync(self.sync))
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), n))
+}
+
+struct AuditLog {
+    audit: Audit,
+    path: PathBuf,
+    file: Mutex<ChainAppender>,
+}
+
+static AUDIT_LOG: RwLock<Option<AuditLog>> = const_rwlock(None);
+
+// Opens the audit log at `path`, used by `audit!` from now on.
+pub(crate) fn install(audit: Audit, path: PathBuf) -> io::Result<()> {
+    // The chain runs on from the last rotated file
+    let previous = chain::last_digest(&rotated(&path, 1))?;
+    let file = audit.open(&path, previous.as_deref())?;
+    *AUDIT_LOG.write() = Some(AuditLog {
+        audit,
+        path,
+        file: Mutex::new(file),
+    });
+    Ok(())
+}
+
+#[doc(hidden)]
+pub fn __audit(
+    actor: &dyn Display,
+    action: &dyn Display,
+    resource: &dyn Display,
+    outcome: &dyn Display,
+) {
+    let message = format!(
+        "actor={} action={} resource={} outcome={}",
+        actor, action, resource, outcome
+    );
+    let audit_log = AUDIT_LOG.read();
+    let audit_log = match &*audit_log {
+        Some(audit_log) => audit_log,
+        None => {
+            let log = |record: &Record| {
+                if !logger::log_unfiltered(record) {
+                    log::logger().log(record);
+                }
+            };
+            log(&Record::builder()
+                .level(Level::Info)
+                .target("audit")
+                .args(format_args!("{}", message))
+                .build());
+            return;
+        }
+    };
+
+    let mut file = audit_log.file.lock();
+    let result = file.append(
+        &Record::builder()
+            .level(Level::Info)
+            .target("audit")
+            .args(format_args!("{}", message))
+            .build(),
+    );
+    if let Err(e) = result {
+        error::report(InternalErrorKind::Append, &format!("audit: {}", e));
+        return;
+    }
+    let full = fs::metadata(&audit_log.path).is_ok_and(|m| m.len() >= audit_log.audit.max_size);
+    if full {
+        match rotate(&audit_log.path, &audit_log.audit, &file.last_digest()) {
+            Ok(rotated) => *file = rotated,
+            Err(e) => error::report(InternalErrorKind::Rotate, &format!("audit: {}", e)),
+        }
+    }
+}
+
+// Shifts `path` to `path.1`, `path.1` to `path.2` and so on, dropping
+// the files beyond the retention, and opens a new `path` continuing the
+// chain from `last`, the digest of the last line of the old one.
+fn rotate(path: &Path, audit: &Audit, last: &str) -> io::Result<ChainAppender> {
+    let rotated = |n: usize| rotated(path, n);
+    if audit.retention == 0 {
+        fs::remove_file(path)?;
+    } else {
+        let _ = fs::remove_file(rotated(audit.retention));
+        for n in (1..audit.retention).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+        fs::rename(path, rotated(1))?;
+    }
+    let file = audit.open(path, Some(last))?;
+    if audit.sync != SyncPolicy::Never {
+        if let Some(dir) = path.parent() {
+            file::sync_dir(dir)?;
+        }
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rotate, Audit};
+    use crate::append::chain::{verify_chain, ChainAppender, ChainError};
+    use crate::append::Appender;
+    use log::Record;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn rotation_keeps_retention() {
+        let dir = env::temp_dir().join(format!("cita-logger-audit-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("chain.audit.log");
+        let audit = Audit::new().retention(2);
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+
+        let mut file = ChainAppender::open(&path, None).unwrap();
+        for _ in 0..3 {
+            file.append(&Record::builder().args(format_args!("event")).build())
+                .unwrap();
+            file = rotate(&path, &audit, &file.last_digest()).unwrap();
+        }
+        file.append(&Record::builder().args(format_args!("event")).build())
+            .unwrap();
+        drop(file);
+        assert!(rotated(1).exists());
+        assert!(rotated(2).exists());
+        assert!(!rotated(3).exists());
+
+        // One chain across the files, the first of which is gone
+        assert_eq!(audit.verify(&path).unwrap(), 3);
+        assert!(verify_chain(rotated(1), None).is_err());
+        fs::write(rotated(1), fs::read_to_string(rotated(2)).unwrap()).unwrap();
+        match audit.verify(&path) {
+            Err(ChainError::Tampered(2)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Markers delimiting the runs of a process inside an appended log file.
+
+use crate::describe;
+use crate::filter;
+use log::{info, LevelFilter};
+use std::process;
+
+const RULE: &str = "==========";
+
+/// Logs a banner marking the start of a run, with the version and the
+/// commit of the service and a summary of its own configuration (may be
+/// empty), whatever the levels.
+///
+/// ```no_run
+/// cita_logger::init_config(&cita_logger::LogFavour::File("chain"));
+/// cita_logger::log_startup_banner(env!("CARGO_PKG_VERSION"), "a1b2c3d", "node 0 of 4");
+/// ```
+pub fn log_startup_banner(version: &str, git_sha: &str, config_summary: &str) {
+    filter::with_thread_level(LevelFilter::Info, || {
+        info!(
+            "{}",
+            marker(&format!(
+                "{} {} ({}) starting, pid {}",
+                service_name(),
+                version,
+                git_sha,
+                process::id()
+            ))
+        );
+        if !config_summary.is_empty() {
+            info!("config: {}", config_summary);
+        }
+    });
+}
+
+/// Logs a marker of the end of the run and flushes the buffered records,
+/// to be called last before the process exits.
+pub fn shutdown() {
+    filter::with_thread_level(LevelFilter::Info, || {
+        info!(
+            "{}",
+            marker(&format!(
+                "{} shutting down, pid {}",
+                service_name(),
+                process::id()
+            ))
+        );
+    });
+    log::logger().flush();
+}
+
+fn service_name() -> String {
+    describe::describe_config().map_or_else(String::new, |summary| summary.service_name)
+}
+
+fn marker(text: &str) -> String {
+    format!("{} {} {}", RULE, text.trim(), RULE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::marker;
+
+    #[test]
+    fn delimited() {
+        assert_eq!(
+            marker("chain 1.0 (a1b2c3d) starting, pid 7"),
+            "========== chain 1.0 (a1b2c3d) starting, pid 7 =========="
+        );
+        assert_eq!(
+            marker(" shutting down, pid 7"),
+            "========== shutting down, pid 7 =========="
+        );
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Prints binary log files (see `cita_logger::encode::binary`) as text.
+//
+// Usage: cita-logcat [FILE]...
+// Reads stdin if no file is given.
+
+use cita_logger::encode::binary::BinaryReader;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::process;
+
+fn cat<R: Read>(reader: R, out: &mut dyn Write) -> io::Result<()> {
+    for record in BinaryReader::new(BufReader::new(reader)) {
+        writeln!(out, "{}", record?)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let files: Vec<String> = env::args().skip(1).collect();
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    let result = if files.is_empty() {
+        cat(io::stdin(), &mut out)
+    } else {
+        files
+            .iter()
+            .try_for_each(|file| File::open(file).and_then(|f| cat(f, &mut out)))
+    };
+    if let Err(e) = result.and_then(|()| out.flush()) {
+        eprintln!("cita-logcat: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,376 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Controls the logging of running services through their control sockets
+// (see `Builder::control_socket`), tails and searches their log files, and
+// checks their logging config before a deployment.
+//
+// Usage: cita-logctl [--dir DIR] <rotate|set|reset|show|tail|grep|check> SERVICE ...
+//        cita-logctl check --config FILE
+// Requires the `cli` feature.
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use cita_logger::encode::LevelNames;
+use cita_logger::reader::{read_logs, LogQuery};
+use cita_logger::LoggerConfig;
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
+use regex::Regex;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[derive(Parser)]
+#[command(
+    name = "cita-logctl",
+    version,
+    about = "Controls the logging of CITA services"
+)]
+struct Cli {
+    /// The directory of the log files and the control sockets.
+    #[arg(long, default_value = "logs", value_name = "DIR")]
+    dir: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Rotates the log files of a service.
+    Rotate {
+        service: String,
+        /// Sends SIGUSR1 to this process instead of using the control
+        /// socket.
+        #[arg(long)]
+        pid: Option<i32>,
+    },
+    /// Sets the global level, or the level of a module.
+    Set {
+        service: String,
+        #[arg(num_args = 1..=2, value_names = ["MODULE", "LEVEL"])]
+        args: Vec<String>,
+    },
+    /// Removes the level of a module set with `set`.
+    Reset { service: String, module: String },
+    /// Describes the logging configuration of a service.
+    Show { service: String },
+    /// Prints the last lines of the log file of a service.
+    Tail {
+        service: String,
+        /// The number of lines.
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+        /// Keeps printing the lines appended, across rotations.
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Prints the records of a service from its log files, rotated ones
+    /// included.
+    Grep {
+        service: String,
+        /// Only the records whose message matches this regular expression.
+        pattern: Option<Regex>,
+        /// Only the records at or above this level.
+        #[arg(short, long, default_value_t = LevelFilter::Trace)]
+        level: LevelFilter,
+        /// Only the records whose target starts with this prefix.
+        #[arg(short, long)]
+        target: Option<String>,
+        /// Only the records logged at or after this time, e.g.
+        /// `2019-05-16 10:20:30` or `2019-05-16`.
+        #[arg(long, value_parser = parse_time, value_name = "TIME")]
+        since: Option<SystemTime>,
+        /// Only the records logged at or before this time.
+        #[arg(long, value_parser = parse_time, value_name = "TIME")]
+        until: Option<SystemTime>,
+        /// The level names of the service, e.g. `warn=WARNING, width=7`.
+        #[arg(long, value_name = "NAMES")]
+        level_names: Option<LevelNames>,
+    },
+    /// Checks the logging config of a service and the environment, e.g.
+    /// `RUST_LOG`, and describes what it would install.
+    Check {
+        /// The service, instead of the `service_name` of the config file.
+        service: Option<String>,
+        /// A TOML config file, with the settings in a `[logger]` section or
+        /// at the top level.
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+        /// Logs to a file instead of the console.
+        #[arg(long)]
+        file: bool,
+        /// Directives in the syntax of `RUST_LOG`.
+        #[arg(long)]
+        filter: Option<String>,
+        /// A log4rs pattern for the console.
+        #[arg(long, value_name = "PATTERN")]
+        console_pattern: Option<String>,
+        #[arg(long)]
+        chain_id: Option<String>,
+        /// Prints the settings in effect, those of the environment
+        /// included, as TOML instead of a summary.
+        #[arg(long)]
+        dump: bool,
+    },
+}
+
+// A local time, a day or RFC 3339.
+fn parse_time(time: &str) -> Result<SystemTime, String> {
+    let local = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDate::parse_from_str(time, "%Y-%m-%d").map(|day| day.into()));
+    match local {
+        Ok(local) => local
+            .and_local_timezone(Local)
+            .earliest()
+            .map(SystemTime::from)
+            .ok_or_else(|| format!("nonexistent local time '{}'", time)),
+        Err(_) => DateTime::parse_from_rfc3339(time)
+            .map(SystemTime::from)
+            .map_err(|_| format!("invalid time '{}'", time)),
+    }
+}
+
+// Sends a command to the control socket of `service`, returning the answer.
+#[cfg(unix)]
+fn control(dir: &Path, service: &str, command: &str) -> io::Result<String> {
+    let path = dir.join(format!("{}.sock", service));
+    let mut stream = UnixStream::connect(&path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    writeln!(stream, "{}", command)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer)?;
+    let answer = answer.trim_end().to_string();
+    match answer.strip_prefix("error: ") {
+        Some(e) => Err(io::Error::other(e.to_string())),
+        None => Ok(answer),
+    }
+}
+
+#[cfg(not(unix))]
+fn control(_dir: &Path, _service: &str, _command: &str) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "control sockets need Unix",
+    ))
+}
+
+#[cfg(unix)]
+fn signal(pid: i32) -> io::Result<()> {
+    // Safe: kill only takes plain integers
+    if unsafe { libc::kill(pid, libc::SIGUSR1) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn signal(_pid: i32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "signals need Unix",
+    ))
+}
+
+// Whether `a` and `b` describe the same file, and not one rotated in its
+// place.
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    a.ino() == b.ino() && a.dev() == b.dev()
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    a.created().ok() == b.created().ok()
+}
+
+// The offset of the last `lines` lines of `file`.
+fn last_lines(file: &mut File, lines: usize) -> io::Result<u64> {
+    let len = file.metadata()?.len();
+    let mut buf = [0; 8192];
+    let mut pos = len;
+    let mut found = 0;
+    while pos > 0 && lines > 0 {
+        let size = pos.min(buf.len() as u64) as usize;
+        pos -= size as u64;
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut buf[..size])?;
+        for (i, _) in buf[..size]
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, &b)| b == b'\n')
+        {
+            let end = pos + i as u64 + 1;
+            // The newline ending the file does not start a line
+            if end == len {
+                continue;
+            }
+            found += 1;
+            if found == lines {
+                return Ok(end);
+            }
+        }
+    }
+    Ok(if lines == 0 { len } else { 0 })
+}
+
+fn tail(dir: &Path, service: &str, lines: usize, follow: bool) -> io::Result<()> {
+    let path = dir.join(format!("{}.log", service));
+    let mut file = File::open(&path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let start = last_lines(&mut file, lines)?;
+    file.seek(SeekFrom::Start(start))?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut reader = BufReader::new(file);
+    io::copy(&mut reader, &mut out)?;
+    out.flush()?;
+    if !follow {
+        return Ok(());
+    }
+
+    loop {
+        thread::sleep(Duration::from_millis(250));
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            out.write_all(&line)?;
+            line.clear();
+        }
+        out.flush()?;
+
+        // Start over with the new file after a rotation or a truncation
+        let current = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let opened = reader.get_ref().metadata()?;
+        let position = reader.stream_position()?;
+        if !same_file(&current, &opened) || current.len() < position {
+            if let Ok(file) = File::open(&path) {
+                reader = BufReader::new(file);
+            }
+        }
+    }
+}
+
+fn grep(dir: &Path, service: &str, pattern: Option<Regex>, query: LogQuery) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    for entry in read_logs(dir, service, query)? {
+        let entry = entry?;
+        if pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(&entry.message))
+        {
+            writeln!(out, "{}", entry)?;
+        }
+    }
+    out.flush()
+}
+
+fn check(config: &LoggerConfig, dump: bool) -> io::Result<()> {
+    match cita_logger::check(config) {
+        Ok(_) if dump => {
+            let effective = toml::to_string(&config.builder().effective_config())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            print!("{}", effective);
+            Ok(())
+        }
+        Ok(summary) => {
+            println!("{}", summary);
+            Ok(())
+        }
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid config\n{}", e),
+        )),
+    }
+}
+
+fn run(cli: Cli) -> io::Result<()> {
+    match cli.command {
+        Command::Rotate { service, pid } => match pid {
+            Some(pid) => signal(pid),
+            None => control(&cli.dir, &service, "rotate").map(drop),
+        },
+        Command::Set { service, args } => {
+            control(&cli.dir, &service, &format!("set {}", args.join(" "))).map(drop)
+        }
+        Command::Reset { service, module } => {
+            control(&cli.dir, &service, &format!("reset {}", module)).map(drop)
+        }
+        Command::Show { service } => {
+            println!("{}", control(&cli.dir, &service, "show")?);
+            Ok(())
+        }
+        Command::Tail {
+            service,
+            lines,
+            follow,
+        } => tail(&cli.dir, &service, lines, follow),
+        Command::Grep {
+            service,
+            pattern,
+            level,
+            target,
+            since,
+            until,
+            level_names,
+        } => {
+            let mut query = LogQuery::new().level(level);
+            if let Some(names) = level_names {
+                query = query.level_names(names);
+            }
+            if let Some(target) = target {
+                query = query.target(&target);
+            }
+            if let Some(since) = since {
+                query = query.since(since);
+            }
+            if let Some(until) = until {
+                query = query.until(until);
+            }
+            grep(&cli.dir, &service, pattern, query)
+        }
+        Command::Check {
+            service,
+            config,
+            file,
+            filter,
+            console_pattern,
+            chain_id,
+            dump,
+        } => {
+            let mut config = match config {
+                Some(path) => LoggerConfig::default().merge_file(path)?,
+                None => LoggerConfig::default(),
+            };
+            config.service_name = service.unwrap_or(config.service_name);
+            config.to_file |= file;
+            config.filter = filter.or(config.filter);
+            config.console_pattern = console_pattern.or(config.console_pattern);
+            config.chain_id = chain_id.or(config.chain_id);
+            check(&config, dump)
+        }
+    }
+}
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("cita-logctl: {}", e);
+        process::exit(1);
+    }
+}
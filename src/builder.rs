@@ -0,0 +1,1899 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+#[cfg(feature = "amqp")]
+use crate::append::amqp::{Amqp, AmqpAppender};
+#[cfg(feature = "smtp")]
+use crate::append::email::{Email, EmailAppender};
+use crate::append::fallback::FallbackAppender;
+use crate::append::file::{self, FileAppender, SyncPolicy};
+#[cfg(feature = "webhook")]
+use crate::append::webhook::{Webhook, WebhookAppender};
+use crate::append::{self, AppenderContext};
+#[cfg(feature = "s3")]
+use crate::archive::S3Archive;
+use crate::audit::{self, Audit};
+use crate::banner;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use crate::compress::Compression;
+use crate::config::{format_duration, parse_duration, LoggerConfig};
+use crate::container;
+#[cfg(unix)]
+use crate::control;
+use crate::crash;
+use crate::describe::{self, ConfigSummary};
+use crate::disk::{DiskAction, DiskGuard};
+use crate::encode::color::{ColorTheme, Themed};
+#[cfg(feature = "encryption")]
+use crate::encode::encrypt::{Encrypt, EncryptionKey};
+use crate::encode::level::{LevelNames, LevelPatterns};
+use crate::encode::message::{AppendFields, MessageFormat, Rewrite};
+use crate::encode::{self, Encoder, JsonEncoder, LogFormat, TimeFormat};
+use crate::error::{self, InternalErrorKind};
+#[cfg(feature = "zipkin")]
+use crate::export::ZipkinExporter;
+use crate::filter::{self, Filter};
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::handle::{self, LoggerHandle};
+use crate::index;
+use crate::instance::LoggerInstance;
+use crate::logger;
+use crate::manifest;
+use crate::preset::ServicePreset;
+use crate::ring;
+use crate::rotate::{self, LogLayout, RotationPolicy};
+use crate::slow;
+use crate::stats;
+#[cfg(feature = "websocket")]
+use crate::websocket;
+use crate::{info, level_from_str, parse_spec, warn, Directive, LogFavour, INIT_LOG};
+use chrono::{DateTime, Local};
+use crossbeam_channel::{bounded, Receiver, Sender};
+use libc::c_int;
+use log::LevelFilter;
+use log4rs::append::console::ConsoleAppender;
+use log4rs::append::Append;
+use log4rs::config::{Appender, Config, Logger, Root};
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::Encode;
+#[cfg(any(feature = "webhook", feature = "smtp", feature = "amqp"))]
+use log4rs::filter::threshold::ThresholdFilter;
+use regex::Regex;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::iter;
+#[cfg(any(feature = "grpc", feature = "websocket"))]
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::vec::Vec;
+
+// The latency of the batches of a `LoggerConfig` without `batch_latency`
+const BATCH_LATENCY: Duration = Duration::from_millis(50);
+
+/// Configures and installs the logger.
+///
+/// `init_config(&favour)` is a shorthand for `Builder::new(&favour).init()`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    service_name: String,
+    to_file: bool,
+    detect_container: bool,
+    env: String,
+    directives: Vec<Directive>,
+    message: Option<Regex>,
+    flush_level: LevelFilter,
+    flush_interval: Option<Duration>,
+    flush_every: Option<Duration>,
+    async_writes: bool,
+    batch: Option<(usize, Duration)>,
+    index_interval: Option<u64>,
+    sync: SyncPolicy,
+    file_mode: Option<u32>,
+    file_group: Option<String>,
+    disk_guard: Option<DiskGuard>,
+    stats_interval: Option<Duration>,
+    audit: Option<Audit>,
+    slow_log: bool,
+    slow_sync: Option<SyncPolicy>,
+    ring_buffer: Option<(usize, LevelFilter)>,
+    crash_dump: bool,
+    control_socket: bool,
+    file_encoder: Option<Arc<dyn Encoder>>,
+    console_encoder: Option<Arc<dyn Encoder>>,
+    console_pattern: Option<String>,
+    console_location: bool,
+    message_format: MessageFormat,
+    level_names: Option<LevelNames>,
+    console_symbols: bool,
+    color_theme: Option<ColorTheme>,
+    retention: Option<usize>,
+    instances: Vec<(String, LoggerInstance)>,
+    quiet_crates: Vec<String>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<EncryptionKey>,
+    #[cfg(feature = "webhook")]
+    webhook: Option<Webhook>,
+    #[cfg(feature = "amqp")]
+    amqp: Option<Amqp>,
+    #[cfg(feature = "smtp")]
+    email: Option<Email>,
+    #[cfg(feature = "s3")]
+    s3_archive: Option<S3Archive>,
+    #[cfg(feature = "zipkin")]
+    span_exporter: Option<ZipkinExporter>,
+    #[cfg(feature = "grpc")]
+    grpc_server: Option<SocketAddr>,
+    #[cfg(feature = "websocket")]
+    websocket_server: Option<SocketAddr>,
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    compression: Option<Compression>,
+    rotate_hooks: Vec<rotate::Hook>,
+    manifest: bool,
+    layout: LogLayout,
+    log_root: String,
+    chain_id: Option<String>,
+    // `{log_root}`, or `{log_root}/{chain_id}`
+    log_dir: String,
+    format: LogFormat,
+    time_format: TimeFormat,
+    rotation: RotationPolicy,
+    current_symlink: bool,
+    rotate_on_startup: bool,
+    truncate_on_startup: bool,
+    flush_on_exit: bool,
+}
+
+impl Builder {
+    pub fn new(favour: &LogFavour) -> Builder {
+        let (service_name, to_file) = match favour {
+            LogFavour::Stdout(service_name) => (service_name, false),
+            LogFavour::File(service_name) => (service_name, true),
+        };
+        encode::mark_start();
+        Builder {
+            service_name: service_name.to_string(),
+            to_file,
+            detect_container: true,
+            env: "RUST_LOG".to_string(),
+            directives: Vec::new(),
+            message: None,
+            flush_level: LevelFilter::Warn,
+            flush_interval: None,
+            flush_every: None,
+            async_writes: false,
+            batch: None,
+            index_interval: None,
+            sync: SyncPolicy::Never,
+            file_mode: None,
+            file_group: None,
+            disk_guard: None,
+            stats_interval: None,
+            audit: None,
+            slow_log: false,
+            slow_sync: None,
+            ring_buffer: None,
+            crash_dump: false,
+            control_socket: false,
+            file_encoder: None,
+            console_encoder: None,
+            console_pattern: None,
+            console_location: false,
+            message_format: MessageFormat::default(),
+            level_names: None,
+            console_symbols: false,
+            color_theme: None,
+            retention: None,
+            instances: Vec::new(),
+            quiet_crates: filter::NOISY_CRATES
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            #[cfg(feature = "webhook")]
+            webhook: None,
+            #[cfg(feature = "amqp")]
+            amqp: None,
+            #[cfg(feature = "smtp")]
+            email: None,
+            #[cfg(feature = "s3")]
+            s3_archive: None,
+            #[cfg(feature = "zipkin")]
+            span_exporter: None,
+            #[cfg(feature = "grpc")]
+            grpc_server: None,
+            #[cfg(feature = "websocket")]
+            websocket_server: None,
+            #[cfg(any(feature = "gzip", feature = "zstd"))]
+            compression: None,
+            rotate_hooks: Vec::new(),
+            manifest: false,
+            layout: LogLayout::Flat,
+            log_root: "logs".to_string(),
+            chain_id: None,
+            log_dir: "logs".to_string(),
+            format: LogFormat::Text,
+            time_format: TimeFormat::Default,
+            rotation: RotationPolicy::default(),
+            current_symlink: false,
+            rotate_on_startup: false,
+            truncate_on_startup: false,
+            flush_on_exit: false,
+        }
+    }
+
+    /// Reads the directives from the environment variable `name`, e.g.
+    /// `CITA_LOG`, instead of `RUST_LOG`.
+    pub fn env(mut self, name: &str) -> Builder {
+        self.env = name.to_string();
+        self
+    }
+
+    /// Sets the level of a module (and its descendants) like a `RUST_LOG`
+    /// directive, an empty `name` setting the global level.
+    ///
+    /// A directive of the environment for the same name takes precedence.
+    pub fn directive(mut self, name: &str, level: LevelFilter) -> Builder {
+        self.directives.push(Directive {
+            name: name.to_string(),
+            level,
+            sample: None,
+        });
+        self
+    }
+
+    /// Sets directives in the syntax of `RUST_LOG`, e.g. read from the config
+    /// file of the service, the environment taking precedence.
+    ///
+    /// Invalid directives are ignored with a warning, see
+    /// `LoggerConfig::validate` to catch them early.
+    pub fn filter(mut self, spec: &str) -> Builder {
+        let (directives, message) = parse_spec(spec);
+        self.directives.extend(directives);
+        self.message = message.or(self.message);
+        self
+    }
+
+    /// Caps the modules `names` at warn unless a directive names them, e.g.
+    /// `RUST_LOG=debug,hyper=debug`, instead of the default `amiquip`,
+    /// `h2`, `hyper`, `mio`, `rustls`, `tokio_reactor`, `tokio_threadpool`,
+    /// `ureq` and `want`.
+    ///
+    /// `quiet_crates(&[])` leaves every crate at the global level.
+    pub fn quiet_crates(mut self, names: &[&str]) -> Builder {
+        self.quiet_crates = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Sets the module levels recommended for a CITA service, e.g.
+    /// `preset::consensus()`, as directives.
+    ///
+    /// The directives of the environment still take precedence.
+    pub fn service_preset(mut self, preset: ServicePreset) -> Builder {
+        for (name, level) in preset.directives() {
+            self = self.directive(name, level);
+        }
+        self
+    }
+
+    /// Records at or above `level` are flushed to the log file as soon as
+    /// they are written, even when the file appender is buffered.
+    ///
+    /// Defaults to `LevelFilter::Warn`.
+    pub fn flush_level(mut self, level: LevelFilter) -> Builder {
+        self.flush_level = level;
+        self
+    }
+
+    /// Buffers writes to the log file and flushes them every `interval`.
+    ///
+    /// By default every record is flushed as soon as it is written. Records
+    /// at or above the flush level are still flushed immediately.
+    pub fn flush_interval(mut self, interval: Duration) -> Builder {
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    /// Flushes all appenders every `interval` from a background thread, so
+    /// that at most `interval` of records are lost if the process dies.
+    ///
+    /// Unlike `flush_interval` it covers every appender, e.g. the instance
+    /// files and the registered ones, and works whether the file is buffered
+    /// or not.
+    pub fn flush_every(mut self, interval: Duration) -> Builder {
+        self.flush_every = Some(interval);
+        self
+    }
+
+    /// Writes the log files from a writer thread, which the logging threads
+    /// hand their encoded records to through a lock-free queue instead of
+    /// contending for the file.
+    ///
+    /// Write errors are then only reported (see `set_error_handler`), the
+    /// file no longer fails over to the console.
+    pub fn async_writes(mut self, enabled: bool) -> Builder {
+        self.async_writes = enabled;
+        self
+    }
+
+    /// With `async_writes`, gathers up to `size` records per logging thread
+    /// and hands them to the writer thread together, at the latest after
+    /// `max_latency`.
+    ///
+    /// Records at or above the flush level are handed over at once.
+    pub fn batch(mut self, size: usize, max_latency: Duration) -> Builder {
+        self.batch = Some((size, max_latency));
+        self
+    }
+
+    /// Keeps an index sidecar of the log file (`logs/{service}.log.idx`),
+    /// noting the offset and the time of one record in `every`, so that the
+    /// `reader` seeks to the start of a time range instead of scanning.
+    ///
+    /// The sidecar follows the file through the rotations, and is deleted
+    /// with the compression.
+    pub fn index(mut self, every: u64) -> Builder {
+        self.index_interval = Some(every);
+        self
+    }
+
+    /// Sets when the log file of the service is synced to disk with `fsync`,
+    /// and those of the instances and the slow log without a policy of their
+    /// own (see `LoggerInstance::sync` and `slow_log_sync`). The audit log
+    /// has its own, see `Audit::sync`.
+    ///
+    /// If any file has a policy but `SyncPolicy::Never` the log directory is
+    /// synced after each rotation too. Defaults to `SyncPolicy::Never`.
+    pub fn sync(mut self, sync: SyncPolicy) -> Builder {
+        self.sync = sync;
+        self
+    }
+
+    /// Sets the mode bits of the log files, e.g. `0o640`, instead of leaving
+    /// them to the umask. The `logs/` directory gets them too, executable
+    /// where readable.
+    pub fn file_mode(mut self, mode: u32) -> Builder {
+        self.file_mode = Some(mode);
+        self
+    }
+
+    /// Sets the group of the log files and the `logs/` directory, by name or
+    /// id.
+    pub fn file_group(mut self, group: &str) -> Builder {
+        self.file_group = Some(group.to_string());
+        self
+    }
+
+    /// Watches the free space of the log volume and takes `action` when it
+    /// drops below `min_free` bytes, logging a "log volume nearly full"
+    /// warning.
+    ///
+    /// Only applies when logging to a file, on Unix.
+    pub fn disk_guard(mut self, min_free: u64, action: DiskAction) -> Builder {
+        self.disk_guard = Some(DiskGuard { min_free, action });
+        self
+    }
+
+    /// Logs a summary of the record counters (see `stats()`) every
+    /// `interval`.
+    pub fn stats_interval(mut self, interval: Duration) -> Builder {
+        self.stats_interval = Some(interval);
+        self
+    }
+
+    /// Writes the events of `audit!` to `logs/{service}.audit.log`, a
+    /// hash-linked log (see `ChainAppender`) with its own rotation.
+    pub fn audit(mut self, audit: Audit) -> Builder {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Writes the records of `slow_log!` to `logs/{service}.slow.log`
+    /// instead of the regular log.
+    pub fn slow_log(mut self, enabled: bool) -> Builder {
+        self.slow_log = enabled;
+        self
+    }
+
+    /// Sets when the slow log is synced to disk, instead of the policy of
+    /// the service (see `sync`).
+    pub fn slow_log_sync(mut self, sync: SyncPolicy) -> Builder {
+        self.slow_sync = Some(sync);
+        self
+    }
+
+    /// Keeps the last `capacity` records at or above `level` in memory, see
+    /// `recent_records()`.
+    ///
+    /// `level` applies whatever the level filters of the log, so e.g. debug
+    /// records can be kept without writing them.
+    pub fn ring_buffer(mut self, capacity: usize, level: LevelFilter) -> Builder {
+        self.ring_buffer = Some((capacity, level));
+        self
+    }
+
+    /// Writes the ring buffer (see `ring_buffer`) and a backtrace to
+    /// `logs/{service}.crash-{time}.log` on a SIGSEGV, SIGBUS or SIGABRT,
+    /// or a panic if panics abort.
+    ///
+    /// Unwinding panics, which `catch_unwind` or a `join` may catch, go to
+    /// `logs/{service}.panic-{time}.log`, at most once a second.
+    pub fn crash_dump(mut self, enabled: bool) -> Builder {
+        self.crash_dump = enabled;
+        self
+    }
+
+    /// Logs JSON to stdout instead of to the file when running in a container
+    /// (or when `CITA_LOG_TO_STDOUT=1`), for the collector of the host.
+    ///
+    /// Enabled by default; `CITA_LOG_TO_STDOUT=0` disables it as well.
+    pub fn detect_container(mut self, enabled: bool) -> Builder {
+        self.detect_container = enabled;
+        self
+    }
+
+    /// Listens on the Unix socket `logs/{service}.sock` for commands
+    /// changing the levels at runtime, like `set cita_network debug`, which
+    /// only the user of the service may connect to. Only on Unix.
+    pub fn control_socket(mut self, enabled: bool) -> Builder {
+        self.control_socket = enabled;
+        self
+    }
+
+    /// Formats the records of the log file with `encoder` instead of the
+    /// built-in pattern.
+    pub fn file_encoder(mut self, encoder: Box<dyn Encoder>) -> Builder {
+        self.file_encoder = Some(Arc::from(encoder));
+        self
+    }
+
+    /// Formats the records printed to the console with `encoder` instead of
+    /// the built-in pattern.
+    pub fn console_encoder(mut self, encoder: Box<dyn Encoder>) -> Builder {
+        self.console_encoder = Some(Arc::from(encoder));
+        self
+    }
+
+    /// Encrypts the log file with `key`, see `encode::encrypt`.
+    ///
+    /// Requires the `encryption` feature.
+    #[cfg(feature = "encryption")]
+    pub fn encryption(mut self, key: EncryptionKey) -> Builder {
+        self.encryption = Some(key);
+        self
+    }
+
+    /// Formats the records printed to the console with a log4rs pattern
+    /// (e.g. `{h({l})} {m}{n}` for colored levels) instead of the built-in
+    /// one.
+    pub fn console_pattern(mut self, pattern: &str) -> Builder {
+        self.console_pattern = Some(pattern.to_string());
+        self
+    }
+
+    /// Colors the console records with `theme`, e.g. read from the config
+    /// file of the service, instead of the colors of `{h(...)}`.
+    ///
+    /// Defaults to the theme of `CITA_LOG_COLORS`, if any.
+    pub fn color_theme(mut self, theme: ColorTheme) -> Builder {
+        self.color_theme = Some(theme);
+        self
+    }
+
+    /// Includes the source file and line of each record in the built-in
+    /// console pattern.
+    pub fn console_location(mut self, enabled: bool) -> Builder {
+        self.console_location = enabled;
+        self
+    }
+
+    /// Escapes line breaks in messages as `\n` and `\r`, so that every
+    /// record takes exactly one line.
+    pub fn escape_newlines(mut self, enabled: bool) -> Builder {
+        self.message_format.escape_newlines = enabled;
+        self
+    }
+
+    /// Strips ANSI escape sequences from messages and escapes other control
+    /// characters, against log injection from untrusted data.
+    pub fn sanitize_control(mut self, enabled: bool) -> Builder {
+        self.message_format.sanitize_control = enabled;
+        self
+    }
+
+    /// Truncates messages longer than `bytes`, marking them with
+    /// `…[truncated N bytes]`. `None` or 0 disables the limit, as in
+    /// `LoggerConfig`.
+    ///
+    /// Defaults to 16 KiB.
+    pub fn max_message_len(mut self, bytes: Option<usize>) -> Builder {
+        self.message_format.max_len = bytes.filter(|bytes| *bytes > 0);
+        self
+    }
+
+    /// Abbreviates targets longer than `width`, shortening their leading
+    /// modules to one letter, e.g. `cita_chain::core::libchain::chain` to
+    /// `c::c::l::chain`, so the tail survives the fixed-width column.
+    pub fn abbreviate_targets(mut self, width: usize) -> Builder {
+        self.message_format.target_width = Some(width);
+        self
+    }
+
+    /// Displays the levels with other names or padding in the file and
+    /// console patterns.
+    pub fn level_names(mut self, names: LevelNames) -> Builder {
+        self.level_names = Some(names);
+        self
+    }
+
+    /// Displays the levels of the console records as symbols (see
+    /// `LevelNames::symbols`) instead of words, for the dense interactive
+    /// output of command line tools. The log file keeps the words.
+    pub fn console_symbols(mut self, enabled: bool) -> Builder {
+        self.console_symbols = enabled;
+        self
+    }
+
+    /// Keeps only the `files` most recent rotated log files, deleting older
+    /// ones after each rotation.
+    pub fn retention(mut self, files: usize) -> Builder {
+        self.retention = Some(files);
+        self
+    }
+
+    /// Routes the records of `target` and its submodules to `instance`
+    /// instead of the log of the service.
+    pub fn instance(mut self, target: &str, instance: LoggerInstance) -> Builder {
+        self.instances.push((target.to_string(), instance));
+        self
+    }
+
+    /// Posts warn and error records to a webhook as well.
+    ///
+    /// Requires the `webhook` feature.
+    #[cfg(feature = "webhook")]
+    pub fn webhook(mut self, webhook: Webhook) -> Builder {
+        self.webhook = Some(webhook);
+        self
+    }
+
+    /// Publishes warn and error records to a RabbitMQ exchange as well.
+    ///
+    /// Requires the `amqp` feature.
+    #[cfg(feature = "amqp")]
+    pub fn amqp(mut self, amqp: Amqp) -> Builder {
+        self.amqp = Some(amqp);
+        self
+    }
+
+    /// Mails digests of error records as well.
+    ///
+    /// Requires the `smtp` feature.
+    #[cfg(feature = "smtp")]
+    pub fn email(mut self, email: Email) -> Builder {
+        self.email = Some(email);
+        self
+    }
+
+    /// Uploads the rotated log files to an S3-compatible bucket, deleting
+    /// them locally once uploaded.
+    ///
+    /// Requires the `s3` feature.
+    #[cfg(feature = "s3")]
+    pub fn s3_archive(mut self, archive: S3Archive) -> Builder {
+        self.s3_archive = Some(archive);
+        self
+    }
+
+    /// Reports the timings of the spans of `span!` to Zipkin or Jaeger.
+    ///
+    /// Requires the `zipkin` feature.
+    #[cfg(feature = "zipkin")]
+    pub fn span_exporter(mut self, exporter: ZipkinExporter) -> Builder {
+        self.span_exporter = Some(exporter);
+        self
+    }
+
+    /// Serves the live records over gRPC on `addr`, see
+    /// `proto/log_stream.proto`.
+    ///
+    /// Requires the `grpc` feature.
+    #[cfg(feature = "grpc")]
+    pub fn grpc_server(mut self, addr: SocketAddr) -> Builder {
+        self.grpc_server = Some(addr);
+        self
+    }
+
+    /// Pushes the live records to WebSocket clients connecting to `addr`,
+    /// e.g. `ws://node:9100/?level=debug&target=cita_network&format=json`.
+    ///
+    /// Requires the `websocket` feature.
+    #[cfg(feature = "websocket")]
+    pub fn websocket_server(mut self, addr: SocketAddr) -> Builder {
+        self.websocket_server = Some(addr);
+        self
+    }
+
+    /// Compresses the rotated log files, before the rotation hooks and the
+    /// archival see them.
+    ///
+    /// Requires the `gzip` or the `zstd` feature.
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    pub fn compression(mut self, compression: Compression) -> Builder {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Sets where rotated files go, e.g. `LogLayout::Dated` for a directory
+    /// per day like the layout of object store archives.
+    pub fn layout(mut self, layout: LogLayout) -> Builder {
+        self.layout = layout;
+        self
+    }
+
+    /// Writes the log files of the service to `logs/{chain_id}/` instead
+    /// of `logs/`, so that the services of several chains in one process or
+    /// on one node never mix their files, rotations included.
+    ///
+    /// Characters other than ASCII letters, digits, `-`, `_` and `.` are
+    /// replaced with `_`.
+    pub fn chain_id(mut self, chain_id: &str) -> Builder {
+        self.chain_id = sanitize_chain_id(chain_id);
+        self.log_dir = log_dir(&self.log_root, self.chain_id.as_deref());
+        self
+    }
+
+    /// Writes the log files to `dir` instead of `logs`, e.g. a volume of
+    /// their own.
+    ///
+    /// `CITA_LOG_DIR` takes precedence.
+    pub fn log_dir(mut self, dir: &str) -> Builder {
+        self.log_root = dir.to_string();
+        self.log_dir = log_dir(&self.log_root, self.chain_id.as_deref());
+        self
+    }
+
+    /// Writes the records as JSON objects (see `JsonEncoder`) with
+    /// `LogFormat::Json`, to the log file and the console unless they have
+    /// encoders of their own.
+    ///
+    /// `CITA_LOG_FORMAT`, `text` or `json`, takes precedence.
+    pub fn format(mut self, format: LogFormat) -> Builder {
+        self.format = format;
+        self
+    }
+
+    /// Writes the times of the built-in patterns of the log file and the
+    /// console in `format`, e.g. `TimeFormat::Rfc3339` for the collectors
+    /// refusing the default one. The patterns set keep their own times.
+    pub fn time_format(mut self, format: TimeFormat) -> Builder {
+        self.time_format = format;
+        self
+    }
+
+    /// Overrides the settings of the builder with those of the TOML config
+    /// file `path`, in its `[logger]` section or at its top level, see
+    /// `LoggerConfig`.
+    ///
+    /// The settings come from the code, overridden by the config file,
+    /// overridden by the environment: the directives of `RUST_LOG` win over
+    /// the others, `CITA_LOG_DIR` replaces the log directory and
+    /// `CITA_LOG_FORMAT` the format. `effective_config` tells the outcome.
+    ///
+    /// Requires the `config` feature.
+    #[cfg(feature = "config")]
+    pub fn config_file<P: AsRef<Path>>(self, path: P) -> Result<Builder, Error> {
+        let config = self.settings().merge_file(path)?;
+        Ok(self.with_settings(&config))
+    }
+
+    /// The settings `init` would install, from the code, the config file and
+    /// the environment, e.g. to dump them.
+    pub fn effective_config(&self) -> LoggerConfig {
+        self.effective_config_with(env_var)
+    }
+
+    // `effective_config`, reading the environment through `var`.
+    pub(crate) fn effective_config_with(
+        &self,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> LoggerConfig {
+        let mut builder = self.clone();
+        builder.apply_env(&var);
+        let mut config = builder.settings();
+        if let Some(spec) = var(&self.env) {
+            config.filter = Some(match config.filter {
+                Some(filter) => join_specs(&filter, &spec),
+                None => spec,
+            });
+        }
+        config
+    }
+
+    /// Sets when the log files rotate, e.g. over a size or daily in addition
+    /// to on SIGUSR1.
+    pub fn rotation(mut self, policy: RotationPolicy) -> Builder {
+        self.rotation = policy;
+        self
+    }
+
+    /// Maintains the symlink `logs/{service}.current.log` to the file being
+    /// written, for `tail -F` and dashboards.
+    pub fn current_symlink(mut self, enabled: bool) -> Builder {
+        self.current_symlink = enabled;
+        self
+    }
+
+    /// Rotates the log files left by a previous run at `init`, named after
+    /// their last write, so that each run starts a fresh file.
+    pub fn rotate_on_startup(mut self, enabled: bool) -> Builder {
+        self.rotate_on_startup = enabled;
+        self
+    }
+
+    /// Empties the log files left by a previous run at `init` instead of
+    /// appending to them, which is the default.
+    ///
+    /// Has no effect with `rotate_on_startup`, which moves these files away
+    /// first.
+    pub fn truncate_on_startup(mut self, enabled: bool) -> Builder {
+        self.truncate_on_startup = enabled;
+        self
+    }
+
+    /// Handles SIGTERM and SIGINT by logging a shutdown marker and flushing
+    /// the appenders (see `shutdown`) before terminating as usual.
+    pub fn flush_on_exit(mut self, enabled: bool) -> Builder {
+        self.flush_on_exit = enabled;
+        self
+    }
+
+    /// Calls `hook` after each rotation with the path of the rotated file and
+    /// of the new one, e.g. to ship the rotated file.
+    pub fn on_rotate<F>(mut self, hook: F) -> Builder
+    where
+        F: Fn(&Path, &Path) + Send + Sync + 'static,
+    {
+        self.rotate_hooks
+            .push(rotate::Hook::Callback(Arc::new(hook)));
+        self
+    }
+
+    /// Runs the shell `command` after each rotation, with the path of the
+    /// rotated file and of the new one as `$1` and `$2`, and waits for it.
+    pub fn rotate_command(mut self, command: &str) -> Builder {
+        self.rotate_hooks
+            .push(rotate::Hook::Command(command.to_string()));
+        self
+    }
+
+    /// Keeps a manifest of the rotated files (`logs/{service}.manifest`),
+    /// with the SHA-256 and the length of each, for archival pipelines to
+    /// verify them.
+    ///
+    /// It is replaced atomically after each rotation, once the file is
+    /// compressed and before the rotation hooks run.
+    pub fn manifest(mut self, enabled: bool) -> Builder {
+        self.manifest = enabled;
+        self
+    }
+
+    /// Installs the logger. Only the first call in a process has any effect.
+    pub fn init(mut self) -> LoggerHandle {
+        INIT_LOG.call_once(|| {
+            self.apply_env(env_var);
+            let in_container = self.to_file && container::prefers_stdout(self.detect_container);
+            if in_container {
+                self.to_file = false;
+                if self.console_encoder.is_none() {
+                    self.console_encoder = Some(Arc::new(JsonEncoder::new()));
+                }
+            }
+
+            // Fall back to the console if the log file cannot be written
+            let log_name = format!("{}/{}.log", self.log_dir, self.service_name);
+            let unwritable = match self.to_file {
+                true => check_writable(&log_name).err(),
+                false => None,
+            };
+            if unwritable.is_some() {
+                self.to_file = false;
+            }
+
+            filter::install(self.level_filter(env_var));
+
+            if let Some((capacity, level)) = self.ring_buffer {
+                ring::install(capacity, level);
+            }
+            if self.crash_dump {
+                crash::install(&self.log_dir, &self.service_name);
+            }
+            if let Some(audit) = self.audit.clone() {
+                let path =
+                    PathBuf::from(format!("{}/{}.audit.log", self.log_dir, self.service_name));
+                if let Err(e) = audit::install(audit, path) {
+                    error::report(InternalErrorKind::Append, &format!("audit: {}", e));
+                }
+            }
+
+            if !self.to_file {
+                let config = self.config_console_appender();
+                describe::install(self.summary(appender_names(&config), None));
+                logger::init(config).unwrap();
+                if in_container {
+                    info!("logging to stdout instead of a file in a container");
+                }
+                if let Some(e) = unwritable {
+                    log::error!(
+                        "cannot write {} in {}: {}, logging to the console instead",
+                        log_name,
+                        env::current_dir().unwrap_or_default().display(),
+                        e
+                    );
+                }
+                log_summary();
+                self.spawn_flush_on_exit();
+                if let Some(interval) = self.flush_every {
+                    logger::spawn_flush(interval).unwrap();
+                }
+                if let Some(interval) = self.stats_interval {
+                    stats::spawn_summary(interval).unwrap();
+                }
+                self.spawn_span_exporter();
+                self.spawn_grpc_server();
+                self.spawn_websocket_server();
+                self.spawn_control_socket();
+                return;
+            }
+
+            // The config of log4rs
+            let previous = if self.rotate_on_startup {
+                self.rotate_previous()
+            } else {
+                if self.truncate_on_startup {
+                    self.truncate_previous();
+                }
+                Vec::new()
+            };
+            let config = self.config_file_appender(&log_name).unwrap();
+            describe::install(self.summary(appender_names(&config), Some(&log_name)));
+            let logger = logger::init(config).unwrap();
+            self.link_current();
+            log_summary();
+            if !previous.is_empty() {
+                self.after_rotation(&previous);
+                self.prune();
+            }
+
+            // Log rotate via signal(USR1)
+            let signals: &[c_int] = if self.rotation.signal && cfg!(unix) {
+                &[handle::ROTATE_SIGNAL]
+            } else {
+                &[]
+            };
+            let (rotate, signal) = notify(signals).unwrap();
+            handle::install_rotation(rotate);
+            self.spawn_flush_on_exit();
+
+            // Any and all threads spawned must come after the first call to notify (or notify_on).
+            // This is so all spawned threads inherit the blocked status of signals.
+            // If a thread starts before notify is called, it will not have the correct signal mask.
+            // When a signal is delivered, the result is indeterminate.
+            if let Some(interval) = self.flush_every {
+                logger::spawn_flush(interval).unwrap();
+            }
+            if let Some(interval) = self.stats_interval {
+                stats::spawn_summary(interval).unwrap();
+            }
+            if let Some(disk_guard) = self.disk_guard.clone() {
+                disk_guard
+                    .spawn(PathBuf::from(&self.log_dir), self.service_name.clone())
+                    .unwrap();
+            }
+            self.spawn_span_exporter();
+            self.spawn_grpc_server();
+            self.spawn_websocket_server();
+            self.spawn_control_socket();
+            thread::spawn(move || {
+                loop {
+                    // Blocks until this process is sent an USR1 signal, a
+                    // file is full, or midnight.
+                    self.wait_rotation(&signal);
+                    // The requests sent meanwhile are served by this round
+                    while signal.try_recv().is_ok() {}
+                    self.rotate(logger, &log_name);
+                    // Lets the full files request a rotation again, should
+                    // this one have failed
+                    handle::rotation_done();
+                }
+            });
+        });
+        LoggerHandle::new()
+    }
+
+    // Rotates the current log files and reconfigures the logger with new
+    // ones.
+    fn rotate(&self, logger: &logger::Logger, log_name: &str) {
+        let now = Local::now();
+        let mut rotated = match rotate_file(&self.log_dir, &self.service_name, self.layout, now) {
+            Ok(path) => vec![path],
+            Err(e) => {
+                warn!("logrotate failed because of {:?}", e.kind());
+                error::report(InternalErrorKind::Rotate, &e);
+                return;
+            }
+        };
+        for name in &self.file_names()[1..] {
+            match rotate_file(&self.log_dir, name, self.layout, now) {
+                Ok(path) => rotated.push(path),
+                Err(e) => {
+                    warn!("logrotate of {} failed because of {:?}", name, e.kind());
+                    error::report(InternalErrorKind::Rotate, &e);
+                }
+            }
+        }
+        if self.synced() {
+            if let Err(e) = file::sync_dir(&self.log_dir) {
+                warn!("sync of logs directory failed because of {:?}", e.kind());
+                error::report(InternalErrorKind::Rotate, &e);
+            }
+        }
+
+        // Reconfig
+        let new_config = match self.config_file_appender(log_name) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("reopening {} failed because of {}", log_name, e);
+                error::report(InternalErrorKind::Rotate, &e);
+                return;
+            }
+        };
+        logger.set_config(new_config);
+        self.link_current();
+        stats::count_rotation();
+
+        self.after_rotation(&rotated);
+        self.prune();
+    }
+
+    fn wait_rotation(&self, signal: &Receiver<c_int>) {
+        let midnight = match self.rotation.daily {
+            true => rotate::next_midnight(Local::now()),
+            false => None,
+        };
+        match midnight {
+            Some(midnight) => {
+                let timeout = (midnight - Local::now()).to_std().unwrap_or_default();
+                let _ = signal.recv_timeout(timeout);
+            }
+            None => {
+                signal.recv().unwrap();
+            }
+        }
+    }
+
+    // Rotates the non-empty files of the service and the instances.
+    fn rotate_previous(&self) -> Vec<(PathBuf, PathBuf)> {
+        let mut rotated = Vec::new();
+        for name in self.file_names() {
+            let modified = match fs::metadata(format!("{}/{}.log", self.log_dir, name)) {
+                Ok(metadata) if metadata.len() > 0 => metadata.modified(),
+                _ => continue,
+            };
+            let time = modified.map_or_else(|_| Local::now(), DateTime::from);
+            match rotate_file(&self.log_dir, &name, self.layout, time) {
+                Ok(paths) => rotated.push(paths),
+                Err(e) => error::report(InternalErrorKind::Rotate, &e),
+            }
+        }
+        rotated
+    }
+
+    fn truncate_previous(&self) {
+        for name in self.file_names() {
+            let path = format!("{}/{}.log", self.log_dir, name);
+            match OpenOptions::new().write(true).truncate(true).open(&path) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => error::report(InternalErrorKind::Rotate, &e),
+            }
+        }
+    }
+
+    // The names of the log files of the service, the instances and the slow
+    // operations, the service first.
+    fn file_names(&self) -> Vec<String> {
+        let mut names = vec![self.service_name.clone()];
+        names.extend(
+            self.instances
+                .iter()
+                .map(|(_, instance)| instance.name.clone()),
+        );
+        if self.slow_log {
+            names.push(format!("{}.slow", self.service_name));
+        }
+        names
+    }
+
+    // Whether any log file rotating with the service is synced to disk.
+    fn synced(&self) -> bool {
+        let instances = self
+            .instances
+            .iter()
+            .map(|(_, instance)| instance.sync.unwrap_or(self.sync));
+        let slow = self.slow_sync.filter(|_| self.slow_log);
+        iter::once(self.sync)
+            .chain(instances)
+            .chain(slow)
+            .any(|sync| sync != SyncPolicy::Never)
+    }
+
+    fn prune(&self) {
+        if let Some(retention) = self.retention {
+            for name in self.file_names() {
+                prune_rotated(&self.log_dir, &name, retention);
+            }
+        }
+        if self.manifest {
+            for name in self.file_names() {
+                self.update_manifest(&name, &[]);
+            }
+        }
+    }
+
+    fn update_manifest(&self, name: &str, rotated: &[&Path]) {
+        if let Err(e) = manifest::update(Path::new(&self.log_dir), name, rotated) {
+            warn!("manifest of {} failed because of {}", name, e);
+            error::report(InternalErrorKind::Rotate, &e);
+        }
+    }
+
+    fn link_current(&self) {
+        if !self.current_symlink {
+            return;
+        }
+        for name in self.file_names() {
+            if let Err(e) = link_current(&self.log_dir, &name) {
+                warn!("symlink of {} failed because of {:?}", name, e.kind());
+                error::report(InternalErrorKind::Rotate, &e);
+            }
+        }
+    }
+
+    // Processes the rotated files (with their current files), before they
+    // are pruned.
+    fn after_rotation(&self, rotated: &[(PathBuf, PathBuf)]) {
+        #[cfg(any(feature = "gzip", feature = "zstd"))]
+        let compressed: Vec<_>;
+        #[cfg(any(feature = "gzip", feature = "zstd"))]
+        let rotated = match self.compression {
+            Some(compression) => {
+                compressed = rotated
+                    .iter()
+                    .map(|(path, current)| match compression.compress(path) {
+                        Ok(path) => (path, current.clone()),
+                        Err(e) => {
+                            warn!("compression of {} failed because of {}", path.display(), e);
+                            error::report(InternalErrorKind::Rotate, &e);
+                            (path.clone(), current.clone())
+                        }
+                    })
+                    .collect();
+                &compressed[..]
+            }
+            None => rotated,
+        };
+        if self.manifest {
+            for (path, current) in rotated {
+                if let Some(name) = current.file_stem().and_then(|name| name.to_str()) {
+                    self.update_manifest(name, &[path]);
+                }
+            }
+        }
+        for (path, current) in rotated {
+            for hook in &self.rotate_hooks {
+                if let Err(e) = hook.run(path, current) {
+                    warn!(
+                        "rotation hook of {} failed because of {}",
+                        path.display(),
+                        e
+                    );
+                    error::report(InternalErrorKind::Rotate, &e);
+                }
+            }
+        }
+        #[cfg(feature = "s3")]
+        if let Some(archive) = &self.s3_archive {
+            for (path, _) in rotated {
+                if let Err(e) = archive.archive(path) {
+                    warn!("archival of {} failed because of {}", path.display(), e);
+                    error::report(InternalErrorKind::Rotate, &e);
+                }
+            }
+        }
+    }
+
+    fn spawn_flush_on_exit(&self) {
+        if !self.flush_on_exit {
+            return;
+        }
+        let exit = [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT];
+        match notify(&exit) {
+            Ok((_, signal)) => {
+                thread::spawn(move || {
+                    if let Ok(signal) = signal.recv() {
+                        banner::shutdown();
+                        let _ = signal_hook::low_level::emulate_default_handler(signal);
+                    }
+                });
+            }
+            Err(e) => warn!("flush on exit failed because of {:?}", e.kind()),
+        }
+    }
+
+    fn spawn_span_exporter(&self) {
+        #[cfg(feature = "zipkin")]
+        if let Some(exporter) = self.span_exporter.clone() {
+            if let Err(e) = exporter.spawn(&self.service_name) {
+                warn!("span export failed because of {:?}", e.kind());
+            }
+        }
+    }
+
+    fn spawn_grpc_server(&self) {
+        #[cfg(feature = "grpc")]
+        if let Some(addr) = self.grpc_server {
+            if let Err(e) = grpc::spawn(addr, &self.service_name) {
+                warn!("grpc server failed because of {:?}", e.kind());
+            }
+        }
+    }
+
+    fn spawn_websocket_server(&self) {
+        #[cfg(feature = "websocket")]
+        if let Some(addr) = self.websocket_server {
+            if let Err(e) = websocket::spawn(addr) {
+                warn!("websocket server failed because of {:?}", e.kind());
+            }
+        }
+    }
+
+    fn spawn_control_socket(&self) {
+        if self.control_socket {
+            #[cfg(unix)]
+            {
+                let path = PathBuf::from(format!("{}/{}.sock", self.log_dir, self.service_name));
+                if let Err(e) = control::spawn(&path) {
+                    warn!("control socket failed because of {:?}", e.kind());
+                }
+            }
+            #[cfg(not(unix))]
+            warn!("control socket failed: it needs Unix sockets");
+        }
+    }
+
+    // FileAppender config
+    fn config_file_appender(&self, file_path: &str) -> Result<Config, Error> {
+        let requests = self.file_appender(file_path, &self.service_name, self.sync)?;
+        Ok(self.config(Appender::builder().build("requests", Box::new(requests))))
+    }
+
+    // ConsoleAppender config
+    fn config_console_appender(&self) -> Config {
+        let stdout = ConsoleAppender::builder()
+            .encoder(self.console_encode(&self.service_name))
+            .build();
+
+        self.config(Appender::builder().build("stdout", Box::new(stdout)))
+    }
+
+    // The log file of a service or an instance
+    pub(crate) fn file_appender(
+        &self,
+        file_path: &str,
+        service_name: &str,
+        sync: SyncPolicy,
+    ) -> Result<FallbackAppender, Error> {
+        let mut file = FileAppender::builder()
+            .encoder(self.file_encode(service_name))
+            .flush_level(self.flush_level)
+            .sync(sync)
+            .max_size(self.rotation.max_size)
+            .index(self.index_interval)
+            .mode(self.file_mode)
+            .queued(self.async_writes)
+            .group(self.file_group.as_deref().map(file::group_id).transpose()?);
+        if let Some(interval) = self.flush_interval {
+            file = file.flush_interval(interval);
+        }
+        if let Some((size, max_latency)) = self.batch {
+            file = file.batch(size, max_latency);
+        }
+        let file = file.build(file_path)?;
+
+        // Fail over to the console if the file keeps failing
+        let stdout = ConsoleAppender::builder()
+            .encoder(self.console_encode(service_name))
+            .build();
+        Ok(FallbackAppender::new(Box::new(file), Box::new(stdout)))
+    }
+
+    fn file_encode(&self, service_name: &str) -> Box<dyn Encode> {
+        let encoder: Box<dyn Encode> = match &self.file_encoder {
+            Some(encoder) => Box::new(encode::Custom::new(encoder.clone())),
+            None => self.time_format.stamp(pattern_encode(
+                &file_pattern(service_name, self.time_format),
+                self.level_names.as_ref(),
+            )),
+        };
+        let encoder = Rewrite::wrap(encoder, self.message_format);
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.encryption {
+            return Box::new(Encrypt::new(encoder, key));
+        }
+        encoder
+    }
+
+    fn console_encode(&self, service_name: &str) -> Box<dyn Encode> {
+        let symbols = LevelNames::symbols();
+        let names = match self.console_symbols {
+            true => Some(&symbols),
+            false => self.level_names.as_ref(),
+        };
+        let encoder: Box<dyn Encode> = match &self.console_encoder {
+            Some(encoder) => Box::new(encode::Custom::new(encoder.clone())),
+            None => {
+                let encoder: Box<dyn Encode> =
+                    match self.color_theme.clone().or_else(ColorTheme::from_env) {
+                        Some(theme) => Box::new(AppendFields(Box::new(Themed::new(
+                            &self.console_layout(service_name),
+                            &theme,
+                            names,
+                        )))),
+                        None => pattern_encode(&self.console_layout(service_name), names),
+                    };
+                match self.console_pattern {
+                    Some(_) => encoder,
+                    None => self.time_format.stamp(encoder),
+                }
+            }
+        };
+        Rewrite::wrap(encoder, self.message_format)
+    }
+
+    fn console_layout(&self, service_name: &str) -> String {
+        match &self.console_pattern {
+            Some(pattern) => pattern.clone(),
+            None => console_pattern(service_name, self.console_location, self.time_format),
+        }
+    }
+
+    // What `init` would install, without installing it or touching the
+    // log files.
+    pub(crate) fn dry_run(mut self, var: impl Fn(&str) -> Option<String>) -> ConfigSummary {
+        self.apply_env(&var);
+        if self.to_file && container::prefers_stdout(self.detect_container) {
+            self.to_file = false;
+            if self.console_encoder.is_none() {
+                self.console_encoder = Some(Arc::new(JsonEncoder::new()));
+            }
+        }
+        let log_name = format!("{}/{}.log", self.log_dir, self.service_name);
+        let (main, file) = match self.to_file {
+            true => ("requests", Some(log_name.as_str())),
+            false => ("stdout", None),
+        };
+        let mut summary = self.summary(vec![main.to_string()], file);
+        let (root_level, levels) = self.level_filter(&var).levels();
+        summary.root_level = root_level;
+        summary.levels = levels;
+        summary
+    }
+
+    // `CITA_LOG_DIR` and `CITA_LOG_FORMAT` override the code and the config
+    // file, as the directives of RUST_LOG do.
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        if let Some(dir) = var("CITA_LOG_DIR") {
+            self.log_root = dir;
+            self.log_dir = log_dir(&self.log_root, self.chain_id.as_deref());
+        }
+        if let Some(format) = var("CITA_LOG_FORMAT") {
+            match format.parse() {
+                Ok(format) => self.format = format,
+                Err(e) => println!("warning: {} in CITA_LOG_FORMAT, ignoring it", e),
+            }
+        }
+        if self.format == LogFormat::Json {
+            let json = || -> Arc<dyn Encoder> { Arc::new(JsonEncoder::new()) };
+            self.file_encoder.get_or_insert_with(json);
+            self.console_encoder.get_or_insert_with(json);
+        }
+    }
+
+    // The settings of `LoggerConfig`.
+    pub(crate) fn settings(&self) -> LoggerConfig {
+        #[cfg(any(feature = "gzip", feature = "zstd"))]
+        let compression = self.compression.map(|compression| compression.to_string());
+        #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+        let compression = None;
+        LoggerConfig {
+            service_name: self.service_name.clone(),
+            to_file: self.to_file,
+            filter: directives_spec(&self.directives, self.message.as_ref()),
+            env: Some(self.env.clone()).filter(|env| env != "RUST_LOG"),
+            quiet_crates: Some(self.quiet_crates.clone()),
+            console_pattern: self.console_pattern.clone(),
+            colors: self.color_theme.as_ref().map(ColorTheme::to_string),
+            console_location: self.console_location,
+            console_symbols: self.console_symbols,
+            level_names: self.level_names.as_ref().map(LevelNames::to_string),
+            escape_newlines: self.message_format.escape_newlines,
+            sanitize_control: self.message_format.sanitize_control,
+            max_message_len: Some(self.message_format.max_len.unwrap_or(0)),
+            abbreviate_targets: self.message_format.target_width,
+            chain_id: self.chain_id.clone(),
+            log_dir: Some(self.log_root.clone()),
+            format: self.format,
+            time_format: self.time_format,
+            flush_level: Some(self.flush_level.as_str().to_ascii_lowercase()),
+            flush_interval: self.flush_interval.map(format_duration),
+            flush_every: self.flush_every.map(format_duration),
+            async_writes: self.async_writes,
+            batch_size: self.batch.map(|(size, _)| size),
+            batch_latency: self.batch.map(|(_, latency)| format_duration(latency)),
+            sync: self.sync,
+            index: self.index_interval,
+            file_mode: self.file_mode,
+            file_group: self.file_group.clone(),
+            disk_min_free: self.disk_guard.as_ref().map(|guard| guard.min_free),
+            disk_action: self
+                .disk_guard
+                .as_ref()
+                .map_or(DiskAction::Prune, |guard| guard.action),
+            max_size: self.rotation.max_size,
+            daily: self.rotation.daily,
+            rotate_on_signal: self.rotation.signal,
+            retention: self.retention,
+            layout: self.layout,
+            compression,
+            current_symlink: self.current_symlink,
+            rotate_on_startup: self.rotate_on_startup,
+            truncate_on_startup: self.truncate_on_startup,
+            rotate_command: self.rotate_hooks.iter().rev().find_map(|hook| match hook {
+                rotate::Hook::Command(command) => Some(command.clone()),
+                rotate::Hook::Callback(_) => None,
+            }),
+            manifest: self.manifest,
+            flush_on_exit: self.flush_on_exit,
+            detect_container: self.detect_container,
+            control_socket: self.control_socket,
+            ring_buffer: self.ring_buffer.map(|(capacity, _)| capacity),
+            ring_buffer_level: self
+                .ring_buffer
+                .map(|(_, level)| level.as_str().to_ascii_lowercase()),
+            crash_dump: self.crash_dump,
+            slow_log: self.slow_log,
+            stats_interval: self.stats_interval.map(format_duration),
+        }
+    }
+
+    // Replaces the settings of `LoggerConfig`, the invalid ones with their
+    // defaults, and keeps the others.
+    pub(crate) fn with_settings(mut self, config: &LoggerConfig) -> Builder {
+        let (directives, message) = match &config.filter {
+            Some(spec) => parse_spec(spec),
+            None => (Vec::new(), None),
+        };
+        self.service_name = config.service_name.clone();
+        self.to_file = config.to_file;
+        self.directives = directives;
+        self.message = message;
+        self.env = config.env.clone().unwrap_or_else(|| "RUST_LOG".to_string());
+        self.quiet_crates = match &config.quiet_crates {
+            Some(names) => names.clone(),
+            None => filter::NOISY_CRATES
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        };
+        self.console_pattern = config.console_pattern.clone();
+        self.color_theme = config
+            .colors
+            .as_ref()
+            .and_then(|colors| colors.parse().ok());
+        self.console_location = config.console_location;
+        self.console_symbols = config.console_symbols;
+        self.level_names = config
+            .level_names
+            .as_ref()
+            .and_then(|names| names.parse().ok());
+        self.message_format.escape_newlines = config.escape_newlines;
+        self.message_format.sanitize_control = config.sanitize_control;
+        self.message_format.max_len = match config.max_message_len {
+            Some(0) => None,
+            Some(bytes) => Some(bytes),
+            None => MessageFormat::default().max_len,
+        };
+        self.message_format.target_width = config.abbreviate_targets;
+        self.chain_id = config.chain_id.as_deref().and_then(sanitize_chain_id);
+        self.log_root = config.log_dir.clone().unwrap_or_else(|| "logs".to_string());
+        self.log_dir = log_dir(&self.log_root, self.chain_id.as_deref());
+        self.format = config.format;
+        self.time_format = config.time_format;
+        self.flush_level = config
+            .flush_level
+            .as_deref()
+            .and_then(|level| level_from_str(level.trim()))
+            .unwrap_or(LevelFilter::Warn);
+        let duration = |duration: &Option<String>| {
+            duration
+                .as_deref()
+                .and_then(|duration| parse_duration(duration).ok())
+                .filter(|duration| !duration.is_zero())
+        };
+        self.flush_interval = duration(&config.flush_interval);
+        self.flush_every = duration(&config.flush_every);
+        self.async_writes = config.async_writes;
+        self.batch = config.batch_size.map(|size| {
+            let latency = duration(&config.batch_latency).unwrap_or(BATCH_LATENCY);
+            (size, latency)
+        });
+        self.sync = config.sync;
+        self.index_interval = config.index;
+        self.file_mode = config.file_mode;
+        self.file_group = config.file_group.clone();
+        self.disk_guard = config.disk_min_free.map(|min_free| DiskGuard {
+            min_free,
+            action: config.disk_action,
+        });
+        self.rotation = RotationPolicy {
+            max_size: config.max_size.filter(|&bytes| bytes > 0),
+            daily: config.daily,
+            signal: config.rotate_on_signal,
+        };
+        self.retention = config.retention;
+        self.layout = config.layout;
+        #[cfg(any(feature = "gzip", feature = "zstd"))]
+        {
+            self.compression = config
+                .compression
+                .as_deref()
+                .and_then(|compression| crate::config::parse_compression(compression).ok());
+        }
+        self.current_symlink = config.current_symlink;
+        self.rotate_on_startup = config.rotate_on_startup;
+        self.truncate_on_startup = config.truncate_on_startup;
+        // Those of the code stay unless the config changes the last one
+        let command = self.settings().rotate_command;
+        if config.rotate_command != command {
+            self.rotate_hooks
+                .retain(|hook| matches!(hook, rotate::Hook::Callback(_)));
+            if let Some(command) = &config.rotate_command {
+                self.rotate_hooks
+                    .push(rotate::Hook::Command(command.clone()));
+            }
+        }
+        self.manifest = config.manifest;
+        self.flush_on_exit = config.flush_on_exit;
+        self.detect_container = config.detect_container;
+        self.control_socket = config.control_socket;
+        self.ring_buffer = config.ring_buffer.map(|capacity| {
+            let level = config
+                .ring_buffer_level
+                .as_deref()
+                .and_then(|level| level_from_str(level.trim()))
+                .unwrap_or(LevelFilter::Debug);
+            (capacity, level)
+        });
+        self.crash_dump = config.crash_dump;
+        self.slow_log = config.slow_log;
+        self.stats_interval = duration(&config.stats_interval);
+        self
+    }
+
+    // The directives of RUST_LOG come last to win over ours.
+    fn level_filter(&self, var: impl Fn(&str) -> Option<String>) -> Filter {
+        let (env_directives, message) = match var(&self.env) {
+            Some(s) => parse_spec(&s),
+            None => (Vec::new(), None),
+        };
+        let mut directives = self.directives.clone();
+        directives.extend(self.instances.iter().filter_map(|(target, instance)| {
+            instance.level.map(|level| Directive {
+                name: target.clone(),
+                level,
+                sample: None,
+            })
+        }));
+        directives.extend(env_directives);
+        Filter::new(
+            LevelFilter::Info,
+            directives,
+            message.or_else(|| self.message.clone()),
+        )
+        .quiet(&self.quiet_crates)
+    }
+
+    fn summary(&self, appenders: Vec<String>, file: Option<&str>) -> ConfigSummary {
+        let file_pattern = match &self.file_encoder {
+            Some(encoder) => format!("{:?}", encoder),
+            None => self
+                .time_format
+                .describe(&file_pattern(&self.service_name, self.time_format)),
+        };
+        #[cfg(feature = "encryption")]
+        let file_pattern = match &self.encryption {
+            Some(_) => format!("{} (encrypted)", file_pattern),
+            None => file_pattern,
+        };
+        ConfigSummary {
+            service_name: self.service_name.clone(),
+            file: file.map(str::to_string),
+            appenders,
+            file_pattern: file.map(|_| file_pattern),
+            console_pattern: match &self.console_encoder {
+                Some(encoder) => format!("{:?}", encoder),
+                None => self.console_layout(&self.service_name),
+            },
+            root_level: LevelFilter::Off,
+            levels: Vec::new(),
+            rotation: match (file, self.retention) {
+                (Some(_), Some(retention)) => format!(
+                    "{} to {}, keeping {}",
+                    self.rotation,
+                    self.rotated_pattern(),
+                    retention
+                ),
+                (Some(_), None) => format!("{} to {}", self.rotation, self.rotated_pattern()),
+                (None, _) => "none".to_string(),
+            },
+        }
+    }
+
+    fn rotated_pattern(&self) -> String {
+        let pattern = match self.layout {
+            LogLayout::Flat => format!(
+                "{}/{}_%Y-%m-%d_%H-%M-%S.log",
+                self.log_dir, self.service_name
+            ),
+            LogLayout::Dated => format!(
+                "{0}/{1}/%Y/%m/%d/{1}_%Y-%m-%d_%H-%M-%S.log",
+                self.log_dir, self.service_name
+            ),
+        };
+        #[cfg(any(feature = "gzip", feature = "zstd"))]
+        if let Some(compression) = self.compression {
+            return format!("{}.{}", pattern, compression.extension());
+        }
+        pattern
+    }
+
+    // Attach `main` and the optional appenders to the root logger. Levels
+    // are filtered by the front-end logger, so the root takes everything.
+    fn config(&self, main: Appender) -> Config {
+        let mut appenders = vec![main];
+        #[cfg(feature = "webhook")]
+        if let Some(webhook) = &self.webhook {
+            match WebhookAppender::new(webhook.clone(), &self.service_name) {
+                Ok(appender) => appenders.push(
+                    Appender::builder()
+                        .filter(Box::new(ThresholdFilter::new(webhook.level_filter())))
+                        .build("webhook", Box::new(appender)),
+                ),
+                Err(e) => error::report(InternalErrorKind::Append, &format!("webhook: {}", e)),
+            }
+        }
+        #[cfg(feature = "amqp")]
+        if let Some(amqp) = &self.amqp {
+            match AmqpAppender::new(amqp.clone(), &self.service_name) {
+                Ok(appender) => appenders.push(
+                    Appender::builder()
+                        .filter(Box::new(ThresholdFilter::new(amqp.level_filter())))
+                        .build("amqp", Box::new(appender)),
+                ),
+                Err(e) => error::report(InternalErrorKind::Append, &format!("amqp: {}", e)),
+            }
+        }
+        #[cfg(feature = "smtp")]
+        if let Some(email) = &self.email {
+            match EmailAppender::new(email.clone(), &self.service_name) {
+                Ok(appender) => appenders.push(
+                    Appender::builder()
+                        .filter(Box::new(ThresholdFilter::new(email.level_filter())))
+                        .build("email", Box::new(appender)),
+                ),
+                Err(e) => error::report(InternalErrorKind::Append, &format!("email: {}", e)),
+            }
+        }
+        let context = AppenderContext::new(&self.service_name, Path::new(&self.log_dir));
+        for (name, plugin) in append::registered(&context) {
+            appenders.push(Appender::builder().build(format!("plugin.{}", name), Box::new(plugin)));
+        }
+        let names: Vec<String> = appenders.iter().map(|a| a.name().to_string()).collect();
+
+        let mut config_builder = Config::builder().appenders(appenders);
+
+        // Components with loggers of their own
+        for (target, instance) in &self.instances {
+            let name = format!("instance.{}", instance.name);
+            let appender: Box<dyn Append> = if self.to_file {
+                let path = format!("{}/{}.log", self.log_dir, instance.name);
+                let sync = instance.sync.unwrap_or(self.sync);
+                match self.file_appender(&path, &instance.name, sync) {
+                    Ok(file) => Box::new(file),
+                    Err(e) => {
+                        error::report(InternalErrorKind::Append, &format!("{}: {}", name, e));
+                        continue;
+                    }
+                }
+            } else {
+                Box::new(
+                    ConsoleAppender::builder()
+                        .encoder(self.console_encode(&instance.name))
+                        .build(),
+                )
+            };
+            config_builder = config_builder
+                .appender(Appender::builder().build(name.clone(), appender))
+                .logger(
+                    Logger::builder()
+                        .appender(name)
+                        .additive(false)
+                        .build(target.clone(), LevelFilter::Trace),
+                );
+        }
+
+        // Slow operations go to their own file only, past the level filters
+        let mut slow_routed = false;
+        if self.slow_log {
+            let path = format!("{}/{}.slow.log", self.log_dir, self.service_name);
+            let sync = self.slow_sync.unwrap_or(self.sync);
+            match self.file_appender(&path, &self.service_name, sync) {
+                Ok(slow) => {
+                    slow_routed = true;
+                    config_builder =
+                        config_builder.appender(Appender::builder().build("slow", Box::new(slow)));
+                    config_builder = config_builder.logger(
+                        Logger::builder()
+                            .appender("slow")
+                            .additive(false)
+                            .build(slow::TARGET, LevelFilter::Trace),
+                    );
+                }
+                Err(e) => error::report(InternalErrorKind::Append, &format!("slow log: {}", e)),
+            }
+        }
+        slow::route(slow_routed);
+
+        config_builder
+            .build(Root::builder().appenders(names).build(LevelFilter::Trace))
+            .unwrap()
+    }
+}
+
+// Deletes the oldest rotated files in `log_dir` beyond `retention`.
+fn prune_rotated(log_dir: &str, service_name: &str, retention: usize) {
+    let files = match rotate::rotated_files(log_dir, service_name) {
+        Ok(files) => files,
+        Err(e) => {
+            error::report(InternalErrorKind::Rotate, &e);
+            return;
+        }
+    };
+    let excess = files.len().saturating_sub(retention);
+    for file in &files[..excess] {
+        if let Err(e) = fs::remove_file(file) {
+            warn!("delete {} failed because of {:?}", file.display(), e.kind());
+            error::report(InternalErrorKind::Rotate, &e);
+            continue;
+        }
+        index::remove(file);
+        // Empty day, month and year directories of the dated layout
+        let mut dir = file.parent();
+        while let Some(d) = dir.filter(|d| *d != Path::new(log_dir)) {
+            if fs::remove_dir(d).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+}
+
+// Renames the log file of a service or an instance with the time stamp,
+// returning the rotated and the current path.
+fn rotate_file(
+    log_dir: &str,
+    name: &str,
+    layout: LogLayout,
+    time: DateTime<Local>,
+) -> Result<(PathBuf, PathBuf), Error> {
+    let rotated = rotate::unused_rotated_path(Path::new(log_dir), name, time, layout);
+    let current = PathBuf::from(format!("{}/{}.log", log_dir, name));
+    if let Some(dir) = rotated.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::rename(&current, &rotated)?;
+    // The file is rotated already, so the logger must be reconfigured anyway
+    if let Err(e) = index::rename(&current, &rotated) {
+        warn!(
+            "renaming the index of {} failed because of {:?}",
+            name,
+            e.kind()
+        );
+        error::report(InternalErrorKind::Rotate, &e);
+    }
+    Ok((rotated, current))
+}
+
+// Points `{log_dir}/{name}.current.log` to `{log_dir}/{name}.log`, replacing
+// the link atomically.
+fn link_current(log_dir: &str, name: &str) -> Result<(), Error> {
+    let link = format!("{}/{}.current.log", log_dir, name);
+    let tmp = format!("{}.tmp", link);
+    let _ = fs::remove_file(&tmp);
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(format!("{}.log", name), &tmp)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(format!("{}.log", name), &tmp)?;
+    fs::rename(&tmp, &link)
+}
+
+// A chain id usable as a directory name, if any.
+fn sanitize_chain_id(chain_id: &str) -> Option<String> {
+    let chain_id: String = chain_id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    match chain_id.as_str() {
+        "" | "." | ".." => None,
+        _ => Some(chain_id),
+    }
+}
+
+// The directory of the log files, under `root` for the services of a
+// chain.
+pub(crate) fn log_dir(root: &str, chain_id: Option<&str>) -> String {
+    match chain_id.and_then(sanitize_chain_id) {
+        Some(chain_id) => format!("{}/{}", root, chain_id),
+        None => root.to_string(),
+    }
+}
+
+// `directives` in the syntax of RUST_LOG.
+fn directives_spec(directives: &[Directive], message: Option<&Regex>) -> Option<String> {
+    let directives: Vec<String> = directives
+        .iter()
+        .map(|directive| {
+            let mut level = directive.level.as_str().to_ascii_lowercase();
+            if let Some(rate) = directive.sample {
+                level = format!("{}@{}", level, rate);
+            }
+            match directive.name.as_str() {
+                "" => level,
+                name => format!("{}={}", name, level),
+            }
+        })
+        .collect();
+    let spec = match message {
+        Some(message) => format!("{}/{}", directives.join(","), message.as_str()),
+        None => directives.join(","),
+    };
+    Some(spec).filter(|spec| !spec.is_empty())
+}
+
+// The directives of `first` then those of `second`, which win, and the
+// regex of `second`, if any, instead of that of `first`.
+fn join_specs(first: &str, second: &str) -> String {
+    let (first_directives, first_message) = split_spec(first);
+    let (second_directives, second_message) = split_spec(second);
+    let directives: Vec<&str> = [first_directives, second_directives]
+        .iter()
+        .copied()
+        .filter(|directives| !directives.is_empty())
+        .collect();
+    match second_message.or(first_message) {
+        Some(message) => format!("{}/{}", directives.join(","), message),
+        None => directives.join(","),
+    }
+}
+
+fn split_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once('/') {
+        Some((directives, message)) => (directives, Some(message)),
+        None => (spec, None),
+    }
+}
+
+// A variable of the environment of the process, if set to valid unicode.
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+fn appender_names(config: &Config) -> Vec<String> {
+    config
+        .appenders()
+        .iter()
+        .map(|a| a.name().to_string())
+        .collect()
+}
+
+fn log_summary() {
+    if let Some(summary) = describe::describe_config() {
+        info!("logging config: {}", summary);
+    }
+}
+
+// The sender also takes signals raised in code, see `LoggerHandle::rotate`.
+fn notify(signals: &[c_int]) -> Result<(Sender<c_int>, Receiver<c_int>), Error> {
+    let (s, r) = bounded(100);
+    #[cfg(unix)]
+    {
+        let mut signals = signal_hook::iterator::Signals::new(signals)?;
+        let sender = s.clone();
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                let _ = sender.send(signal);
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    if !signals.is_empty() {
+        return Err(Error::new(ErrorKind::Unsupported, "signals need Unix"));
+    }
+    Ok((s, r))
+}
+
+// Whether the log file `path` can be appended to, creating it and its
+// directory, without building an appender: the file may be rotated or
+// truncated before the appender opens it.
+fn check_writable(path: &str) -> Result<(), Error> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map(|_| ())
+}
+
+fn pattern_encode(pattern: &str, names: Option<&LevelNames>) -> Box<dyn Encode> {
+    let encoder: Box<dyn Encode> = match names {
+        Some(names) => Box::new(LevelPatterns::new(pattern, names)),
+        None => Box::new(PatternEncoder::new(pattern)),
+    };
+    Box::new(AppendFields(encoder))
+}
+
+// Prefixed with the service, so merged files stay attributable
+fn file_pattern(service_name: &str, time: TimeFormat) -> String {
+    format!(
+        "[{}] {} | {{t:20.20}} - {{L:5}} | {{l:5}} - {{m}}{{n}}",
+        service_name,
+        time.field("{d(%Y-%m-%d - %H:%M:%S)}")
+    )
+}
+
+fn console_pattern(service_name: &str, location: bool, time: TimeFormat) -> String {
+    let time = time.field("{d}");
+    if location {
+        format!(
+            "[{}]: {} - {{l}} - {{f}}:{{L}} - {{m}}{{n}}",
+            service_name, time
+        )
+    } else {
+        format!("[{}]: {} - {{l}} - {{m}}{{n}}", service_name, time)
+    }
+}
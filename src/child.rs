@@ -0,0 +1,122 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Capture of the output of child processes, for launcher binaries.
+
+use log::Level;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// Forwards the stdout and stderr lines of a child process into the logger,
+/// with the target `child::{name}`.
+///
+/// ```no_run
+/// use cita_logger::ChildLogger;
+/// use std::process::Command;
+///
+/// let mut child = ChildLogger::new("executor")
+///     .spawn(Command::new("cita-executor").arg("-c").arg("executor.toml"))
+///     .unwrap();
+/// child.wait().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChildLogger {
+    target: String,
+    stdout_level: Level,
+    stderr_level: Level,
+}
+
+impl ChildLogger {
+    /// Logs stdout lines at info and stderr lines at warn.
+    pub fn new(name: &str) -> ChildLogger {
+        ChildLogger {
+            target: format!("child::{}", name),
+            stdout_level: Level::Info,
+            stderr_level: Level::Warn,
+        }
+    }
+
+    pub fn stdout_level(mut self, level: Level) -> ChildLogger {
+        self.stdout_level = level;
+        self
+    }
+
+    pub fn stderr_level(mut self, level: Level) -> ChildLogger {
+        self.stderr_level = level;
+        self
+    }
+
+    /// Spawns `command` with its stdout and stderr piped into the logger.
+    pub fn spawn(&self, command: &mut Command) -> io::Result<Child> {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(stdout) = child.stdout.take() {
+            self.adopt(stdout, self.stdout_level)?;
+        }
+        if let Some(stderr) = child.stderr.take() {
+            self.adopt(stderr, self.stderr_level)?;
+        }
+        Ok(child)
+    }
+
+    /// Forwards the lines of an existing pipe at `level` in the background,
+    /// until its end. The thread returns the number of lines.
+    pub fn adopt<R: Read + Send + 'static>(
+        &self,
+        pipe: R,
+        level: Level,
+    ) -> io::Result<JoinHandle<io::Result<usize>>> {
+        let target = self.target.clone();
+        thread::Builder::new()
+            .name("cita-logger-child".to_string())
+            .spawn(move || forward(pipe, &target, level))
+    }
+}
+
+fn forward<R: Read>(pipe: R, target: &str, level: Level) -> io::Result<usize> {
+    let mut pipe = BufReader::new(pipe);
+    let mut line = Vec::new();
+    let mut lines = 0;
+    loop {
+        line.clear();
+        if pipe.read_until(b'\n', &mut line)? == 0 {
+            return Ok(lines);
+        }
+        // The output of a child need not be UTF-8
+        let text = String::from_utf8_lossy(&line);
+        log::log!(target: target, level, "{}", text.trim_end_matches(['\n', '\r']));
+        lines += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChildLogger;
+    use log::Level;
+    use std::io::Cursor;
+    use std::process::Command;
+
+    #[test]
+    fn forward_lines() {
+        let logger = ChildLogger::new("test");
+        let lines = logger
+            .adopt(
+                Cursor::new(b"block 1\r\nblock \xff\nlast".to_vec()),
+                Level::Info,
+            )
+            .unwrap();
+        assert_eq!(lines.join().unwrap().unwrap(), 3);
+
+        let mut child = logger
+            .spawn(Command::new("sh").arg("-c").arg("echo out; echo err >&2"))
+            .unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! Command line flags configuring the logger.
+//!
+//! Requires the `clap` feature.
+//!
+//! ```no_run
+//! use clap::Parser;
+//!
+//! #[derive(Parser)]
+//! struct Cli {
+//!     #[command(flatten)]
+//!     log: cita_logger::clap::LogArgs,
+//! }
+//!
+//! Cli::parse().log.init("chain");
+//! ```
+
+use crate::encode::{BinaryEncoder, CsvEncoder, LogfmtEncoder, Rfc5424Encoder, TimeFormat};
+use crate::{Builder, LogFavour, LoggerHandle};
+use log::LevelFilter;
+
+/// The format of the log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ::clap::ValueEnum)]
+pub enum LogFormat {
+    /// The built-in text layout.
+    #[default]
+    Text,
+    Csv,
+    /// RFC 5424 syslog messages.
+    Syslog,
+    /// The compact binary format, read with `cita-logcat`.
+    Binary,
+    /// `key=value` lines, with the fields of `event!`.
+    Logfmt,
+}
+
+/// The logging flags of a CITA binary.
+#[derive(Debug, Clone, Default, ::clap::Args)]
+pub struct LogArgs {
+    /// Global log level: off, error, warn, info, debug or trace.
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+    /// Log to logs/{service}.log instead of the console.
+    #[arg(long)]
+    pub log_file: bool,
+    /// Format of the log file.
+    #[arg(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub log_format: LogFormat,
+    /// Format of the times of the built-in layouts, e.g. rfc3339.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub log_time: Option<TimeFormat>,
+    /// More verbose logging, may be repeated.
+    #[arg(short, long, action = ::clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Less verbose logging, may be repeated.
+    #[arg(short, long, action = ::clap::ArgAction::Count)]
+    pub quiet: u8,
+}
+
+impl LogArgs {
+    /// The global level: `--log-level`, or info raised by each `-v` and
+    /// lowered by each `-q`.
+    pub fn level(&self) -> LevelFilter {
+        if let Some(level) = self.log_level {
+            return level;
+        }
+        let n = LevelFilter::Info as i32 + i32::from(self.verbose) - i32::from(self.quiet);
+        LevelFilter::iter()
+            .nth(n.clamp(0, LevelFilter::Trace as i32) as usize)
+            .unwrap_or(LevelFilter::Trace)
+    }
+
+    /// A builder set up from the flags, for further configuration.
+    ///
+    /// `RUST_LOG` still takes precedence over the level.
+    pub fn builder(&self, service_name: &str) -> Builder {
+        let favour = if self.log_file {
+            LogFavour::File(service_name)
+        } else {
+            LogFavour::Stdout(service_name)
+        };
+        let mut builder = Builder::new(&favour).directive("", self.level());
+        if let Some(format) = self.log_time {
+            builder = builder.time_format(format);
+        }
+        match self.log_format {
+            LogFormat::Text => builder,
+            LogFormat::Csv => builder.file_encoder(Box::new(CsvEncoder::new())),
+            LogFormat::Syslog => builder.file_encoder(Box::new(Rfc5424Encoder::new(service_name))),
+            LogFormat::Binary => builder.file_encoder(Box::new(BinaryEncoder::new())),
+            LogFormat::Logfmt => builder.file_encoder(Box::new(LogfmtEncoder::new())),
+        }
+    }
+
+    /// Installs the logger as set up by the flags.
+    pub fn init(&self, service_name: &str) -> LoggerHandle {
+        self.builder(service_name).init()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogArgs, LogFormat, TimeFormat};
+    use ::clap::Parser;
+    use log::LevelFilter;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        log: LogArgs,
+    }
+
+    #[test]
+    fn flags() {
+        let cli = Cli::parse_from(["chain", "-vv", "--log-file", "--log-format", "csv"]);
+        assert_eq!(cli.log.level(), LevelFilter::Trace);
+        assert!(cli.log.log_file);
+        assert_eq!(cli.log.log_format, LogFormat::Csv);
+        assert_eq!(cli.log.log_time, None);
+
+        let cli = Cli::parse_from(["chain", "--log-time", "rfc3339"]);
+        assert_eq!(cli.log.log_time, Some(TimeFormat::Rfc3339));
+
+        let cli = Cli::parse_from(["chain", "-qqqq"]);
+        assert_eq!(cli.log.level(), LevelFilter::Off);
+
+        let cli = Cli::parse_from(["chain", "-v", "--log-level", "warn"]);
+        assert_eq!(cli.log.level(), LevelFilter::Warn);
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Compression of rotated log files, before the hooks and the archival.
+
+use crate::index;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// How rotated log files are compressed, see `Builder::compression`.
+///
+/// Requires the `gzip` or the `zstd` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// `.log.gz`, with a level from 0 to 9.
+    #[cfg(feature = "gzip")]
+    Gzip(u32),
+    /// `.log.zst`, with a level from 1 to 22 (0 for the default, 3).
+    /// Repetitive logs compress about three times better than with gzip.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+// As in a config file, e.g. `zstd:19`.
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(level) => write!(f, "gzip:{}", level),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => write!(f, "zstd:{}", level),
+        }
+    }
+}
+
+impl Compression {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(_) => "gz",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(_) => "zst",
+        }
+    }
+
+    // Compresses `path` next to it, then deletes it, returning the path of
+    // the compressed file.
+    pub(crate) fn compress(self, path: &Path) -> io::Result<PathBuf> {
+        let mut compressed = path.as_os_str().to_owned();
+        compressed.push(".");
+        compressed.push(self.extension());
+        let compressed = PathBuf::from(compressed);
+
+        let mut input = BufReader::new(File::open(path)?);
+        let output = File::create(&compressed)?;
+        let result = match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(level) => {
+                let level = flate2::Compression::new(level.min(9));
+                let mut encoder = flate2::write::GzEncoder::new(output, level);
+                io::copy(&mut input, &mut encoder).and_then(|_| encoder.finish())
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => {
+                zstd::stream::copy_encode(&mut input, &output, level).map(|_| output)
+            }
+        }
+        .and_then(|mut output| {
+            output.flush()?;
+            Ok(output)
+        });
+        if let Err(e) = result {
+            let _ = fs::remove_file(&compressed);
+            return Err(e);
+        }
+        // Keep the mode and the group of the log file
+        let metadata = fs::metadata(path)?;
+        fs::set_permissions(&compressed, metadata.permissions())?;
+        #[cfg(unix)]
+        let _ = std::os::unix::fs::chown(&compressed, None, Some(metadata.gid()));
+        fs::remove_file(path)?;
+        // Its offsets are those of the uncompressed file
+        index::remove(path);
+        Ok(compressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compression;
+    use std::fs;
+    use std::io::Read;
+
+    #[test]
+    fn compress() {
+        let dir = std::env::temp_dir().join(format!("cita-logger-compress-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let text = "INFO - consensus - new block\n".repeat(100);
+        let compressions = [
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(6),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(3),
+        ];
+        for compression in compressions {
+            let path = dir.join("chain_2019-05-16_10-20-30.log");
+            fs::write(&path, &text).unwrap();
+            let compressed = compression.compress(&path).unwrap();
+            assert!(!path.exists());
+            let file = fs::File::open(&compressed).unwrap();
+            let mut decompressed = String::new();
+            match compression {
+                #[cfg(feature = "gzip")]
+                Compression::Gzip(_) => {
+                    assert!(compressed.to_string_lossy().ends_with(".log.gz"));
+                    flate2::read::GzDecoder::new(file)
+                        .read_to_string(&mut decompressed)
+                        .unwrap();
+                }
+                #[cfg(feature = "zstd")]
+                Compression::Zstd(_) => {
+                    assert!(compressed.to_string_lossy().ends_with(".log.zst"));
+                    zstd::stream::read::Decoder::new(file)
+                        .unwrap()
+                        .read_to_string(&mut decompressed)
+                        .unwrap();
+                }
+            }
+            assert_eq!(decompressed, text);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,978 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// The settings of the logger as data, e.g. read from the config file of a
+// service, their validation and a dry run of their installation.
+
+use crate::append::file::{self, SyncPolicy};
+use crate::builder::{self, Builder};
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use crate::compress::Compression;
+use crate::describe::ConfigSummary;
+use crate::disk::DiskAction;
+use crate::encode::{field_end, ColorTheme, LevelNames, LogFormat, TimeFormat};
+use crate::rotate::LogLayout;
+use crate::{level_from_str, parse_level, LogFavour};
+use log::Record;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::Encode;
+use regex::Regex;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+#[cfg(feature = "config")]
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// The settings of the logger, e.g. read from the config file of a
+/// service.
+///
+/// `validate` reports every problem of the settings at once, where
+/// installing a logger would panic on the first or silently ignore it, and
+/// `builder` configures a `Builder` with them.
+///
+/// ```
+/// use cita_logger::LoggerConfig;
+///
+/// let mut config = LoggerConfig::new("chain");
+/// config.filter = Some("info,cita_network=loud".to_string());
+/// let error = config.validate().unwrap_err();
+/// assert_eq!(error.to_string(), "filter at 18: invalid level 'loud'");
+/// ```
+///
+/// With the `serde` feature, it is read from a section of the config file
+/// of the service, the settings left out at their defaults, e.g. in TOML:
+///
+/// ```toml
+/// [logger]
+/// service_name = "chain"
+/// favour = "file"
+/// filter = "info,cita_network=debug"
+/// max_size = 104857600
+/// daily = true
+/// retention = 7
+/// compression = "zstd:19"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct LoggerConfig {
+    /// Names the log files of the service.
+    pub service_name: String,
+    /// Logs to `logs/{service}.log` instead of the console, `favour =
+    /// "file"` rather than `"stdout"` in a config file.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            rename = "favour",
+            deserialize_with = "deserialize_favour",
+            serialize_with = "serialize_favour"
+        )
+    )]
+    pub to_file: bool,
+    /// Directives in the syntax of `RUST_LOG`, e.g. `info,cita_network=debug`.
+    pub filter: Option<String>,
+    /// Reads the directives of the environment from this variable instead
+    /// of `RUST_LOG`.
+    pub env: Option<String>,
+    /// See `Builder::quiet_crates`.
+    pub quiet_crates: Option<Vec<String>>,
+    /// A log4rs pattern for the console, see `Builder::console_pattern`.
+    pub console_pattern: Option<String>,
+    /// The colors of the console, in the syntax of `CITA_LOG_COLORS`.
+    pub colors: Option<String>,
+    pub console_location: bool,
+    pub console_symbols: bool,
+    /// The names of the levels, e.g. `warn=WARNING, width=7`, see
+    /// `LevelNames`.
+    pub level_names: Option<String>,
+    pub escape_newlines: bool,
+    pub sanitize_control: bool,
+    /// Truncates longer messages, 0 for no limit. Defaults to 16 KiB.
+    pub max_message_len: Option<usize>,
+    pub abbreviate_targets: Option<usize>,
+    /// See `Builder::chain_id`.
+    pub chain_id: Option<String>,
+    /// The directory of the log files, `logs` by default.
+    pub log_dir: Option<String>,
+    pub format: LogFormat,
+    /// `default`, `rfc3339`, `epoch` or `elapsed`, see `Builder::time_format`.
+    pub time_format: TimeFormat,
+    /// Flushes the log file after the records at or above this level, warn
+    /// by default.
+    pub flush_level: Option<String>,
+    /// Buffers the log file and flushes it this often, e.g. `1s`, see
+    /// `Builder::flush_interval`.
+    pub flush_interval: Option<String>,
+    /// Flushes every appender this often, see `Builder::flush_every`.
+    pub flush_every: Option<String>,
+    pub async_writes: bool,
+    /// Gathers this many records per thread with `async_writes`, see
+    /// `Builder::batch`.
+    pub batch_size: Option<usize>,
+    /// The latest the batches are handed over, 50ms by default.
+    pub batch_latency: Option<String>,
+    pub sync: SyncPolicy,
+    /// Indexes every this many records, see `Builder::index`.
+    pub index: Option<u64>,
+    /// The permissions of the log files, e.g. `0o640`.
+    pub file_mode: Option<u32>,
+    /// The group of the log files, by name or number.
+    pub file_group: Option<String>,
+    /// Guards the log volume below this many free bytes, see
+    /// `Builder::disk_guard`.
+    pub disk_min_free: Option<u64>,
+    /// `prune` or `raise-level`, the former by default.
+    pub disk_action: DiskAction,
+    /// Rotates once a file grows beyond this many bytes.
+    pub max_size: Option<u64>,
+    /// Rotates at local midnight.
+    pub daily: bool,
+    /// Rotates on SIGUSR1, enabled by default.
+    pub rotate_on_signal: bool,
+    /// Keeps only this many rotated files.
+    pub retention: Option<usize>,
+    pub layout: LogLayout,
+    /// `gzip` or `zstd`, with an optional level, e.g. `zstd:19`.
+    pub compression: Option<String>,
+    pub current_symlink: bool,
+    pub rotate_on_startup: bool,
+    pub truncate_on_startup: bool,
+    /// See `Builder::rotate_command`.
+    pub rotate_command: Option<String>,
+    pub manifest: bool,
+    pub flush_on_exit: bool,
+    /// Logs to the console in a container, enabled by default.
+    pub detect_container: bool,
+    pub control_socket: bool,
+    /// Keeps this many records in memory, see `Builder::ring_buffer`.
+    pub ring_buffer: Option<usize>,
+    /// The level of the ring buffer, debug by default.
+    pub ring_buffer_level: Option<String>,
+    pub crash_dump: bool,
+    pub slow_log: bool,
+    /// Logs the record counters this often, see `Builder::stats_interval`.
+    pub stats_interval: Option<String>,
+}
+
+impl LoggerConfig {
+    /// The defaults of `Builder`, logging to the console.
+    pub fn new(service_name: &str) -> LoggerConfig {
+        LoggerConfig {
+            service_name: service_name.to_string(),
+            to_file: false,
+            filter: None,
+            env: None,
+            quiet_crates: None,
+            console_pattern: None,
+            colors: None,
+            console_location: false,
+            console_symbols: false,
+            level_names: None,
+            escape_newlines: false,
+            sanitize_control: false,
+            max_message_len: None,
+            abbreviate_targets: None,
+            chain_id: None,
+            log_dir: None,
+            format: LogFormat::Text,
+            time_format: TimeFormat::Default,
+            flush_level: None,
+            flush_interval: None,
+            flush_every: None,
+            async_writes: false,
+            batch_size: None,
+            batch_latency: None,
+            sync: SyncPolicy::Never,
+            index: None,
+            file_mode: None,
+            file_group: None,
+            disk_min_free: None,
+            disk_action: DiskAction::Prune,
+            max_size: None,
+            daily: false,
+            rotate_on_signal: true,
+            retention: None,
+            layout: LogLayout::Flat,
+            compression: None,
+            current_symlink: false,
+            rotate_on_startup: false,
+            truncate_on_startup: false,
+            rotate_command: None,
+            manifest: false,
+            flush_on_exit: false,
+            detect_container: true,
+            control_socket: false,
+            ring_buffer: None,
+            ring_buffer_level: None,
+            crash_dump: false,
+            slow_log: false,
+            stats_interval: None,
+        }
+    }
+
+    /// Checks every setting, and reports all the problems found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Problems(Vec::new());
+        check_file_name(&mut problems, "service_name", &self.service_name);
+        if let Some(filter) = &self.filter {
+            check_filter(&mut problems, "filter", filter);
+        }
+        if self.env.as_deref() == Some("") {
+            problems.push("env", None, "is empty".to_string());
+        }
+        if let Some(pattern) = &self.console_pattern {
+            check_pattern(&mut problems, "console_pattern", pattern);
+        }
+        if let Some(colors) = &self.colors {
+            check_colors(&mut problems, "colors", colors);
+        }
+        if let Some(names) = &self.level_names {
+            if let Err(e) = names.parse::<LevelNames>() {
+                problems.push("level_names", None, e);
+            }
+        }
+        if let Some(chain_id) = &self.chain_id {
+            check_chain_id(&mut problems, chain_id);
+        }
+        if self.log_dir.as_deref() == Some("") {
+            problems.push("log_dir", None, "is empty".to_string());
+        }
+        if let Some(level) = &self.flush_level {
+            if level_from_str(level.trim()).is_none() {
+                problems.push("flush_level", None, format!("invalid level '{}'", level));
+            }
+        }
+        self.check_durations(&mut problems);
+        if self.batch_latency.is_some() && self.batch_size.is_none() {
+            problems.push(
+                "batch_latency",
+                None,
+                "has no effect without batch_size".to_string(),
+            );
+        }
+        if let Some(level) = &self.ring_buffer_level {
+            if level_from_str(level.trim()).is_none() {
+                let message = format!("invalid level '{}'", level);
+                problems.push("ring_buffer_level", None, message);
+            } else if self.ring_buffer.is_none() {
+                let message = "has no effect without ring_buffer".to_string();
+                problems.push("ring_buffer_level", None, message);
+            }
+        }
+        self.check_files(&mut problems);
+        self.check_rotation(&mut problems);
+        problems.into_result(())
+    }
+
+    /// A `Builder` with these settings, the invalid ones at their defaults.
+    pub fn builder(&self) -> Builder {
+        let favour = match self.to_file {
+            true => LogFavour::File(&self.service_name),
+            false => LogFavour::Stdout(&self.service_name),
+        };
+        Builder::new(&favour).with_settings(self)
+    }
+
+    /// Overrides these settings with those of the TOML config file `path`,
+    /// in its `[logger]` section or at its top level.
+    ///
+    /// Requires the `config` feature.
+    #[cfg(feature = "config")]
+    pub fn merge_file<P: AsRef<Path>>(self, path: P) -> io::Result<LoggerConfig> {
+        let path = path.as_ref();
+        let invalid = |e: &dyn fmt::Display| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        };
+        let file: toml::Value = fs::read_to_string(path)?.parse().map_err(|e| invalid(&e))?;
+        let file = match file.get("logger") {
+            Some(section) => section.clone(),
+            None => file,
+        };
+        let mut merged = toml::Value::try_from(&self).map_err(|e| invalid(&e))?;
+        if let (Some(merged), toml::Value::Table(file)) = (merged.as_table_mut(), file) {
+            merged.extend(file);
+        }
+        merged.try_into().map_err(|e| invalid(&e))
+    }
+
+    fn log_dir(&self) -> String {
+        builder::log_dir(
+            self.log_dir.as_deref().unwrap_or("logs"),
+            self.chain_id.as_deref(),
+        )
+    }
+
+    fn check_durations(&self, problems: &mut Problems) {
+        let durations = [
+            ("flush_interval", &self.flush_interval),
+            ("flush_every", &self.flush_every),
+            ("batch_latency", &self.batch_latency),
+            ("stats_interval", &self.stats_interval),
+        ];
+        for (field, duration) in durations {
+            match duration.as_deref().map(parse_duration) {
+                Some(Ok(duration)) if duration.is_zero() => {
+                    problems.push(field, None, "must be above 0".to_string())
+                }
+                Some(Err(e)) => problems.push(field, None, e),
+                _ => {}
+            }
+        }
+    }
+
+    fn check_files(&self, problems: &mut Problems) {
+        if let Some(mode) = self.file_mode {
+            if mode & !0o777 != 0 {
+                problems.push("file_mode", None, format!("{:o} is not a file mode", mode));
+            } else if mode & 0o600 != 0o600 {
+                problems.push(
+                    "file_mode",
+                    None,
+                    format!("{:o} denies the service its own files", mode),
+                );
+            }
+        }
+        if let Some(group) = &self.file_group {
+            if let Err(e) = file::group_id(group) {
+                problems.push("file_group", None, e.to_string());
+            }
+        }
+    }
+
+    fn check_rotation(&self, problems: &mut Problems) {
+        if self.max_size == Some(0) {
+            problems.push("max_size", None, "must be above 0".to_string());
+        }
+        match self.retention {
+            Some(0) => problems.push(
+                "retention",
+                None,
+                "deletes every rotated file, must be at least 1".to_string(),
+            ),
+            Some(_) if self.max_size.is_none() && !self.daily && !self.rotate_on_signal => problems
+                .push(
+                    "retention",
+                    None,
+                    "has no effect, the files never rotate".to_string(),
+                ),
+            _ => {}
+        }
+        if let Some(compression) = &self.compression {
+            #[cfg(any(feature = "gzip", feature = "zstd"))]
+            let error = parse_compression(compression).err();
+            #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+            let error = Some(unknown_compression(compression));
+            if let Some(error) = error {
+                problems.push("compression", None, error);
+            }
+        }
+        if self.truncate_on_startup && self.rotate_on_startup {
+            problems.push(
+                "truncate_on_startup",
+                None,
+                "has no effect with rotate_on_startup".to_string(),
+            );
+        }
+        if self.rotate_command.as_deref().map(str::trim) == Some("") {
+            problems.push("rotate_command", None, "is empty".to_string());
+        }
+    }
+}
+
+/// Checks `config` and the environment of the process (`RUST_LOG` or the
+/// variable of `env`, `CITA_LOG_COLORS`, `CITA_LOG_DIR`, `CITA_LOG_FORMAT`
+/// and the log directory) without installing a logger,
+/// e.g. to lint the logging of a deployment before rolling it out.
+///
+/// Describes what `config.builder().init()` would install, or reports every
+/// problem found.
+///
+/// ```
+/// let config = cita_logger::LoggerConfig::new("chain");
+/// let summary = cita_logger::check(&config).unwrap();
+/// assert_eq!(summary.file, None);
+/// ```
+pub fn check(config: &LoggerConfig) -> Result<ConfigSummary, ConfigError> {
+    check_with(config, |name| env::var(name).ok())
+}
+
+// `check`, reading the environment through `var`.
+pub(crate) fn check_with(
+    config: &LoggerConfig,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<ConfigSummary, ConfigError> {
+    let mut problems = Problems(match config.validate() {
+        Ok(()) => Vec::new(),
+        Err(e) => e.problems,
+    });
+    // The variable named by `env`, if any
+    let (field, name) = match &config.env {
+        Some(name) => ("env", name.as_str()),
+        None => ("RUST_LOG", "RUST_LOG"),
+    };
+    if let Some(spec) = var(name) {
+        check_filter(&mut problems, field, &spec);
+    }
+    if let Some(colors) = var("CITA_LOG_COLORS") {
+        check_colors(&mut problems, "CITA_LOG_COLORS", &colors);
+    }
+    if let Some(format) = var("CITA_LOG_FORMAT") {
+        if let Err(e) = format.parse::<LogFormat>() {
+            problems.push("CITA_LOG_FORMAT", None, e);
+        }
+    }
+    if var("CITA_LOG_DIR").as_deref() == Some("") {
+        problems.push("CITA_LOG_DIR", None, "is empty".to_string());
+    }
+    if config.to_file {
+        let mut config = config.clone();
+        config.log_dir = var("CITA_LOG_DIR").or(config.log_dir);
+        check_log_dir(&mut problems, Path::new(&config.log_dir()));
+    }
+    problems.into_result(())?;
+    Ok(config.builder().dry_run(var))
+}
+
+impl Default for LoggerConfig {
+    fn default() -> LoggerConfig {
+        LoggerConfig::new("")
+    }
+}
+
+// `favour = "file"` or `"stdout"`, as `LogFavour`.
+#[cfg(feature = "serde")]
+fn deserialize_favour<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let favour: String = serde::Deserialize::deserialize(deserializer)?;
+    match favour.as_str() {
+        "file" => Ok(true),
+        "stdout" => Ok(false),
+        _ => Err(serde::de::Error::unknown_variant(
+            &favour,
+            &["file", "stdout"],
+        )),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn serialize_favour<S: serde::Serializer>(
+    to_file: &bool,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(if *to_file { "file" } else { "stdout" })
+}
+
+// `gzip` or `zstd`, with an optional level, e.g. `zstd:19`.
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub(crate) fn parse_compression(compression: &str) -> Result<Compression, String> {
+    let (name, level) = match compression.split_once(':') {
+        Some((name, level)) => (name.trim(), Some(level.trim())),
+        None => (compression.trim(), None),
+    };
+    let level = level
+        .map(|level| {
+            level
+                .parse::<u32>()
+                .map_err(|_| format!("invalid compression level '{}'", level))
+        })
+        .transpose()?;
+    match name {
+        #[cfg(feature = "gzip")]
+        "gzip" => Ok(Compression::Gzip(level.unwrap_or(6))),
+        #[cfg(feature = "zstd")]
+        "zstd" => Ok(Compression::Zstd(level.unwrap_or(0) as i32)),
+        _ => Err(unknown_compression(name)),
+    }
+}
+
+// A duration of the config file, e.g. `1s` or `1min 30s`.
+pub(crate) fn parse_duration(duration: &str) -> Result<Duration, String> {
+    humantime::parse_duration(duration.trim())
+        .map_err(|e| format!("invalid duration '{}': {}", duration, e))
+}
+
+pub(crate) fn format_duration(duration: Duration) -> String {
+    humantime::format_duration(duration).to_string()
+}
+
+fn unknown_compression(compression: &str) -> String {
+    let name = compression.split(':').next().unwrap_or_default().trim();
+    match name {
+        "gzip" | "zstd" => format!("requires the `{}` feature", name),
+        _ => format!("unknown compression '{}'", name),
+    }
+}
+
+/// A problem of a setting, see `LoggerConfig::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// The name of the setting, e.g. `filter`.
+    pub field: &'static str,
+    /// Where the problem starts in the value of the setting, in bytes.
+    pub position: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{} at {}: {}", self.field, position, self.message),
+            None => write!(f, "{}: {}", self.field, self.message),
+        }
+    }
+}
+
+/// The problems of an invalid `LoggerConfig`, a line each when displayed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    problems: Vec<ConfigProblem>,
+}
+
+impl ConfigError {
+    pub fn problems(&self) -> &[ConfigProblem] {
+        &self.problems
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConfigError {}
+
+struct Problems(Vec<ConfigProblem>);
+
+impl Problems {
+    fn into_result<T>(self, value: T) -> Result<T, ConfigError> {
+        match self.0.is_empty() {
+            true => Ok(value),
+            false => Err(ConfigError { problems: self.0 }),
+        }
+    }
+
+    fn push(&mut self, field: &'static str, position: Option<usize>, message: String) {
+        self.0.push(ConfigProblem {
+            field,
+            position,
+            message,
+        });
+    }
+}
+
+fn check_file_name(problems: &mut Problems, field: &'static str, name: &str) {
+    match name {
+        "" => return problems.push(field, None, "is empty".to_string()),
+        "." | ".." => return problems.push(field, Some(0), "is not a file name".to_string()),
+        _ => {}
+    }
+    for (i, c) in name.char_indices() {
+        if matches!(c, '/' | '\\') || c.is_control() {
+            problems.push(field, Some(i), format!("invalid character {:?}", c));
+        }
+    }
+}
+
+// As sanitized by `Builder::chain_id`.
+fn check_chain_id(problems: &mut Problems, chain_id: &str) {
+    match chain_id {
+        "" => return problems.push("chain_id", None, "is empty".to_string()),
+        "." | ".." => {
+            return problems.push("chain_id", Some(0), "is not a directory name".to_string())
+        }
+        _ => {}
+    }
+    for (i, c) in chain_id.char_indices() {
+        if !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+            problems.push(
+                "chain_id",
+                Some(i),
+                format!("invalid character {:?}, it would be replaced with '_'", c),
+            );
+        }
+    }
+}
+
+// Whether the log files could be created, without creating them.
+fn check_log_dir(problems: &mut Problems, dir: &Path) {
+    // The closest directory which exists
+    let existing = dir.ancestors().find(|dir| dir.exists());
+    let message = match existing.map(fs::metadata) {
+        Some(Ok(metadata)) if !metadata.is_dir() => "is not a directory",
+        Some(Ok(metadata)) if metadata.permissions().readonly() => "is read-only",
+        Some(Err(_)) => "cannot be read",
+        _ => return,
+    };
+    let existing = existing.filter(|existing| !existing.as_os_str().is_empty());
+    problems.push(
+        "log_dir",
+        None,
+        format!("{} {}", existing.unwrap_or(dir).display(), message),
+    );
+}
+
+// The syntax of `parse_spec`, which ignores the invalid directives.
+fn check_filter(problems: &mut Problems, field: &'static str, spec: &str) {
+    let (directives, message) = match spec.split_once('/') {
+        Some((directives, message)) => (directives, Some(message)),
+        None => (spec, None),
+    };
+    if let Some(message) = message {
+        if let Err(e) = Regex::new(message) {
+            // The last line of a syntax error, after the regex and a caret
+            let e = e.to_string();
+            let e = e.lines().last().unwrap_or_default();
+            problems.push(
+                field,
+                Some(directives.len() + 1),
+                format!("invalid regex: {}", e.trim_start_matches("error: ")),
+            );
+        }
+    }
+
+    let mut start = 0;
+    for directive in directives.split(',') {
+        let offset = start;
+        start += directive.len() + 1;
+        let parts: Vec<&str> = directive.split('=').collect();
+        let (level, at) = match parts.as_slice() {
+            // A target alone is at info, a sampled one is a level
+            [level] if level.contains('@') => (*level, offset),
+            [_] => continue,
+            [name, level] => (*level, offset + name.len() + 1),
+            _ => {
+                problems.push(
+                    field,
+                    Some(offset),
+                    format!("invalid directive '{}'", directive),
+                );
+                continue;
+            }
+        };
+        let trimmed = level.trim();
+        if !trimmed.is_empty() && parse_level(trimmed).is_none() {
+            let at = at + level.len() - level.trim_start().len();
+            problems.push(field, Some(at), format!("invalid level '{}'", trimmed));
+        }
+    }
+}
+
+// Encodes each field of the pattern alone, for the errors log4rs writes in
+// place of the invalid ones.
+fn check_pattern(problems: &mut Problems, field: &'static str, pattern: &str) {
+    let bytes = pattern.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let next = bytes.get(i + 1).copied();
+        match c {
+            b'{' | b'}' | b'(' | b')' if next == Some(c) => i += 2,
+            b'\\' if matches!(next, Some(b'{' | b'}' | b'(' | b')' | b'\\')) => i += 2,
+            b'{' => {
+                let end = field_end(bytes, i);
+                if let Some(error) = encode_error(&pattern[i..end]) {
+                    problems.push(field, Some(i), error);
+                }
+                i = end;
+            }
+            b'}' => {
+                problems.push(field, Some(i), "unmatched '}'".to_string());
+                i += 1;
+            }
+            b'(' | b')' => {
+                problems.push(field, Some(i), format!("unexpected '{}'", c as char));
+                i += 1;
+            }
+            b'\\' => {
+                problems.push(field, Some(i), "unexpected '\\'".to_string());
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+}
+
+fn encode_error(field: &str) -> Option<String> {
+    let mut out = SimpleWriter(Vec::new());
+    let encoded = PatternEncoder::new(field).encode(
+        &mut out,
+        &Record::builder()
+            .target("cita_logger")
+            .args(format_args!(""))
+            .build(),
+    );
+    if let Err(e) = encoded {
+        return Some(format!("invalid field '{}': {}", field, e));
+    }
+    let out = String::from_utf8_lossy(&out.0);
+    let start = out.find("{ERROR: ")? + "{ERROR: ".len();
+    let end = out.rfind('}').filter(|&end| end >= start)?;
+    Some(format!("{} in '{}'", &out[start..end], field))
+}
+
+fn check_colors(problems: &mut Problems, field: &'static str, colors: &str) {
+    let mut start = 0;
+    for pair in colors.split(',') {
+        let at = start + pair.len() - pair.trim_start().len();
+        start += pair.len() + 1;
+        if let Err(e) = pair.parse::<ColorTheme>() {
+            problems.push(field, Some(at), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_with, LoggerConfig};
+    use crate::disk::DiskAction;
+    use crate::encode::LevelNames;
+    use crate::{Builder, LogFavour, LogLayout};
+    #[cfg(feature = "serde")]
+    use crate::{SyncPolicy, TimeFormat};
+    use log::{Level, LevelFilter};
+    use std::time::Duration;
+
+    #[test]
+    fn all_problems() {
+        let mut config = LoggerConfig::new("chain");
+        config.to_file = true;
+        config.filter = Some("info,cita_network=debug@1%,chain=debug/block \\d+".to_string());
+        config.console_pattern = Some("{d(%H:%M)} {h({l})} {{x}} {m}{n}".to_string());
+        config.colors = Some("warn=bright yellow, time=cyan".to_string());
+        config.chain_id = Some("side-1".to_string());
+        config.max_size = Some(100 << 20);
+        config.retention = Some(7);
+        config.file_mode = Some(0o640);
+        assert_eq!(config.validate(), Ok(()));
+
+        let config = LoggerConfig {
+            service_name: "chain/1".to_string(),
+            filter: Some("info,a=loud, b= debug@200%,c=warn=info/block (".to_string()),
+            console_pattern: Some("{l} {q} {d(%H)} ) {m".to_string()),
+            colors: Some("warn=red, loud=red".to_string()),
+            chain_id: Some("side 1".to_string()),
+            max_size: Some(0),
+            retention: Some(0),
+            flush_level: Some("loud".to_string()),
+            flush_every: Some("often".to_string()),
+            batch_latency: Some("10ms".to_string()),
+            ring_buffer_level: Some("debug".to_string()),
+            file_mode: Some(0o440),
+            compression: Some("brotli".to_string()),
+            ..LoggerConfig::new("")
+        };
+        let error = config.validate().unwrap_err();
+        let problems: Vec<_> = error
+            .problems()
+            .iter()
+            .map(|problem| (problem.field, problem.position))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                ("service_name", Some(5)),
+                ("filter", Some(39)),
+                ("filter", Some(7)),
+                ("filter", Some(16)),
+                ("filter", Some(27)),
+                ("console_pattern", Some(4)),
+                ("console_pattern", Some(16)),
+                ("console_pattern", Some(18)),
+                ("colors", Some(10)),
+                ("chain_id", Some(4)),
+                ("flush_level", None),
+                ("flush_every", None),
+                ("batch_latency", None),
+                ("ring_buffer_level", None),
+                ("file_mode", None),
+                ("max_size", None),
+                ("retention", None),
+                ("compression", None),
+            ]
+        );
+        let lines: Vec<_> = error.to_string().lines().map(str::to_string).collect();
+        assert_eq!(lines[1], "filter at 39: invalid regex: unclosed group");
+        assert_eq!(lines[2], "filter at 7: invalid level 'loud'");
+        assert_eq!(lines[3], "filter at 16: invalid level 'debug@200%'");
+        assert_eq!(lines[4], "filter at 27: invalid directive 'c=warn=info'");
+        assert_eq!(
+            lines[5],
+            "console_pattern at 4: unknown formatter `q` in '{q}'"
+        );
+        assert_eq!(lines[6], "console_pattern at 16: unexpected ')'");
+        assert_eq!(lines[7], "console_pattern at 18: expected '}' in '{m'");
+    }
+
+    #[test]
+    fn dry_run() {
+        let mut config = LoggerConfig::new("chain");
+        config.filter = Some("debug,cita_network=trace".to_string());
+        let no_env = |_: &str| None;
+        let summary = check_with(&config, no_env).unwrap();
+        assert_eq!(summary.file, None);
+        assert_eq!(summary.appenders, ["stdout"]);
+        assert_eq!(summary.root_level, LevelFilter::Debug);
+        assert_eq!(
+            summary.levels[0],
+            ("cita_network".to_string(), LevelFilter::Trace)
+        );
+
+        // The directives of RUST_LOG are added
+        let summary = check_with(&config, |name| match name {
+            "RUST_LOG" => Some("cita_chain=warn".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert!(summary
+            .levels
+            .contains(&("cita_chain".to_string(), LevelFilter::Warn)));
+
+        config.console_pattern = Some("{q}".to_string());
+        assert_eq!(check_with(&config, no_env).unwrap_err().problems().len(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialized() {
+        #[derive(serde::Deserialize)]
+        struct ServiceConfig {
+            logger: LoggerConfig,
+        }
+
+        let config: ServiceConfig = toml::from_str(
+            r#"
+            [logger]
+            service_name = "chain"
+            favour = "file"
+            filter = "info,cita_network=debug"
+            sync = "always"
+            file_mode = 0o640
+            max_size = 104857600
+            retention = 7
+            layout = "dated"
+            time_format = "rfc3339"
+            flush_interval = "1s"
+            batch_size = 256
+            batch_latency = "20ms"
+            ring_buffer = 1000
+            ring_buffer_level = "trace"
+            stats_interval = "1min"
+            level_names = "warn=WARNING, width=7"
+            disk_min_free = 1073741824
+            disk_action = "raise-level"
+            "#,
+        )
+        .unwrap();
+        let config = config.logger;
+        assert!(config.to_file);
+        assert_eq!(config.filter.as_deref(), Some("info,cita_network=debug"));
+        assert_eq!(config.sync, SyncPolicy::Always);
+        assert_eq!(config.file_mode, Some(0o640));
+        assert_eq!(config.layout, LogLayout::Dated);
+        assert_eq!(config.time_format, TimeFormat::Rfc3339);
+        assert!(config.rotate_on_signal);
+        assert_eq!(config.batch_size, Some(256));
+        assert_eq!(config.ring_buffer, Some(1000));
+        assert_eq!(config.disk_min_free, Some(1 << 30));
+        assert_eq!(config.disk_action, DiskAction::RaiseLevel);
+        assert_eq!(config.validate(), Ok(()));
+
+        let effective = config.builder().effective_config();
+        assert_eq!(effective.flush_interval.as_deref(), Some("1s"));
+        assert_eq!(effective.batch_size, Some(256));
+        assert_eq!(effective.batch_latency.as_deref(), Some("20ms"));
+        assert_eq!(effective.ring_buffer_level.as_deref(), Some("trace"));
+        assert_eq!(effective.stats_interval.as_deref(), Some("1m"));
+        assert_eq!(effective.level_names, config.level_names);
+        assert_eq!(effective.disk_min_free, Some(1 << 30));
+        assert_eq!(effective.disk_action, DiskAction::RaiseLevel);
+
+        assert!(toml::from_str::<LoggerConfig>("favour = \"syslog\"").is_err());
+        assert!(toml::from_str::<LoggerConfig>("max_sise = 100").is_err());
+    }
+
+    #[test]
+    fn unlimited_messages() {
+        let config = |bytes| {
+            Builder::new(&LogFavour::Stdout("chain"))
+                .max_message_len(bytes)
+                .effective_config()
+        };
+        assert_eq!(config(Some(0)), config(None));
+        assert_eq!(config(None).max_message_len, Some(0));
+        for bytes in [None, Some(0), Some(100)] {
+            let config = config(bytes);
+            assert_eq!(config.builder().effective_config(), config);
+        }
+    }
+
+    #[test]
+    fn layered() {
+        let builder = Builder::new(&LogFavour::File("chain"))
+            .directive("", LevelFilter::Warn)
+            .filter("cita_network=debug@1%/block \\d+")
+            .retention(7)
+            .layout(LogLayout::Dated)
+            .flush_interval(Duration::from_millis(1500))
+            .batch(256, Duration::from_millis(50))
+            .ring_buffer(1000, LevelFilter::Trace)
+            .disk_guard(1 << 30, DiskAction::RaiseLevel)
+            .level_names(LevelNames::new().name(Level::Warn, "WARNING"));
+        let config = builder.effective_config();
+        assert_eq!(
+            config.filter.as_deref(),
+            Some("warn,cita_network=debug@0.01/block \\d+")
+        );
+        assert_eq!(config.retention, Some(7));
+        assert_eq!(config.flush_interval.as_deref(), Some("1s 500ms"));
+        assert_eq!(config.batch_latency.as_deref(), Some("50ms"));
+        assert_eq!(config.ring_buffer_level.as_deref(), Some("trace"));
+        assert_eq!(config.disk_action, DiskAction::RaiseLevel);
+        assert_eq!(config.level_names.as_deref(), Some("warn=WARNING"));
+        assert_eq!(config.builder().effective_config(), config);
+
+        #[cfg(feature = "config")]
+        let builder = {
+            let path =
+                std::env::temp_dir().join(format!("cita-logger-{}.toml", std::process::id()));
+            std::fs::write(
+                &path,
+                "[logger]\nservice_name = \"chain\"\nfavour = \"stdout\"\nretention = 3\n",
+            )
+            .unwrap();
+            let builder = builder.config_file(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let config = builder.effective_config();
+            assert!(!config.to_file);
+            assert_eq!(config.retention, Some(3));
+            assert_eq!(config.layout, LogLayout::Dated);
+            builder
+        };
+
+        let config = builder.effective_config_with(|name| match name {
+            "CITA_LOG_DIR" => Some("/var/log/cita".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.log_dir.as_deref(), Some("/var/log/cita"));
+    }
+}
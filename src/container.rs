@@ -0,0 +1,57 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Detection of containers, where services should log to stdout for the
+// collector instead of to files inside the container.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Forces (1) or prevents (0) logging to stdout
+const TO_STDOUT_ENV: &str = "CITA_LOG_TO_STDOUT";
+
+// Whether to log to stdout instead of to a file, `detect` enabling the
+// detection of containers.
+pub(crate) fn prefers_stdout(detect: bool) -> bool {
+    match env::var(TO_STDOUT_ENV).as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        _ => detect && detected(),
+    }
+}
+
+fn detected() -> bool {
+    Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+        || env::var_os("KUBERNETES_SERVICE_HOST").is_some()
+        || fs::read_to_string("/proc/1/cgroup").is_ok_and(|cgroup| in_container(&cgroup))
+}
+
+// Container runtimes name the cgroups of their containers (cgroup v1).
+fn in_container(cgroup: &str) -> bool {
+    cgroup.lines().any(|line| {
+        let path = line.rsplit(':').next().unwrap_or("");
+        ["docker", "kubepods", "containerd", "lxc", "libpod"]
+            .iter()
+            .any(|runtime| path.contains(runtime))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::in_container;
+
+    #[test]
+    fn cgroup() {
+        assert!(in_container(
+            "12:memory:/docker/3f2a9c\n11:cpu:/docker/3f2a9c\n"
+        ));
+        assert!(in_container("1:name=systemd:/kubepods/besteffort/pod1\n"));
+        assert!(!in_container("12:memory:/user.slice\n0::/init.scope\n"));
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// A Unix socket changing the levels of a running service.
+//
+// Each line sent is a command, answered with `ok`, `error: ...` or the
+// requested information:
+//
+// * `set <level>` sets the global level,
+// * `set <module> <level>` sets the level of a module and its descendants,
+// * `reset <module>` removes the directive of a module,
+// * `rotate` rotates the log files as on a SIGUSR1,
+// * `show` describes the configuration.
+//
+// e.g. `echo "set cita_network::sync debug" | nc -U logs/network.sock`
+//
+// Only the user of the service may connect. Each connection is served by
+// a thread of its own, and closed once idle for `IDLE_TIMEOUT`.
+
+use crate::describe;
+use crate::filter;
+use crate::handle;
+use log::LevelFilter;
+use std::fs::{self, Permissions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub(crate) fn spawn(path: &Path) -> io::Result<()> {
+    // Left behind by an earlier run
+    let _ = fs::remove_file(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    thread::Builder::new()
+        .name("cita-logger-control".to_string())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // Dropping the stream closes the connection if no thread
+                // can be spawned for it
+                let _ = thread::Builder::new()
+                    .name("cita-logger-control-connection".to_string())
+                    .spawn(move || serve(stream));
+            }
+        })?;
+    Ok(())
+}
+
+fn serve(stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        writeln!(writer, "{}", execute(&line?))?;
+    }
+    Ok(())
+}
+
+fn execute(command: &str) -> String {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let result = match words.as_slice() {
+        ["set", level] => parse_level(level).map(|level| filter::set_module_level("", level)),
+        ["set", module, level] => {
+            parse_level(level).map(|level| filter::set_module_level(module, level))
+        }
+        ["reset", module] => {
+            filter::reset_module_level(module);
+            Ok(())
+        }
+        ["rotate"] => match handle::request_rotation() {
+            true => Ok(()),
+            false => Err("not logging to a file".to_string()),
+        },
+        ["show"] => {
+            return match describe::describe_config() {
+                Some(summary) => summary.to_string(),
+                None => "error: logger not installed".to_string(),
+            }
+        }
+        _ => Err(format!("unknown command '{}'", command.trim())),
+    };
+    match result {
+        Ok(()) => "ok".to_string(),
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level).map_err(|_| format!("invalid level '{}'", level))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{execute, spawn};
+    use std::env;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn commands() {
+        assert_eq!(execute("set chain debug"), "ok");
+        assert_eq!(execute("reset chain"), "ok");
+        assert_eq!(execute("set chain loud"), "error: invalid level 'loud'");
+        assert_eq!(execute("restart"), "error: unknown command 'restart'");
+    }
+
+    #[test]
+    fn connections_served_together() {
+        let dir = env::temp_dir().join(format!("cita-logger-control-{}", std::process::id()));
+        let path = dir.join("chain.sock");
+        spawn(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // An idle client doesn't hold up the others
+        let _idle = UnixStream::connect(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        writeln!(client, "reset chain").unwrap();
+        let mut answer = String::new();
+        BufReader::new(client).read_line(&mut answer).unwrap();
+        assert_eq!(answer, "ok\n");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
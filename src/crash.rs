@@ -0,0 +1,178 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Crash dumps of the recent records on panics and fatal signals.
+//
+// A panic only brings the process down when panics abort: an unwinding one
+// may be caught by `catch_unwind` or a `join`. Those are dumped to
+// `{service}.panic-{ts}.log` instead, at most once a second, and leave the
+// crash dump to a later crash.
+
+use crate::ring::{self, RecentRecord};
+use chrono::Local;
+#[cfg(unix)]
+use libc::c_int;
+use std::backtrace::Backtrace;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::panic;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+const FATAL_SIGNALS: [c_int; 3] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGABRT];
+// The alternate stack of the signal handler, which can't run on a stack
+// that overflowed. It has room for the backtrace and the dump.
+#[cfg(unix)]
+const SIGNAL_STACK_SIZE: usize = 256 << 10;
+
+// The log directory and the service
+static SERVICE: OnceLock<(String, String)> = OnceLock::new();
+// Only the first crash of a process is dumped.
+static DUMPED: AtomicBool = AtomicBool::new(false);
+// The second of the last dump of an unwinding panic
+static LAST_PANIC: AtomicU64 = AtomicU64::new(0);
+
+// Dumps the ring buffer and a backtrace to `{dir}/{service}.crash-{ts}.log`
+// on panics and fatal signals.
+pub(crate) fn install(dir: &str, service_name: &str) {
+    if SERVICE
+        .set((dir.to_string(), service_name.to_string()))
+        .is_err()
+    {
+        return;
+    }
+
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if cfg!(panic = "abort") {
+            dump_crash(&format!("panic: {}", info));
+        } else {
+            dump_panic(&format!("panic, unwinding so maybe caught: {}", info));
+        }
+        hook(info);
+    }));
+
+    // The handler runs once and restores the default action, so re-raising
+    // the signal terminates the process as it would have without it.
+    #[cfg(unix)]
+    unsafe {
+        signal_stack();
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as *const () as usize;
+        action.sa_flags = libc::SA_RESETHAND | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+        for signal in &FATAL_SIGNALS {
+            libc::sigaction(*signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+// The alternate stack is per thread: the one of the thread installing the
+// handler is replaced if it is too small, the other threads keep the one
+// the standard library gives each thread it spawns.
+#[cfg(unix)]
+unsafe fn signal_stack() {
+    let mut current: libc::stack_t = std::mem::zeroed();
+    if libc::sigaltstack(std::ptr::null(), &mut current) == 0
+        && current.ss_flags & libc::SS_DISABLE == 0
+        && current.ss_size >= SIGNAL_STACK_SIZE
+    {
+        return;
+    }
+    let memory = Box::leak(vec![0u8; SIGNAL_STACK_SIZE].into_boxed_slice());
+    let mut stack: libc::stack_t = std::mem::zeroed();
+    stack.ss_sp = memory.as_mut_ptr().cast();
+    stack.ss_size = SIGNAL_STACK_SIZE;
+    libc::sigaltstack(&stack, std::ptr::null_mut());
+}
+
+// Not async-signal-safe: the process is going down anyway, so writing the
+// dump is worth the risk of it failing.
+#[cfg(unix)]
+extern "C" fn on_signal(signal: c_int) {
+    dump_crash(&format!("fatal signal {}", signal));
+    unsafe {
+        libc::raise(signal);
+    }
+}
+
+fn dump_crash(reason: &str) {
+    if !DUMPED.swap(true, Ordering::SeqCst) {
+        dump("crash", reason);
+    }
+}
+
+fn dump_panic(reason: &str) {
+    let second = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    if LAST_PANIC.swap(second, Ordering::SeqCst) != second {
+        dump("panic", reason);
+    }
+}
+
+fn dump(kind: &str, reason: &str) {
+    let (dir, service_name) = match SERVICE.get() {
+        Some(service) => service,
+        None => return,
+    };
+    let path = PathBuf::from(format!(
+        "{}/{}.{}-{}.log",
+        dir,
+        service_name,
+        kind,
+        Local::now().format("%Y-%m-%d_%H-%M-%S")
+    ));
+    let result = fs::create_dir_all(dir).and_then(|()| {
+        let mut file = File::create(&path)?;
+        write_dump(&mut file, reason, &ring::recent_records())?;
+        file.sync_all()
+    });
+    if let Err(e) = result {
+        eprintln!(
+            "cita-logger: crash dump to {} failed: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn write_dump<W: Write>(w: &mut W, reason: &str, records: &[RecentRecord]) -> io::Result<()> {
+    writeln!(w, "{}", reason)?;
+    writeln!(w, "\nbacktrace:\n{}", Backtrace::force_capture())?;
+    writeln!(w, "recent records:")?;
+    for record in records {
+        writeln!(w, "{}", record)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_dump;
+    use crate::ring::RecentRecord;
+    use log::Level;
+    use std::time::SystemTime;
+
+    #[test]
+    fn dump_layout() {
+        let record = RecentRecord {
+            time: SystemTime::now(),
+            level: Level::Debug,
+            target: "executor".to_string(),
+            message: "executing block 7".to_string(),
+        };
+        let mut out = Vec::new();
+        write_dump(&mut out, "panic: boom", &[record]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("panic: boom\n\nbacktrace:\n"));
+        assert!(out.trim_end().ends_with("DEBUG - executing block 7"));
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Introspection of the installed configuration.
+
+use crate::filter;
+use log::LevelFilter;
+use parking_lot::{const_rwlock, RwLock};
+use std::fmt;
+
+/// What the logger is set to, see `describe_config()`.
+#[derive(Debug, Clone)]
+pub struct ConfigSummary {
+    pub service_name: String,
+    /// The log file, `None` when logging to the console.
+    pub file: Option<String>,
+    /// The names of the log4rs appenders.
+    pub appenders: Vec<String>,
+    /// The pattern of the log file, or a description of its encoder.
+    pub file_pattern: Option<String>,
+    /// The pattern of the console, or a description of its encoder.
+    pub console_pattern: String,
+    /// The level of targets without a directive.
+    pub root_level: LevelFilter,
+    /// The level of each directive, least specific first.
+    pub levels: Vec<(String, LevelFilter)>,
+    pub rotation: String,
+}
+
+impl fmt::Display for ConfigSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "service {}, ", self.service_name)?;
+        match &self.file {
+            Some(file) => write!(f, "file {}", file)?,
+            None => write!(f, "console")?,
+        }
+        write!(f, ", appenders [{}]", self.appenders.join(", "))?;
+        if let Some(pattern) = &self.file_pattern {
+            write!(f, ", file pattern \"{}\"", pattern.trim_end_matches("{n}"))?;
+        }
+        write!(
+            f,
+            ", console pattern \"{}\", level {}",
+            self.console_pattern.trim_end_matches("{n}"),
+            self.root_level
+        )?;
+        for (name, level) in &self.levels {
+            write!(f, ", {}={}", name, level)?;
+        }
+        write!(f, ", rotation {}", self.rotation)
+    }
+}
+
+static SUMMARY: RwLock<Option<ConfigSummary>> = const_rwlock(None);
+
+pub(crate) fn install(summary: ConfigSummary) {
+    *SUMMARY.write() = Some(summary);
+}
+
+/// Describes the installed configuration, `None` before the logger is
+/// installed.
+///
+/// The summary is logged once at startup as well.
+pub fn describe_config() -> Option<ConfigSummary> {
+    let mut summary = SUMMARY.read().clone()?;
+    let (root_level, levels) = filter::levels();
+    summary.root_level = root_level;
+    summary.levels = levels;
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigSummary;
+    use log::LevelFilter;
+
+    #[test]
+    fn display() {
+        let summary = ConfigSummary {
+            service_name: "chain".to_string(),
+            file: Some("logs/chain.log".to_string()),
+            appenders: vec!["requests".to_string()],
+            file_pattern: Some("{m}{n}".to_string()),
+            console_pattern: "{l} - {m}{n}".to_string(),
+            root_level: LevelFilter::Info,
+            levels: vec![("network".to_string(), LevelFilter::Debug)],
+            rotation: "on SIGUSR1".to_string(),
+        };
+        assert_eq!(
+            summary.to_string(),
+            "service chain, file logs/chain.log, appenders [requests], \
+             file pattern \"{m}\", console pattern \"{l} - {m}\", level INFO, \
+             network=DEBUG, rotation on SIGUSR1"
+        );
+    }
+}
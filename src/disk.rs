@@ -0,0 +1,212 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Guard against the log volume running full.
+
+use crate::error::{self, InternalErrorKind};
+use crate::index;
+use crate::rotate;
+use crate::{info, warn};
+use log::LevelFilter;
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What to do when the free space of the log volume drops below the
+/// threshold given to `Builder::disk_guard`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum DiskAction {
+    /// Delete the oldest rotated log files. If that is not enough, raise the
+    /// log level to warn as well.
+    Prune,
+    /// Raise the log level to warn until space is available again.
+    RaiseLevel,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskGuard {
+    pub min_free: u64,
+    pub action: DiskAction,
+}
+
+impl DiskGuard {
+    pub fn spawn(self, dir: PathBuf, service_name: String) -> io::Result<()> {
+        thread::Builder::new()
+            .name("cita-logger-disk".to_string())
+            .spawn(move || self.run(&dir, &service_name))?;
+        Ok(())
+    }
+
+    fn run(self, dir: &Path, service_name: &str) {
+        let mut low = false;
+        // The level to restore once space is available again.
+        let mut raised_from: Option<LevelFilter> = None;
+        loop {
+            if let Ok(mut available) = free_space(dir) {
+                match self.round(available, low) {
+                    Round::Idle => {}
+                    Round::Low { first, prune } => {
+                        if first {
+                            warn!(
+                                "log volume nearly full: {} bytes free, below {} bytes",
+                                available, self.min_free
+                            );
+                            low = true;
+                        }
+                        if prune {
+                            available = self.prune(dir, service_name, available);
+                        }
+                        // A reconfiguration (e.g. a log rotation) resets the
+                        // max level, so it is checked on every round.
+                        if self.raises_level(available, log::max_level()) {
+                            raised_from = Some(log::max_level());
+                            log::set_max_level(LevelFilter::Warn);
+                        }
+                    }
+                    Round::Recovered => {
+                        if let Some(level) = raised_from.take() {
+                            log::set_max_level(level);
+                        }
+                        info!("log volume has {} bytes free again", available);
+                        low = false;
+                    }
+                }
+            }
+            thread::sleep(CHECK_INTERVAL);
+        }
+    }
+
+    // What a round does with `available` bytes free, `low` after a round
+    // short of space.
+    fn round(&self, available: u64, low: bool) -> Round {
+        match (available < self.min_free, low) {
+            (true, _) => Round::Low {
+                first: !low,
+                prune: self.action == DiskAction::Prune,
+            },
+            (false, true) => Round::Recovered,
+            (false, false) => Round::Idle,
+        }
+    }
+
+    // Whether the level goes up to warn with `available` bytes free, after any
+    // pruning.
+    fn raises_level(&self, available: u64, level: LevelFilter) -> bool {
+        available < self.min_free && level > LevelFilter::Warn
+    }
+
+    // Deletes the oldest rotated files until enough space is free.
+    fn prune(&self, dir: &Path, service_name: &str, mut available: u64) -> u64 {
+        let files = match rotate::rotated_files(dir, service_name) {
+            Ok(files) => files,
+            Err(_) => return available,
+        };
+        for file in files {
+            if available >= self.min_free {
+                break;
+            }
+            match fs::remove_file(&file) {
+                Ok(()) => {
+                    index::remove(&file);
+                    warn!("log volume nearly full, deleted {}", file.display())
+                }
+                Err(e) => {
+                    warn!("delete {} failed because of {:?}", file.display(), e.kind());
+                    error::report(InternalErrorKind::Disk, &e);
+                }
+            }
+            available = free_space(dir).unwrap_or(available);
+        }
+        available
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Round {
+    // Enough space, as before
+    Idle,
+    // Short of space: warn the first time, and prune with `DiskAction::Prune`
+    Low { first: bool, prune: bool },
+    // Enough space again, restoring the level
+    Recovered,
+}
+
+// Bytes available to unprivileged users on the volume of `path`.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space needs Unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{free_space, DiskAction, DiskGuard, Round};
+    use log::LevelFilter;
+
+    #[test]
+    fn thresholds() {
+        let guard = DiskGuard {
+            min_free: 100,
+            action: DiskAction::Prune,
+        };
+        assert_eq!(guard.round(100, false), Round::Idle);
+        let low = Round::Low {
+            first: true,
+            prune: true,
+        };
+        assert_eq!(guard.round(99, false), low);
+        let still_low = Round::Low {
+            first: false,
+            prune: true,
+        };
+        assert_eq!(guard.round(0, true), still_low);
+        assert_eq!(guard.round(150, true), Round::Recovered);
+        assert!(guard.raises_level(99, LevelFilter::Info));
+        assert!(!guard.raises_level(99, LevelFilter::Warn));
+        assert!(!guard.raises_level(100, LevelFilter::Trace));
+
+        let guard = DiskGuard {
+            action: DiskAction::RaiseLevel,
+            ..guard
+        };
+        let low = Round::Low {
+            first: true,
+            prune: false,
+        };
+        assert_eq!(guard.round(99, false), low);
+
+        assert!(free_space(&std::env::temp_dir()).unwrap() > 0);
+    }
+}
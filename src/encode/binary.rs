@@ -0,0 +1,274 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! A compact binary log format, and a reader turning it back into records.
+//!
+//! A file is a sequence of frames, each a little-endian `u32` length
+//! followed by that many bytes:
+//!
+//! * `0u8, id: u16, target: [u8]` defines a target id,
+//! * `1u8, micros: u64, level: u8, target id: u16, message: [u8]` is a
+//!   record, timed in microseconds since the Unix epoch.
+//!
+//! Target ids are defined before their first use, and may be redefined
+//! later (e.g. by another thread, or when a restarted process appends to
+//! the file).
+
+use super::{buffer, Encoder};
+use chrono::{DateTime, Local};
+use log::{Level, Record};
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Read};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thread_local::ThreadLocal;
+
+const TARGET: u8 = 0;
+const RECORD: u8 = 1;
+
+/// Writes records in the binary format.
+#[derive(Debug, Default)]
+pub struct BinaryEncoder {
+    targets: Mutex<Targets>,
+    // The targets each thread defined in its output, e.g. the log file, as
+    // a writer thread may write the records of a thread before the
+    // definitions of another
+    defined: ThreadLocal<RefCell<Defined>>,
+}
+
+#[derive(Debug, Default)]
+struct Targets {
+    ids: HashMap<String, u16>,
+    // Bumped when the ids are reused
+    generation: u64,
+}
+
+#[derive(Debug, Default)]
+struct Defined {
+    output: u64,
+    generation: u64,
+    ids: HashSet<u16>,
+}
+
+impl BinaryEncoder {
+    pub fn new() -> BinaryEncoder {
+        BinaryEncoder::default()
+    }
+
+    // Returns the id of `target`, defining it first if needed.
+    fn target_id(&self, buf: &mut Vec<u8>, target: &str) -> u16 {
+        let (id, generation) = {
+            let mut targets = self.targets.lock();
+            match targets.ids.get(target) {
+                Some(id) => (*id, targets.generation),
+                None => {
+                    if targets.ids.len() > u16::MAX as usize {
+                        targets.ids.clear();
+                        targets.generation += 1;
+                    }
+                    let id = targets.ids.len() as u16;
+                    targets.ids.insert(target.to_string(), id);
+                    (id, targets.generation)
+                }
+            }
+        };
+
+        let mut defined = self.defined.get_or_default().borrow_mut();
+        let output = super::output();
+        if defined.output != output || defined.generation != generation {
+            defined.ids.clear();
+            defined.output = output;
+            defined.generation = generation;
+        }
+        if defined.ids.insert(id) {
+            let target = target.as_bytes();
+            buf.extend_from_slice(&(3 + target.len() as u32).to_le_bytes());
+            buf.push(TARGET);
+            buf.extend_from_slice(&id.to_le_bytes());
+            buf.extend_from_slice(target);
+        }
+        id
+    }
+}
+
+impl Encoder for BinaryEncoder {
+    fn encode(&self, buf: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+        let id = self.target_id(buf, record.target());
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        buffer::with_message(record, |message| {
+            buf.extend_from_slice(&(12 + message.len() as u32).to_le_bytes());
+            buf.push(RECORD);
+            buf.extend_from_slice(&micros.to_le_bytes());
+            buf.push(record.level() as u8);
+            buf.extend_from_slice(&id.to_le_bytes());
+            buf.extend_from_slice(message.as_bytes());
+        });
+        Ok(())
+    }
+}
+
+/// A record read back from the binary format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryRecord {
+    pub time: SystemTime,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for BinaryRecord {
+    // Same layout as the text log file
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time: DateTime<Local> = self.time.into();
+        write!(
+            f,
+            "{} | {:20.20} | {:5} - {}",
+            time.format("%Y-%m-%d - %H:%M:%S"),
+            self.target,
+            self.level,
+            self.message
+        )
+    }
+}
+
+/// Iterates over the records of a binary log.
+#[derive(Debug)]
+pub struct BinaryReader<R> {
+    reader: R,
+    targets: HashMap<u16, String>,
+}
+
+impl<R: Read> BinaryReader<R> {
+    pub fn new(reader: R) -> BinaryReader<R> {
+        BinaryReader {
+            reader,
+            targets: HashMap::new(),
+        }
+    }
+
+    // Reads the next frame, `None` at the end of the input.
+    fn frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut frame = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut frame)?;
+        Ok(Some(frame))
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<BinaryRecord>> {
+        while let Some(frame) = self.frame()? {
+            match frame.first() {
+                Some(&TARGET) if frame.len() >= 3 => {
+                    let id = u16::from_le_bytes([frame[1], frame[2]]);
+                    let target = String::from_utf8_lossy(&frame[3..]).into_owned();
+                    self.targets.insert(id, target);
+                }
+                Some(&RECORD) if frame.len() >= 12 => {
+                    let mut micros = [0u8; 8];
+                    micros.copy_from_slice(&frame[1..9]);
+                    let level = level(frame[9])?;
+                    let id = u16::from_le_bytes([frame[10], frame[11]]);
+                    let target = self
+                        .targets
+                        .get(&id)
+                        .cloned()
+                        .ok_or_else(|| invalid(format!("undefined target id {}", id)))?;
+                    return Ok(Some(BinaryRecord {
+                        time: UNIX_EPOCH + Duration::from_micros(u64::from_le_bytes(micros)),
+                        level,
+                        target,
+                        message: String::from_utf8_lossy(&frame[12..]).into_owned(),
+                    }));
+                }
+                _ => return Err(invalid("malformed frame".to_string())),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = io::Result<BinaryRecord>;
+
+    fn next(&mut self) -> Option<io::Result<BinaryRecord>> {
+        self.next_record().transpose()
+    }
+}
+
+fn level(level: u8) -> io::Result<Level> {
+    Ok(match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        5 => Level::Trace,
+        _ => return Err(invalid(format!("invalid level {}", level))),
+    })
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinaryEncoder, BinaryReader};
+    use crate::encode::Encoder;
+    use log::{Level, Record};
+
+    #[test]
+    fn round_trip() {
+        let encoder = BinaryEncoder::new();
+        let mut buf = Vec::new();
+        for (level, target, message) in &[
+            (Level::Info, "chain", "new block"),
+            (Level::Warn, "network", "peer lost"),
+            (Level::Info, "chain", "new block again"),
+        ] {
+            encoder
+                .encode(
+                    &mut buf,
+                    &Record::builder()
+                        .level(*level)
+                        .target(target)
+                        .args(format_args!("{}", message))
+                        .build(),
+                )
+                .unwrap();
+        }
+
+        let records: Vec<_> = BinaryReader::new(&buf[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].level, Level::Warn);
+        assert_eq!(records[1].target, "network");
+        assert_eq!(records[2].target, "chain");
+        assert_eq!(records[2].message, "new block again");
+    }
+
+    #[test]
+    fn truncated_input() {
+        let encoder = BinaryEncoder::new();
+        let mut buf = Vec::new();
+        let record = Record::builder().args(format_args!("cut")).build();
+        encoder.encode(&mut buf, &record).unwrap();
+        buf.pop();
+
+        let mut reader = BinaryReader::new(&buf[..]);
+        assert!(reader.next().unwrap().is_err());
+    }
+}
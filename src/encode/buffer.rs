@@ -0,0 +1,123 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Formatting buffers reused across the records of a thread.
+//
+// Each thread keeps a few buffers, enough for an encoder wrapping another;
+// a record formatted into a buffer grown above `MAX_CAPACITY` frees it.
+
+use log::Record;
+use std::cell::RefCell;
+use std::fmt::Write;
+
+// Buffers kept per thread
+const MAX_POOLED: usize = 4;
+
+// Bytes above which a buffer is not kept, so that a huge record does not
+// hold its memory
+pub(crate) const MAX_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    static STRINGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    static BYTES: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+// Calls `f` with an empty string, reused by the next calls of the thread.
+pub(crate) fn with_string<R>(f: impl FnOnce(&mut String) -> R) -> R {
+    let mut buf = STRINGS
+        .try_with(|pool| pool.borrow_mut().pop())
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let result = f(&mut buf);
+    if buf.capacity() <= MAX_CAPACITY {
+        buf.clear();
+        let _ = STRINGS.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < MAX_POOLED {
+                pool.push(buf);
+            }
+        });
+    }
+    result
+}
+
+// Calls `f` with an empty byte buffer, reused by the next calls of the
+// thread.
+pub(crate) fn with_bytes<R>(f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+    let mut buf = BYTES
+        .try_with(|pool| pool.borrow_mut().pop())
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let result = f(&mut buf);
+    if buf.capacity() <= MAX_CAPACITY {
+        buf.clear();
+        let _ = BYTES.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < MAX_POOLED {
+                pool.push(buf);
+            }
+        });
+    }
+    result
+}
+
+// Calls `f` with the message of `record`, formatted into a reused buffer
+// unless it is a plain string.
+pub(crate) fn with_message<R>(record: &Record, f: impl FnOnce(&str) -> R) -> R {
+    if let Some(message) = record.args().as_str() {
+        return f(message);
+    }
+    with_string(|buf| {
+        let _ = write!(buf, "{}", record.args());
+        f(buf)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{with_message, with_string, MAX_CAPACITY};
+    use log::Record;
+
+    #[test]
+    fn reused() {
+        let first = with_string(|buf| {
+            buf.push_str("block 1");
+            buf.as_ptr()
+        });
+        // Taken again empty, with its memory
+        let again = with_string(|buf| {
+            assert!(buf.is_empty());
+            buf.as_ptr()
+        });
+        assert_eq!(first, again);
+
+        // Nested calls get buffers of their own
+        with_string(|outer| {
+            outer.push_str("outer");
+            with_string(|inner| assert!(inner.is_empty()));
+            assert_eq!(outer, "outer");
+        });
+
+        // Huge buffers are dropped
+        let huge = with_string(|buf| {
+            buf.reserve(MAX_CAPACITY + 1);
+            buf.as_ptr()
+        });
+        assert_ne!(with_string(|buf| buf.as_ptr()), huge);
+
+        let height = 7;
+        let message = with_message(
+            &Record::builder()
+                .args(format_args!("new block {}", height))
+                .build(),
+            str::to_string,
+        );
+        assert_eq!(message, "new block 7");
+    }
+}
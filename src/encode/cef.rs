@@ -0,0 +1,198 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::{buffer, Encoder};
+use chrono::Utc;
+use log::{Level, Record};
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+/// Writes records in ArcSight's Common Event Format, for SIEM ingestion.
+///
+/// The target becomes the signature id, the message the name, and MDC
+/// entries are added as extensions.
+#[derive(Debug, Clone)]
+pub struct CefEncoder {
+    vendor: String,
+    product: String,
+    version: String,
+}
+
+impl CefEncoder {
+    pub fn new(vendor: &str, product: &str, version: &str) -> CefEncoder {
+        CefEncoder {
+            vendor: vendor.to_string(),
+            product: product.to_string(),
+            version: version.to_string(),
+        }
+    }
+}
+
+impl Encoder for CefEncoder {
+    fn encode(&self, buf: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+        buffer::with_message(record, |message| {
+            write!(
+                buf,
+                "CEF:0|{}|{}|{}|{}|{}|{}|",
+                escape_header(&self.vendor),
+                escape_header(&self.product),
+                escape_header(&self.version),
+                escape_header(record.target()),
+                escape_header(message),
+                severity(record.level()),
+            )?;
+            write!(
+                buf,
+                "rt={} cs1Label=level cs1={} msg={}",
+                Utc::now().timestamp_millis(),
+                record.level(),
+                escape_extension(message)
+            )
+        })?;
+        if let Some(file) = record.file() {
+            write!(buf, " fname={}", escape_extension(file))?;
+        }
+        let mut result = Ok(());
+        log_mdc::iter(|key, value| {
+            if result.is_ok() {
+                result = write!(buf, " {}={}", extension_key(key), escape_extension(value));
+            }
+        });
+        result?;
+        buf.push(b'\n');
+        Ok(())
+    }
+}
+
+/// Writes records in IBM QRadar's Log Event Extended Format (LEEF 1.0).
+#[derive(Debug, Clone)]
+pub struct LeefEncoder {
+    vendor: String,
+    product: String,
+    version: String,
+}
+
+impl LeefEncoder {
+    pub fn new(vendor: &str, product: &str, version: &str) -> LeefEncoder {
+        LeefEncoder {
+            vendor: vendor.to_string(),
+            product: product.to_string(),
+            version: version.to_string(),
+        }
+    }
+}
+
+impl Encoder for LeefEncoder {
+    fn encode(&self, buf: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+        write!(
+            buf,
+            "LEEF:1.0|{}|{}|{}|{}|",
+            escape_header(&self.vendor),
+            escape_header(&self.product),
+            escape_header(&self.version),
+            escape_header(record.target()),
+        )?;
+        buffer::with_message(record, |message| {
+            write!(
+                buf,
+                "devTime={}\tsev={}\tlevel={}\tmsg={}",
+                Utc::now().timestamp_millis(),
+                severity(record.level()),
+                record.level(),
+                escape_leef(message)
+            )
+        })?;
+        let mut result = Ok(());
+        log_mdc::iter(|key, value| {
+            if result.is_ok() {
+                result = write!(buf, "\t{}={}", extension_key(key), escape_leef(value));
+            }
+        });
+        result?;
+        buf.push(b'\n');
+        Ok(())
+    }
+}
+
+// CEF severity, 0 (lowest) to 10.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 8,
+        Level::Warn => 5,
+        Level::Info => 3,
+        Level::Debug => 1,
+        Level::Trace => 0,
+    }
+}
+
+// The escape functions borrow the values which need no escaping.
+fn escape_header(field: &str) -> Cow<'_, str> {
+    if !field.contains(['\\', '|', '\r', '\n']) {
+        return Cow::Borrowed(field);
+    }
+    field
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace(['\r', '\n'], " ")
+        .into()
+}
+
+fn escape_extension(value: &str) -> Cow<'_, str> {
+    if !value.contains(['\\', '=', '\r', '\n']) {
+        return Cow::Borrowed(value);
+    }
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+        .into()
+}
+
+fn escape_leef(value: &str) -> Cow<'_, str> {
+    if !value.contains(['\t', '\r', '\n']) {
+        return Cow::Borrowed(value);
+    }
+    value.replace(['\t', '\r', '\n'], " ").into()
+}
+
+// Extension keys may only hold alphanumeric characters.
+fn extension_key(key: &str) -> String {
+    key.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_extension, escape_header, CefEncoder};
+    use crate::encode::Encoder;
+    use log::{Level, Record};
+
+    #[test]
+    fn escape() {
+        assert_eq!(escape_header("a|b\\c"), "a\\|b\\\\c");
+        assert_eq!(escape_extension("k=v\nnext"), "k\\=v\\nnext");
+    }
+
+    #[test]
+    fn encode_record() {
+        log_mdc::insert("peer", "10.0.0.1");
+        let mut buf = Vec::new();
+        let record = Record::builder()
+            .level(Level::Error)
+            .target("network")
+            .args(format_args!("bad handshake"))
+            .build();
+        CefEncoder::new("Cryptape", "CITA", "1.0")
+            .encode(&mut buf, &record)
+            .unwrap();
+        log_mdc::remove("peer");
+
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.starts_with("CEF:0|Cryptape|CITA|1.0|network|bad handshake|8|rt="));
+        assert!(line.ends_with(" msg=bad handshake peer=10.0.0.1\n"));
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! Colors of the console records, per level and for the time and the
+//! target.
+
+use super::field_end;
+use super::level::LevelNames;
+use log::{Level, Record};
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::{self, Color, Encode, Style};
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+/// Colors of the console patterns, see `Builder::color_theme`, for the
+/// terminals on which the defaults are unreadable.
+///
+/// The level colors replace those of the `{h(...)}` fields and color the
+/// `{l}` fields, the time and target colors those of the `{d}` and `{t}`
+/// fields. As with `{h(...)}`, colors only go to terminals, and `NO_COLOR`
+/// turns them off.
+///
+/// A theme is also read from a string of `key=style` pairs, e.g. a setting
+/// of the service's config file or `CITA_LOG_COLORS`: the keys are the
+/// levels, `time` and `target`, the styles a color (black, red, green,
+/// yellow, blue, magenta, cyan or white), optionally `bright` and `on` a
+/// background color, or `none`.
+///
+/// ```
+/// use cita_logger::encode::ColorTheme;
+///
+/// let theme: ColorTheme = "warn=bright yellow, debug=blue, time=black on white"
+///     .parse()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorTheme {
+    // Indexed by level, from error to trace
+    levels: [Style; 5],
+    time: Option<Style>,
+    target: Option<Style>,
+}
+
+impl ColorTheme {
+    /// The colors of `{h(...)}`: bright red errors, yellow warnings, green
+    /// infos, cyan traces, and the others uncolored.
+    pub fn new() -> ColorTheme {
+        let mut error = Style::new();
+        error.text(Color::Red).intense(true);
+        let color = |color| {
+            let mut style = Style::new();
+            style.text(color);
+            style
+        };
+        ColorTheme {
+            levels: [
+                error,
+                color(Color::Yellow),
+                color(Color::Green),
+                Style::new(),
+                color(Color::Cyan),
+            ],
+            time: None,
+            target: None,
+        }
+    }
+
+    /// Colors the records of `level` with `style`.
+    pub fn level(mut self, level: Level, style: Style) -> ColorTheme {
+        self.levels[level as usize - 1] = style;
+        self
+    }
+
+    /// Colors the time with `style`.
+    pub fn time(mut self, style: Style) -> ColorTheme {
+        self.time = Some(style);
+        self
+    }
+
+    /// Colors the target with `style`.
+    pub fn target(mut self, style: Style) -> ColorTheme {
+        self.target = Some(style);
+        self
+    }
+
+    /// The theme of `CITA_LOG_COLORS`, if it is set and valid.
+    pub fn from_env() -> Option<ColorTheme> {
+        env::var("CITA_LOG_COLORS").ok()?.parse().ok()
+    }
+
+    fn style(&self, part: Part, level: Level) -> Option<&Style> {
+        match part {
+            Part::Plain => None,
+            Part::Level => Some(&self.levels[level as usize - 1]),
+            Part::Time => self.time.as_ref(),
+            Part::Target => self.target.as_ref(),
+        }
+    }
+}
+
+impl Default for ColorTheme {
+    fn default() -> ColorTheme {
+        ColorTheme::new()
+    }
+}
+
+// In the syntax of `FromStr`.
+impl fmt::Display for ColorTheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for level in Level::iter() {
+            if level != Level::Error {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{}={}",
+                level.as_str().to_ascii_lowercase(),
+                StyleName(&self.levels[level as usize - 1])
+            )?;
+        }
+        if let Some(style) = &self.time {
+            write!(f, ", time={}", StyleName(style))?;
+        }
+        if let Some(style) = &self.target {
+            write!(f, ", target={}", StyleName(style))?;
+        }
+        Ok(())
+    }
+}
+
+struct StyleName<'a>(&'a Style);
+
+impl fmt::Display for StyleName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut words = Vec::new();
+        if self.0.intense == Some(true) {
+            words.push("bright");
+        }
+        if let Some(color) = self.0.text {
+            words.push(color_name(color));
+        }
+        if let Some(color) = self.0.background {
+            words.push("on");
+            words.push(color_name(color));
+        }
+        match words.is_empty() {
+            true => write!(f, "none"),
+            false => write!(f, "{}", words.join(" ")),
+        }
+    }
+}
+
+impl FromStr for ColorTheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorTheme, String> {
+        let mut theme = ColorTheme::new();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, style) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid color '{}'", pair))?;
+            let style = parse_style(style)?;
+            theme = match key.trim().to_ascii_lowercase().as_str() {
+                "time" => theme.time(style),
+                "target" => theme.target(style),
+                level => match Level::from_str(level) {
+                    Ok(level) => theme.level(level, style),
+                    Err(_) => return Err(format!("unknown color key '{}'", key.trim())),
+                },
+            };
+        }
+        Ok(theme)
+    }
+}
+
+// `[bright] color [on color]`, or `none`.
+fn parse_style(s: &str) -> Result<Style, String> {
+    let mut style = Style::new();
+    let mut background = false;
+    for word in s.split_whitespace().map(str::to_ascii_lowercase) {
+        match word.as_str() {
+            "none" => {}
+            "bright" => {
+                style.intense(true);
+            }
+            "on" => background = true,
+            color => {
+                let color =
+                    parse_color(color).ok_or_else(|| format!("unknown color '{}'", color))?;
+                if background {
+                    style.background(color);
+                } else {
+                    style.text(color);
+                }
+            }
+        }
+    }
+    Ok(style)
+}
+
+fn parse_color(color: &str) -> Option<Color> {
+    Some(match color {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "white" => Color::White,
+        _ => return None,
+    })
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::Black => "black",
+        Color::Red => "red",
+        Color::Green => "green",
+        Color::Yellow => "yellow",
+        Color::Blue => "blue",
+        Color::Magenta => "magenta",
+        Color::Cyan => "cyan",
+        Color::White => "white",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Plain,
+    Level,
+    Time,
+    Target,
+}
+
+// A pattern split into the parts colored by a theme, per level.
+#[derive(Debug)]
+pub(crate) struct Themed(Vec<Vec<(Option<Style>, PatternEncoder)>>);
+
+impl Themed {
+    pub(crate) fn new(pattern: &str, theme: &ColorTheme, names: Option<&LevelNames>) -> Themed {
+        let parts = split(pattern);
+        Themed(
+            Level::iter()
+                .map(|level| {
+                    parts
+                        .iter()
+                        .map(|(part, pattern)| {
+                            let pattern = match names {
+                                Some(names) => names.pattern(pattern, level),
+                                None => pattern.clone(),
+                            };
+                            (
+                                theme.style(*part, level).cloned(),
+                                PatternEncoder::new(&pattern),
+                            )
+                        })
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Encode for Themed {
+    fn encode(&self, w: &mut dyn encode::Write, record: &Record) -> anyhow::Result<()> {
+        for (style, encoder) in &self.0[record.level() as usize - 1] {
+            match style {
+                Some(style) => {
+                    w.set_style(style)?;
+                    encoder.encode(w, record)?;
+                    w.set_style(&Style::new())?;
+                }
+                None => encoder.encode(w, record)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// The top-level fields of `pattern` colored by a theme, and the text
+// between them. `{h(...)}` loses its own colors.
+fn split(pattern: &str) -> Vec<(Part, String)> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut parts = Vec::new();
+    let mut plain = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let escaped = matches!(chars[i], '{' | '}') && chars.get(i + 1) == Some(&chars[i]);
+        if escaped || chars[i] != '{' {
+            let len = if escaped { 2 } else { 1 };
+            plain.extend(&chars[i..i + len]);
+            i += len;
+            continue;
+        }
+
+        let end = field_end(&chars, i);
+        let field: String = chars[i..end].iter().collect();
+        let name: String = chars[i + 1..end]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        let (part, field) = match name.as_str() {
+            "h" | "highlight" => {
+                let inner = field
+                    .strip_prefix(&format!("{{{}(", name))
+                    .and_then(|inner| inner.strip_suffix(")}"));
+                (Part::Level, inner.map_or(field.clone(), str::to_string))
+            }
+            "l" | "level" => (Part::Level, field),
+            "d" | "date" => (Part::Time, field),
+            "t" | "target" => (Part::Target, field),
+            _ => (Part::Plain, field),
+        };
+        if part == Part::Plain {
+            plain.push_str(&field);
+        } else {
+            if !plain.is_empty() {
+                parts.push((Part::Plain, std::mem::take(&mut plain)));
+            }
+            parts.push((part, field));
+        }
+        i = end;
+    }
+    if !plain.is_empty() {
+        parts.push((Part::Plain, plain));
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split, ColorTheme, Part, Themed};
+    use log::{Level, Record};
+    use log4rs::encode::writer::ansi::AnsiWriter;
+    use log4rs::encode::Encode;
+
+    #[test]
+    fn themed_parts() {
+        assert_eq!(
+            split("{h({l:5})} {{x}} {d(%H:%M)} {t} - {m}{n}"),
+            [
+                (Part::Level, "{l:5}".to_string()),
+                (Part::Plain, " {{x}} ".to_string()),
+                (Part::Time, "{d(%H:%M)}".to_string()),
+                (Part::Plain, " ".to_string()),
+                (Part::Target, "{t}".to_string()),
+                (Part::Plain, " - {m}{n}".to_string()),
+            ]
+        );
+
+        let theme: ColorTheme = "warn=bright blue on white, time=cyan".parse().unwrap();
+        let encoder = Themed::new("{h({l})} {t} {m}", &theme, None);
+        let mut out = Vec::new();
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("chain")
+            .args(format_args!("block"))
+            .build();
+        encoder.encode(&mut AnsiWriter(&mut out), &record).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[0;34;47;1mWARN\x1b[0m chain block"
+        );
+
+        assert_eq!(theme.to_string().parse(), Ok(theme));
+        assert_eq!(
+            ColorTheme::new().to_string(),
+            "error=bright red, warn=yellow, info=green, debug=none, trace=cyan"
+        );
+        assert!("warn=purple".parse::<ColorTheme>().is_err());
+        assert!("loud=red".parse::<ColorTheme>().is_err());
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! At-rest encryption of the log file, and a reader decrypting it.
+//!
+//! Every record is sealed on its own with AES-256-GCM, so a truncated file
+//! loses at most its last record. The file is a sequence of frames, each a
+//! little-endian `u32` length followed by a 12 byte nonce and the
+//! ciphertext.
+//!
+//! Requires the `encryption` feature.
+
+use super::buffer;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use log::Record;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::Encode;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+const NONCE_LEN: usize = 12;
+
+/// A 256 bit key for the log file encryption.
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> EncryptionKey {
+        EncryptionKey(key)
+    }
+
+    /// Parses a key of 64 hex digits.
+    pub fn from_hex(hex: &str) -> io::Result<EncryptionKey> {
+        let hex = hex.trim().as_bytes();
+        if hex.len() != 64 {
+            return Err(invalid("key must be 64 hex digits"));
+        }
+        let mut key = [0u8; 32];
+        for (byte, pair) in key.iter_mut().zip(hex.chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid("invalid hex digit"))?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid("invalid hex digit"))?;
+        }
+        Ok(EncryptionKey(key))
+    }
+
+    /// Reads a hex key from the environment variable `var`.
+    pub fn from_env(var: &str) -> io::Result<EncryptionKey> {
+        let hex = env::var(var).map_err(|e| invalid(&format!("{}: {}", var, e)))?;
+        EncryptionKey::from_hex(&hex)
+    }
+
+    /// Reads a key from a file, either as 32 raw bytes or as hex.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<EncryptionKey> {
+        let bytes = fs::read(path)?;
+        if bytes.len() == 32 {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&bytes);
+            return Ok(EncryptionKey(key));
+        }
+        let hex = String::from_utf8(bytes).map_err(|_| invalid("key must be 64 hex digits"))?;
+        EncryptionKey::from_hex(&hex)
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0))
+    }
+}
+
+// Encrypts the output of another encoder.
+pub(crate) struct Encrypt {
+    inner: Box<dyn Encode>,
+    cipher: Aes256Gcm,
+}
+
+impl fmt::Debug for Encrypt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Encrypt")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl Encrypt {
+    pub(crate) fn new(inner: Box<dyn Encode>, key: &EncryptionKey) -> Encrypt {
+        Encrypt {
+            inner,
+            cipher: key.cipher(),
+        }
+    }
+}
+
+impl Encode for Encrypt {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &Record) -> anyhow::Result<()> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = buffer::with_bytes(|plain| {
+            self.inner.encode(&mut SimpleWriter(&mut *plain), record)?;
+            self.cipher
+                .encrypt(&nonce, plain.as_slice())
+                .map_err(|_| anyhow::anyhow!("encryption failed"))
+        })?;
+        w.write_all(&((NONCE_LEN + sealed.len()) as u32).to_le_bytes())?;
+        w.write_all(&nonce)?;
+        w.write_all(&sealed)?;
+        Ok(())
+    }
+}
+
+/// Reads the plaintext of an encrypted log.
+///
+/// Fails with `InvalidData` if a record was tampered with or the key is
+/// wrong.
+pub struct DecryptReader<R> {
+    reader: R,
+    cipher: Aes256Gcm,
+    plain: Vec<u8>,
+    pos: usize,
+}
+
+impl<R> fmt::Debug for DecryptReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DecryptReader").finish()
+    }
+}
+
+impl<R: Read> DecryptReader<R> {
+    pub fn new(reader: R, key: &EncryptionKey) -> DecryptReader<R> {
+        DecryptReader {
+            reader,
+            cipher: key.cipher(),
+            plain: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    // Decrypts the next record, returns false at the end of the input.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len < NONCE_LEN {
+            return Err(invalid("malformed frame"));
+        }
+        let mut frame = vec![0u8; len];
+        self.reader.read_exact(&mut frame)?;
+
+        let (nonce, sealed) = frame.split_at(NONCE_LEN);
+        self.plain = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|_| invalid("decryption failed"))?;
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plain.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.plain.len() - self.pos);
+        buf[..n].copy_from_slice(&self.plain[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecryptReader, Encrypt, EncryptionKey};
+    use log::Record;
+    use log4rs::encode::pattern::PatternEncoder;
+    use log4rs::encode::writer::simple::SimpleWriter;
+    use log4rs::encode::Encode;
+    use std::io::Read;
+
+    #[test]
+    fn round_trip() {
+        let key = EncryptionKey::from_hex(&"2a".repeat(32)).unwrap();
+        let encrypt = Encrypt::new(Box::new(PatternEncoder::new("{m}{n}")), &key);
+        let mut out = Vec::new();
+        for message in &["first", "second"] {
+            encrypt
+                .encode(
+                    &mut SimpleWriter(&mut out),
+                    &Record::builder().args(format_args!("{}", message)).build(),
+                )
+                .unwrap();
+        }
+        assert!(!out.windows(5).any(|w| w == b"first"));
+
+        let mut plain = String::new();
+        DecryptReader::new(&out[..], &key)
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, "first\nsecond\n");
+
+        let wrong = EncryptionKey::new([0; 32]);
+        let mut plain = String::new();
+        assert!(DecryptReader::new(&out[..], &wrong)
+            .read_to_string(&mut plain)
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_hex() {
+        assert!(EncryptionKey::from_hex("2a2a").is_err());
+        assert!(EncryptionKey::from_hex(&"zz".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::{buffer, Encoder};
+use chrono::Local;
+use log::kv::{self, Key, Value, VisitSource, VisitValue};
+use log::Record;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Writes records as JSON lines, for log shippers.
+///
+/// Each line is an object with `time`, `level`, `target`, `module`, `file`,
+/// `line` and `message` members, then the fields set with `field`, plus an
+/// `mdc` object of the MDC entries.
+#[derive(Debug, Clone, Default)]
+pub struct JsonEncoder {
+    fields: Vec<(String, String)>,
+}
+
+impl JsonEncoder {
+    pub fn new() -> JsonEncoder {
+        JsonEncoder::default()
+    }
+
+    /// Adds the member `key` with `value` to every record, e.g. the name of
+    /// the pod.
+    pub fn field(mut self, key: &str, value: &str) -> JsonEncoder {
+        self.fields.push((key.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl Encoder for JsonEncoder {
+    fn encode(&self, buf: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+        buffer::with_string(|line| {
+            line.push_str("{\"time\":");
+            quote(line, &Local::now().to_rfc3339());
+            line.push_str(",\"level\":");
+            quote(line, record.level().as_str());
+            line.push_str(",\"target\":");
+            quote(line, record.target());
+            if let Some(module) = record.module_path() {
+                line.push_str(",\"module\":");
+                quote(line, module);
+            }
+            if let Some(file) = record.file() {
+                line.push_str(",\"file\":");
+                quote(line, file);
+            }
+            if let Some(n) = record.line() {
+                let _ = write!(line, ",\"line\":{}", n);
+            }
+            line.push_str(",\"message\":");
+            buffer::with_message(record, |message| quote(line, message));
+            for (key, value) in &self.fields {
+                line.push(',');
+                quote(line, key);
+                line.push(':');
+                quote(line, value);
+            }
+            let mut fields = Fields { line, first: true };
+            let _ = record.key_values().visit(&mut fields);
+            if !fields.first {
+                line.push('}');
+            }
+
+            let mut mdc = false;
+            log_mdc::iter(|key, value| {
+                line.push_str(if mdc { "," } else { ",\"mdc\":{" });
+                mdc = true;
+                quote(line, key);
+                line.push(':');
+                quote(line, value);
+            });
+            if mdc {
+                line.push('}');
+            }
+            line.push('}');
+            writeln!(buf, "{}", line)
+        })
+    }
+}
+
+// Appends the fields of `event!` as the "fields" object, with their
+// types.
+struct Fields<'a> {
+    line: &'a mut String,
+    first: bool,
+}
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.line
+            .push_str(if self.first { ",\"fields\":{" } else { "," });
+        self.first = false;
+        quote(self.line, key.as_str());
+        self.line.push(':');
+        value.visit(JsonValue(self.line))
+    }
+}
+
+struct JsonValue<'a>(&'a mut String);
+
+impl<'v> VisitValue<'v> for JsonValue<'_> {
+    fn visit_any(&mut self, value: Value) -> Result<(), kv::Error> {
+        buffer::with_string(|text| {
+            let _ = write!(text, "{}", value);
+            quote(self.0, text);
+        });
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        self.0.push_str("null");
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        let _ = write!(self.0, "{}", value);
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        let _ = write!(self.0, "{}", value);
+        Ok(())
+    }
+
+    fn visit_u128(&mut self, value: u128) -> Result<(), kv::Error> {
+        let _ = write!(self.0, "{}", value);
+        Ok(())
+    }
+
+    fn visit_i128(&mut self, value: i128) -> Result<(), kv::Error> {
+        let _ = write!(self.0, "{}", value);
+        Ok(())
+    }
+
+    // JSON has no NaN nor infinities
+    fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+        if value.is_finite() {
+            let _ = write!(self.0, "{}", value);
+        } else {
+            self.0.push_str("null");
+        }
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        self.0.push_str(if value { "true" } else { "false" });
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        quote(self.0, value);
+        Ok(())
+    }
+}
+
+// Appends `s` as a JSON string.
+fn quote(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonEncoder;
+    use crate::encode::Encoder;
+    use log::{Level, Record};
+
+    #[test]
+    fn escapes() {
+        log_mdc::insert("height", "42");
+        let mut buf = Vec::new();
+        JsonEncoder::new()
+            .field("pod", "chain-0")
+            .encode(
+                &mut buf,
+                &Record::builder()
+                    .level(Level::Warn)
+                    .target("chain")
+                    .line(Some(7))
+                    .args(format_args!("say \"hi\"\n\u{1}"))
+                    .build(),
+            )
+            .unwrap();
+        log_mdc::remove("height");
+
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.contains(r#""level":"WARN","target":"chain","line":7"#));
+        assert!(line.ends_with(
+            r#""message":"say \"hi\"\n\u0001","pod":"chain-0","mdc":{"height":"42"}}
+"#
+        ));
+    }
+
+    #[test]
+    fn typed_fields() {
+        let hash = "0x6a6b";
+        let fields = crate::event!(@fields [] height = 1024u64, txs = 35, gas = 0.5, hash = %hash, sealed = true);
+        let mut buf = Vec::new();
+        JsonEncoder::new()
+            .encode(
+                &mut buf,
+                &Record::builder()
+                    .level(Level::Info)
+                    .key_values(&fields)
+                    .args(format_args!("block_committed"))
+                    .build(),
+            )
+            .unwrap();
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.ends_with(
+            r#""message":"block_committed","fields":{"height":1024,"txs":35,"gas":0.5,"hash":"0x6a6b","sealed":true}}
+"#
+        ));
+    }
+}
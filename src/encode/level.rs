@@ -0,0 +1,230 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! Custom display names of the levels.
+
+use log::{Level, Record};
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::{self, Encode};
+use regex::{Captures, Regex};
+use std::fmt;
+use std::str::FromStr;
+
+/// Display names and padding of the levels in the pattern layouts, e.g.
+/// `WARNING` instead of `WARN` for a downstream parser, or localized names.
+///
+/// Set with `Builder::level_names`; it replaces the `{l}` fields of the
+/// file and console patterns.
+///
+/// The names are also read from a string of `key=value` pairs, e.g. a
+/// setting of the service's config file: the keys are the levels, `width`
+/// and `align`, `left` or `right`.
+///
+/// ```
+/// use cita_logger::encode::LevelNames;
+///
+/// let names: LevelNames = "warn=WARNING, width=7, align=right".parse().unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelNames {
+    // Indexed by level, from error to trace
+    names: [Option<String>; 5],
+    width: Option<usize>,
+    right_align: bool,
+}
+
+impl LevelNames {
+    /// The usual names, padded as in the pattern.
+    pub fn new() -> LevelNames {
+        LevelNames::default()
+    }
+
+    /// Compact symbols, one character wide: `✖` for errors, `⚠` for
+    /// warnings, `ℹ` for infos, `✔` for debug and `·` for trace records.
+    pub fn symbols() -> LevelNames {
+        LevelNames::new()
+            .name(Level::Error, "✖")
+            .name(Level::Warn, "⚠")
+            .name(Level::Info, "ℹ")
+            .name(Level::Debug, "✔")
+            .name(Level::Trace, "·")
+            .width(1)
+    }
+
+    /// Displays `level` as `name`.
+    pub fn name(mut self, level: Level, name: &str) -> LevelNames {
+        self.names[level as usize - 1] = Some(name.to_string());
+        self
+    }
+
+    /// Pads the names to `width` characters, replacing the width of the
+    /// pattern.
+    pub fn width(mut self, width: usize) -> LevelNames {
+        self.width = Some(width);
+        self
+    }
+
+    /// Pads the names on the left instead of the right.
+    pub fn right_align(mut self, enabled: bool) -> LevelNames {
+        self.right_align = enabled;
+        self
+    }
+
+    // The displayed name of `level`, unpadded.
+    fn display(&self, level: Level) -> &str {
+        match &self.names[level as usize - 1] {
+            Some(name) => name,
+            None => level.as_str(),
+        }
+    }
+
+    // The level displayed as `name`, padded or not, or with the usual
+    // `name`.
+    pub(crate) fn level(&self, name: &str) -> Option<Level> {
+        let name = name.trim();
+        Level::iter()
+            .find(|level| self.display(*level) == name)
+            .or_else(|| Level::from_str(name).ok())
+    }
+
+    // `pattern` with its level fields replaced by the name of `level`.
+    pub(crate) fn pattern(&self, pattern: &str, level: Level) -> String {
+        let field = Regex::new(r"\{(?:l|level)(:[^}]*)?\}").unwrap();
+        let name: String = self
+            .display(level)
+            .chars()
+            .flat_map(|c| match c {
+                '{' | '}' | '(' | ')' | '\\' => vec!['\\', c],
+                c => vec![c],
+            })
+            .collect();
+        field
+            .replace_all(pattern, |caps: &Captures| {
+                let spec = match self.width {
+                    Some(width) if self.right_align => format!(":>{}", width),
+                    Some(width) => format!(":{}", width),
+                    None => caps.get(1).map_or("", |m| m.as_str()).to_string(),
+                };
+                format!("{{({}){}}}", name, spec)
+            })
+            .into_owned()
+    }
+}
+
+// In the syntax of `FromStr`.
+impl fmt::Display for LevelNames {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut pairs = Vec::new();
+        for level in Level::iter() {
+            if let Some(name) = &self.names[level as usize - 1] {
+                pairs.push(format!("{}={}", level.as_str().to_ascii_lowercase(), name));
+            }
+        }
+        if let Some(width) = self.width {
+            pairs.push(format!("width={}", width));
+        }
+        if self.right_align {
+            pairs.push("align=right".to_string());
+        }
+        write!(f, "{}", pairs.join(", "))
+    }
+}
+
+impl FromStr for LevelNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LevelNames, String> {
+        let mut names = LevelNames::new();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid level name '{}'", pair))?;
+            let value = value.trim();
+            names = match key.trim().to_ascii_lowercase().as_str() {
+                "width" => names.width(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid width '{}'", value))?,
+                ),
+                "align" => match value {
+                    "left" => names.right_align(false),
+                    "right" => names.right_align(true),
+                    _ => return Err(format!("invalid alignment '{}'", value)),
+                },
+                level => match Level::from_str(level) {
+                    Ok(level) => names.name(level, value),
+                    Err(_) => return Err(format!("unknown level name key '{}'", key.trim())),
+                },
+            };
+        }
+        Ok(names)
+    }
+}
+
+// A pattern encoder per level, each with the name of its level.
+#[derive(Debug)]
+pub(crate) struct LevelPatterns(Vec<PatternEncoder>);
+
+impl LevelPatterns {
+    pub(crate) fn new(pattern: &str, names: &LevelNames) -> LevelPatterns {
+        LevelPatterns(
+            Level::iter()
+                .map(|level| PatternEncoder::new(&names.pattern(pattern, level)))
+                .collect(),
+        )
+    }
+}
+
+impl Encode for LevelPatterns {
+    fn encode(&self, w: &mut dyn encode::Write, record: &Record) -> anyhow::Result<()> {
+        self.0[record.level() as usize - 1].encode(w, record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LevelNames, LevelPatterns};
+    use log::{Level, Record};
+    use log4rs::encode::writer::simple::SimpleWriter;
+    use log4rs::encode::Encode;
+
+    fn encode(encoder: &LevelPatterns, level: Level) -> String {
+        let mut out = Vec::new();
+        let record = Record::builder()
+            .level(level)
+            .args(format_args!("block"))
+            .build();
+        encoder
+            .encode(&mut SimpleWriter(&mut out), &record)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn level_names() {
+        let names = LevelNames::new().name(Level::Warn, "WARNING");
+        let encoder = LevelPatterns::new("{l:5}|{h({l})} - {m}", &names);
+        assert_eq!(encode(&encoder, Level::Warn), "WARNING|WARNING - block");
+        assert_eq!(encode(&encoder, Level::Info), "INFO |INFO - block");
+
+        let names = LevelNames::new()
+            .name(Level::Error, "错误(E)")
+            .width(7)
+            .right_align(true);
+        let encoder = LevelPatterns::new("{l:5} {m}", &names);
+        assert_eq!(encode(&encoder, Level::Error), "  错误(E) block");
+        assert_eq!(encode(&encoder, Level::Debug), "  DEBUG block");
+
+        assert_eq!(names.to_string(), "error=错误(E), width=7, align=right");
+        assert_eq!(names.to_string().parse(), Ok(names));
+        assert!("warn=WARNING, width=wide".parse::<LevelNames>().is_err());
+        assert!("loud=LOUD".parse::<LevelNames>().is_err());
+
+        let encoder = LevelPatterns::new("{l:5} {m}", &LevelNames::symbols());
+        assert_eq!(encode(&encoder, Level::Warn), "⚠ block");
+    }
+}
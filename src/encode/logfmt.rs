@@ -0,0 +1,154 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! Records as logfmt lines, `key=value` pairs understood by Loki, Heroku
+//! or `hl`.
+
+use super::{buffer, Encoder};
+use chrono::Local;
+use log::kv::{self, Key, Source, Value, VisitSource};
+use log::Record;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Writes records as logfmt lines: the time, level, target and message,
+/// then the fields of `event!` and the MDC.
+#[derive(Debug, Clone, Default)]
+pub struct LogfmtEncoder(());
+
+impl LogfmtEncoder {
+    pub fn new() -> LogfmtEncoder {
+        LogfmtEncoder::default()
+    }
+}
+
+impl Encoder for LogfmtEncoder {
+    fn encode(&self, buf: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+        buffer::with_string(|line| {
+            line.push_str("time=");
+            let _ = write!(line, "{}", Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z"));
+            line.push_str(" level=");
+            line.extend(
+                record
+                    .level()
+                    .as_str()
+                    .chars()
+                    .map(|c| c.to_ascii_lowercase()),
+            );
+            line.push_str(" target=");
+            quote(line, record.target());
+            line.push_str(" msg=");
+            buffer::with_message(record, |message| quote(line, message));
+            write_fields(line, record.key_values());
+            log_mdc::iter(|key, value| {
+                line.push(' ');
+                line.push_str(key);
+                line.push('=');
+                quote(line, value);
+            });
+            writeln!(buf, "{}", line)
+        })
+    }
+}
+
+// Appends ` key=value` for each of `fields`.
+pub(crate) fn write_fields(out: &mut String, fields: &dyn Source) {
+    let _ = fields.visit(&mut Fields(out));
+}
+
+struct Fields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push(' ');
+        self.0.push_str(key.as_str());
+        self.0.push('=');
+        match value.to_borrowed_str() {
+            Some(value) => quote(self.0, value),
+            None => buffer::with_string(|text| {
+                let _ = write!(text, "{}", value);
+                quote(self.0, text);
+            }),
+        }
+        Ok(())
+    }
+}
+
+// Appends `value`, quoted if it is empty or holds spaces, quotes or `=`.
+fn quote(out: &mut String, value: &str) {
+    let plain = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c == ' ' || c == '"' || c == '=' || c == '\\' || c.is_control());
+    if plain {
+        out.push_str(value);
+        return;
+    }
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, LogfmtEncoder};
+    use crate::encode::Encoder;
+    use log::kv::{ToValue, Value};
+    use log::{Level, Record};
+
+    #[test]
+    fn quoting() {
+        let mut out = String::new();
+        quote(&mut out, "executor");
+        out.push(' ');
+        quote(&mut out, "new block");
+        out.push(' ');
+        quote(&mut out, "");
+        out.push(' ');
+        quote(&mut out, "a=\"b\"\n");
+        assert_eq!(out, r#"executor "new block" "" "a=\"b\"\n""#);
+    }
+
+    #[test]
+    fn encode_event() {
+        let hash = format!("0x{:x}", 0x6a6b);
+        let fields: [(&str, Value); 3] = [
+            ("height", 1024u64.to_value()),
+            ("proposer", "node 1".to_value()),
+            ("hash", Value::from_display(&hash)),
+        ];
+        let mut buf = Vec::new();
+        LogfmtEncoder::new()
+            .encode(
+                &mut buf,
+                &Record::builder()
+                    .level(Level::Info)
+                    .target("chain")
+                    .key_values(&fields)
+                    .args(format_args!("block_committed"))
+                    .build(),
+            )
+            .unwrap();
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.starts_with("time="));
+        assert!(line.ends_with(
+            " level=info target=chain msg=block_committed height=1024 proposer=\"node 1\" hash=0x6a6b\n"
+        ));
+    }
+}
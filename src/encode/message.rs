@@ -0,0 +1,269 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Rewriting of messages and targets before they are encoded.
+
+use super::{buffer, logfmt};
+use log::Record;
+use log4rs::encode::{self, Encode};
+use std::borrow::Cow;
+
+// Messages are truncated beyond this by default.
+const DEFAULT_MAX_LEN: usize = 16 * 1024;
+
+// How messages are rewritten, see `Builder::escape_newlines`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MessageFormat {
+    pub(crate) escape_newlines: bool,
+    pub(crate) sanitize_control: bool,
+    pub(crate) max_len: Option<usize>,
+    pub(crate) target_width: Option<usize>,
+}
+
+impl Default for MessageFormat {
+    fn default() -> MessageFormat {
+        MessageFormat {
+            escape_newlines: false,
+            sanitize_control: false,
+            max_len: Some(DEFAULT_MAX_LEN),
+            target_width: None,
+        }
+    }
+}
+
+impl MessageFormat {
+    fn is_noop(&self) -> bool {
+        !self.escape_newlines
+            && !self.sanitize_control
+            && self.max_len.is_none()
+            && self.target_width.is_none()
+    }
+
+    fn apply<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        let mut message = Cow::Borrowed(message);
+        if let Some(max_len) = self.max_len {
+            if message.len() > max_len {
+                let mut end = max_len;
+                while !message.is_char_boundary(end) {
+                    end -= 1;
+                }
+                message = Cow::Owned(format!(
+                    "{}…[truncated {} bytes]",
+                    &message[..end],
+                    message.len() - end
+                ));
+            }
+        }
+        if self.sanitize_control && message.chars().any(is_unsafe) {
+            message = Cow::Owned(sanitize(&message));
+        }
+        if self.escape_newlines && message.contains(['\n', '\r']) {
+            message = Cow::Owned(message.replace('\n', "\\n").replace('\r', "\\r"));
+        }
+        message
+    }
+
+    // Shortens the leading modules of a target to their first letter, from
+    // the left, until it fits the width. The last module is kept whole.
+    fn abbreviate<'a>(&self, target: &'a str) -> Cow<'a, str> {
+        let width = match self.target_width {
+            Some(width) if target.len() > width => width,
+            _ => return Cow::Borrowed(target),
+        };
+        let mut modules: Vec<&str> = target.split("::").collect();
+        let mut len = target.len();
+        for module in modules.iter_mut().rev().skip(1).rev() {
+            if len <= width {
+                break;
+            }
+            if let Some(c) = module.chars().next() {
+                let short = &module[..c.len_utf8()];
+                len -= module.len() - short.len();
+                *module = short;
+            }
+        }
+        Cow::Owned(modules.join("::"))
+    }
+}
+
+// Control characters but line breaks and tabs.
+fn is_unsafe(c: char) -> bool {
+    c.is_control() && c != '\n' && c != '\r' && c != '\t'
+}
+
+// Strips ANSI escape sequences and escapes other control characters as
+// `\xNN` or `\u{NN}`, so untrusted data can't forge lines or drive the
+// terminal of whoever reads the log.
+fn sanitize(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut chars = message.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' && chars.peek() == Some(&'[') {
+            // CSI: parameters and intermediates up to a final byte
+            chars.next();
+            for c in chars.by_ref() {
+                if ('\u{40}'..='\u{7e}').contains(&c) {
+                    break;
+                }
+            }
+        } else if is_unsafe(c) && (c as u32) < 0x80 {
+            out.push_str(&format!("\\x{:02x}", c as u32));
+        } else if is_unsafe(c) {
+            out.push_str(&format!("\\u{{{:x}}}", c as u32));
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// Applies a `MessageFormat` in front of another encoder.
+#[derive(Debug)]
+pub(crate) struct Rewrite {
+    inner: Box<dyn Encode>,
+    format: MessageFormat,
+}
+
+impl Rewrite {
+    pub(crate) fn wrap(inner: Box<dyn Encode>, format: MessageFormat) -> Box<dyn Encode> {
+        if format.is_noop() {
+            return inner;
+        }
+        Box::new(Rewrite { inner, format })
+    }
+}
+
+impl Encode for Rewrite {
+    fn encode(&self, w: &mut dyn encode::Write, record: &Record) -> anyhow::Result<()> {
+        buffer::with_message(record, |message| {
+            let target = self.format.abbreviate(record.target());
+            match (self.format.apply(message), target) {
+                (Cow::Borrowed(_), Cow::Borrowed(_)) => self.inner.encode(w, record),
+                (message, target) => self.inner.encode(
+                    w,
+                    &Record::builder()
+                        .level(record.level())
+                        .target(&target)
+                        .module_path(record.module_path())
+                        .file(record.file())
+                        .line(record.line())
+                        .key_values(record.key_values())
+                        .args(format_args!("{}", message))
+                        .build(),
+                ),
+            }
+        })
+    }
+}
+
+// Appends the fields of `event!` to the message, for the text layouts.
+#[derive(Debug)]
+pub(crate) struct AppendFields(pub(crate) Box<dyn Encode>);
+
+impl Encode for AppendFields {
+    fn encode(&self, w: &mut dyn encode::Write, record: &Record) -> anyhow::Result<()> {
+        if record.key_values().count() == 0 {
+            return self.0.encode(w, record);
+        }
+        buffer::with_string(|fields| {
+            logfmt::write_fields(fields, record.key_values());
+            self.0.encode(
+                w,
+                &Record::builder()
+                    .level(record.level())
+                    .target(record.target())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .key_values(record.key_values())
+                    .args(format_args!("{}{}", record.args(), fields))
+                    .build(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AppendFields, MessageFormat};
+    use log::Record;
+    use log4rs::encode::pattern::PatternEncoder;
+    use log4rs::encode::writer::simple::SimpleWriter;
+    use log4rs::encode::Encode;
+
+    #[test]
+    fn escape_newlines() {
+        let format = MessageFormat {
+            escape_newlines: true,
+            ..MessageFormat::default()
+        };
+        assert!(!format.is_noop());
+        assert_eq!(format.apply("block\r\nhash"), "block\\r\\nhash");
+        assert_eq!(format.apply("one line"), "one line");
+    }
+
+    #[test]
+    fn truncate() {
+        let format = MessageFormat {
+            max_len: Some(4),
+            ..MessageFormat::default()
+        };
+        assert_eq!(format.apply("block body"), "bloc…[truncated 6 bytes]");
+        assert_eq!(format.apply("区块"), "区…[truncated 3 bytes]");
+        assert_eq!(format.apply("tx"), "tx");
+    }
+
+    #[test]
+    fn sanitize_control() {
+        let format = MessageFormat {
+            sanitize_control: true,
+            ..MessageFormat::default()
+        };
+        assert_eq!(
+            format.apply("peer \u{1b}[31mred\u{1b}[0m\u{7}\u{9b}\tok"),
+            "peer red\\x07\\u{9b}\tok"
+        );
+    }
+
+    #[test]
+    fn abbreviate_target() {
+        let format = MessageFormat {
+            target_width: Some(20),
+            ..MessageFormat::default()
+        };
+        assert_eq!(
+            format.abbreviate("cita_chain::core::libchain::chain"),
+            "c::c::l::chain"
+        );
+        assert_eq!(
+            format.abbreviate("cita_auth::handler::verify"),
+            "c::handler::verify"
+        );
+        assert_eq!(format.abbreviate("cita_network"), "cita_network");
+        assert_eq!(
+            format.abbreviate("a_very_long_crate_name_here"),
+            "a_very_long_crate_name_here"
+        );
+    }
+
+    #[test]
+    fn append_fields() {
+        let encoder = AppendFields(Box::new(PatternEncoder::new("{m}{n}")));
+        let fields = crate::event!(@fields [] height = 1024u64, proposer = "node 1");
+        let mut out = Vec::new();
+        encoder
+            .encode(
+                &mut SimpleWriter(&mut out),
+                &Record::builder()
+                    .key_values(&fields)
+                    .args(format_args!("block_committed"))
+                    .build(),
+            )
+            .unwrap();
+        assert_eq!(out, b"block_committed height=1024 proposer=\"node 1\"\n");
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! Encoders turning records into bytes.
+
+pub mod binary;
+pub(crate) mod buffer;
+pub mod cef;
+pub mod color;
+pub mod csv;
+#[cfg(feature = "encryption")]
+pub mod encrypt;
+pub mod json;
+pub mod level;
+pub mod logfmt;
+pub(crate) mod message;
+pub mod syslog;
+
+pub use self::binary::BinaryEncoder;
+pub use self::cef::{CefEncoder, LeefEncoder};
+pub use self::color::ColorTheme;
+pub use self::csv::CsvEncoder;
+pub use self::json::JsonEncoder;
+pub use self::level::LevelNames;
+pub use self::logfmt::LogfmtEncoder;
+pub use self::syslog::Rfc5424Encoder;
+
+use log::Record;
+use log4rs::encode::Encode;
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Formats a record, e.g. into an in-house log format.
+///
+/// Custom encoders are set with `Builder::file_encoder` and
+/// `Builder::console_encoder`.
+pub trait Encoder: fmt::Debug + Send + Sync + 'static {
+    /// Appends the encoded `record` to `buf`, including any line terminator.
+    fn encode(&self, buf: &mut Vec<u8>, record: &Record) -> io::Result<()>;
+}
+
+/// The layout of the records of the log file and the console, see
+/// `Builder::format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum LogFormat {
+    /// The built-in patterns, or those set.
+    Text,
+    /// A JSON object per line, see `JsonEncoder`.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid format '{}'", s)),
+        }
+    }
+}
+
+/// The timestamps of the built-in patterns, see `Builder::time_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+#[cfg_attr(feature = "clap", derive(::clap::ValueEnum))]
+pub enum TimeFormat {
+    /// `2019-05-16 - 10:00:00` in the log file, the ISO 8601 time of
+    /// log4rs on the console.
+    Default,
+    /// RFC 3339 with microseconds and the offset, e.g.
+    /// `2019-05-16T10:00:00.123456+08:00`, taken by the collectors refusing
+    /// the default.
+    Rfc3339,
+    /// Milliseconds since the Unix epoch, e.g. `1557972000123`.
+    Epoch,
+    /// Seconds since the process started, e.g. `12.345678`, to diff the
+    /// startup of several nodes. The start is the first `Builder` created.
+    ///
+    /// `read_logs` puts these records at the Unix epoch plus the elapsed
+    /// time.
+    Elapsed,
+}
+
+// Stands for the times of `Epoch` and `Elapsed` in a pattern, which log4rs
+// does not format: `Stamped` writes them in its place
+const TIME_MARK: u8 = 0x01;
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+// Marks the start of the process for `TimeFormat::Elapsed`.
+pub(crate) fn mark_start() {
+    START.get_or_init(Instant::now);
+}
+
+// The end of the pattern field starting at `start`, after its closing
+// brace, in the bytes or the chars of the pattern.
+pub(crate) fn field_end<T: Copy + Into<char>>(pattern: &[T], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < pattern.len() {
+        match pattern[i].into() {
+            '\\' => i += 1,
+            '{' | '(' => depth += 1,
+            '}' | ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    pattern.len()
+}
+
+impl TimeFormat {
+    // The pattern field of the time, e.g. in the log file.
+    pub(crate) fn field(self, default: &str) -> String {
+        match self {
+            TimeFormat::Default => default.to_string(),
+            TimeFormat::Rfc3339 => "{d(%Y-%m-%dT%H:%M:%S%.6f%:z)}".to_string(),
+            TimeFormat::Epoch | TimeFormat::Elapsed => char::from(TIME_MARK).to_string(),
+        }
+    }
+
+    // A pattern with `field` readable, e.g. to describe the config.
+    pub(crate) fn describe(self, pattern: &str) -> String {
+        let field = match self {
+            TimeFormat::Epoch => "{epoch}",
+            TimeFormat::Elapsed => "{elapsed}",
+            TimeFormat::Default | TimeFormat::Rfc3339 => return pattern.to_string(),
+        };
+        pattern.replace(char::from(TIME_MARK), field)
+    }
+
+    // Provides the times of `field` to an encoder of a pattern.
+    pub(crate) fn stamp(self, inner: Box<dyn Encode>) -> Box<dyn Encode> {
+        match self {
+            TimeFormat::Default | TimeFormat::Rfc3339 => inner,
+            TimeFormat::Epoch | TimeFormat::Elapsed => Box::new(Stamped {
+                format: self,
+                inner,
+            }),
+        }
+    }
+}
+
+impl FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimeFormat, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "default" => Ok(TimeFormat::Default),
+            "rfc3339" => Ok(TimeFormat::Rfc3339),
+            "epoch" => Ok(TimeFormat::Epoch),
+            "elapsed" => Ok(TimeFormat::Elapsed),
+            _ => Err(format!("invalid time format '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stamped {
+    format: TimeFormat,
+    inner: Box<dyn Encode>,
+}
+
+impl Encode for Stamped {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &Record) -> anyhow::Result<()> {
+        let time = match self.format {
+            TimeFormat::Elapsed => START.get_or_init(Instant::now).elapsed(),
+            _ => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        };
+        let mut w = StampWriter {
+            inner: w,
+            format: self.format,
+            time: Some(time),
+        };
+        self.inner.encode(&mut w, record)
+    }
+}
+
+// Writes the time of a record in place of the first `TIME_MARK`, which the
+// patterns write as part of a literal.
+struct StampWriter<'a> {
+    inner: &'a mut dyn log4rs::encode::Write,
+    format: TimeFormat,
+    time: Option<Duration>,
+}
+
+impl io::Write for StampWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let time = match self.time {
+            Some(time) => time,
+            None => return self.inner.write(buf),
+        };
+        let at = match buf.iter().position(|b| *b == TIME_MARK) {
+            Some(at) => at,
+            None => return self.inner.write(buf),
+        };
+        self.time = None;
+        self.inner.write_all(&buf[..at])?;
+        match self.format {
+            TimeFormat::Elapsed => {
+                write!(self.inner, "{}.{:06}", time.as_secs(), time.subsec_micros())?
+            }
+            _ => write!(self.inner, "{}", time.as_millis())?,
+        }
+        self.inner.write_all(&buf[at + 1..])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl log4rs::encode::Write for StampWriter<'_> {
+    fn set_style(&mut self, style: &log4rs::encode::Style) -> io::Result<()> {
+        self.inner.set_style(style)
+    }
+}
+
+// Numbers the outputs of the encoders, e.g. a log file and the one
+// replacing it after a rotation
+static NEXT_OUTPUT: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    // The output of the record being encoded by the thread, 0 outside
+    // the appenders
+    static OUTPUT: Cell<u64> = const { Cell::new(0) };
+}
+
+// The output the thread encodes a record for, e.g. for the binary format
+// to define its targets in each file.
+pub(crate) fn output() -> u64 {
+    OUTPUT.with(Cell::get)
+}
+
+// Adapts an `Encoder` to log4rs, for one output.
+#[derive(Debug)]
+pub(crate) struct Custom {
+    encoder: Arc<dyn Encoder>,
+    output: u64,
+}
+
+impl Custom {
+    pub(crate) fn new(encoder: Arc<dyn Encoder>) -> Custom {
+        Custom {
+            encoder,
+            output: NEXT_OUTPUT.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl Encode for Custom {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &Record) -> anyhow::Result<()> {
+        buffer::with_bytes(|buf| {
+            let outer = OUTPUT.with(|output| output.replace(self.output));
+            let result = self.encoder.encode(buf, record);
+            OUTPUT.with(|output| output.set(outer));
+            result?;
+            w.write_all(buf)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field_end, Custom, Encoder, TimeFormat};
+    use log::Record;
+    use log4rs::encode::pattern::PatternEncoder;
+    use log4rs::encode::writer::simple::SimpleWriter;
+    use log4rs::encode::Encode;
+    use std::io::{self, Write};
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Upper;
+
+    #[test]
+    fn field_ends() {
+        let pattern = r"{d(%H\))} {m}";
+        assert_eq!(field_end(pattern.as_bytes(), 0), 9);
+        let chars: Vec<char> = pattern.chars().collect();
+        assert_eq!(field_end(&chars, 10), 13);
+        assert_eq!(field_end(b"{d(", 0), 3);
+    }
+
+    impl Encoder for Upper {
+        fn encode(&self, buf: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+            writeln!(buf, "{}", record.args().to_string().to_uppercase())
+        }
+    }
+
+    #[test]
+    fn custom_encoder() {
+        let mut out = Vec::new();
+        let record = Record::builder().args(format_args!("block")).build();
+        Custom::new(Arc::new(Upper))
+            .encode(&mut SimpleWriter(&mut out), &record)
+            .unwrap();
+        assert_eq!(out, b"BLOCK\n");
+    }
+
+    #[test]
+    fn stamped_times() {
+        let encode = |format: TimeFormat| {
+            let mut out = Vec::new();
+            let record = Record::builder().args(format_args!("block")).build();
+            let pattern = format!(
+                "[chain] {} {{m}}{{X(cita_log_time)(.)}}",
+                format.field("{d}")
+            );
+            format
+                .stamp(Box::new(PatternEncoder::new(&pattern)))
+                .encode(&mut SimpleWriter(&mut out), &record)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // Without a trace in the MDC
+        let out = encode(TimeFormat::Epoch);
+        let (millis, message) = out["[chain] ".len()..].split_once(' ').unwrap();
+        assert!(millis.parse::<u64>().unwrap() > 1_500_000_000_000);
+        assert_eq!(message, "block.");
+        let out = encode(TimeFormat::Elapsed);
+        let elapsed = out["[chain] ".len()..].split_once(' ').unwrap().0;
+        let (secs, micros) = elapsed.split_once('.').unwrap();
+        assert!(secs.parse::<u64>().is_ok() && micros.len() == 6);
+        assert_eq!("RFC3339".parse(), Ok(TimeFormat::Rfc3339));
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::Encoder;
+use chrono::Local;
+use log::{Level, Record};
+#[cfg(unix)]
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::process;
+
+// The SD-ID of the MDC element, 32473 is the enterprise number reserved
+// for documentation (RFC 5612).
+const MDC_SD_ID: &str = "mdc@32473";
+
+/// Writes records as RFC 5424 syslog messages.
+///
+/// The service name is the APP-NAME, the target the MSGID, and the MDC
+/// entries are carried in a structured-data element.
+#[derive(Debug, Clone)]
+pub struct Rfc5424Encoder {
+    app_name: String,
+    hostname: String,
+    facility: u8,
+}
+
+impl Rfc5424Encoder {
+    /// Encodes messages of facility `daemon` (3).
+    pub fn new(app_name: &str) -> Rfc5424Encoder {
+        Rfc5424Encoder {
+            app_name: header_field(app_name, 48),
+            hostname: header_field(&hostname(), 255),
+            facility: 3,
+        }
+    }
+
+    /// Sets the facility code, 0 to 23.
+    pub fn facility(mut self, facility: u8) -> Rfc5424Encoder {
+        self.facility = facility.min(23);
+        self
+    }
+}
+
+impl Encoder for Rfc5424Encoder {
+    fn encode(&self, buf: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+        write!(
+            buf,
+            "<{}>1 {} {} {} {} {} ",
+            self.facility * 8 + severity(record.level()),
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.6f%:z"),
+            self.hostname,
+            self.app_name,
+            process::id(),
+            header_field(record.target(), 32),
+        )?;
+        write_structured_data(buf)?;
+        writeln!(buf, " {}", record.args())
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+fn write_structured_data(buf: &mut Vec<u8>) -> io::Result<()> {
+    let mut params = Vec::new();
+    log_mdc::iter(|key, value| params.push((param_name(key), param_value(value))));
+    if params.is_empty() {
+        buf.push(b'-');
+        return Ok(());
+    }
+
+    write!(buf, "[{}", MDC_SD_ID)?;
+    for (name, value) in params {
+        write!(buf, " {}=\"{}\"", name, value)?;
+    }
+    buf.push(b']');
+    Ok(())
+}
+
+// Header fields are printable ASCII without spaces, or "-" if empty.
+fn header_field(field: &str, max_len: usize) -> String {
+    let field: String = field
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+fn param_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"'))
+        .take(32)
+        .collect()
+}
+
+fn param_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return String::new();
+    }
+    CStr::from_bytes_until_nul(&buf)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{header_field, param_value, Rfc5424Encoder};
+    use crate::encode::Encoder;
+    use log::{Level, Record};
+
+    #[test]
+    fn escape_fields() {
+        assert_eq!(header_field("cita chain", 48), "citachain");
+        assert_eq!(header_field("", 48), "-");
+        assert_eq!(param_value("a\"b]c\\"), "a\\\"b\\]c\\\\");
+    }
+
+    #[test]
+    fn encode_record() {
+        let encoder = Rfc5424Encoder::new("chain");
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("chain::block")
+            .args(format_args!("slow block"))
+            .build();
+
+        let mut buf = Vec::new();
+        encoder.encode(&mut buf, &record).unwrap();
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.starts_with("<28>1 "));
+        assert!(line.ends_with(" chain::block - slow block\n"));
+
+        log_mdc::insert("height", "10");
+        let mut buf = Vec::new();
+        encoder.encode(&mut buf, &record).unwrap();
+        log_mdc::remove("height");
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.ends_with(" chain::block [mdc@32473 height=\"10\"] slow block\n"));
+    }
+}
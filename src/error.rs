@@ -0,0 +1,119 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Errors inside the logging pipeline itself.
+
+use parking_lot::{const_mutex, const_rwlock, Mutex, RwLock};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Where an internal error of the logging pipeline happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InternalErrorKind {
+    /// An appender failed to write or flush a record.
+    Append,
+    /// Renaming or reopening the log file failed during a rotation.
+    Rotate,
+    /// The disk-usage guard failed to prune old log files.
+    Disk,
+}
+
+/// An internal error of the logging pipeline, passed to the handler
+/// registered with `set_error_handler`.
+#[derive(Debug, Clone)]
+pub struct InternalError {
+    kind: InternalErrorKind,
+    message: String,
+    time: SystemTime,
+}
+
+impl InternalError {
+    pub fn kind(&self) -> InternalErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+}
+
+impl fmt::Display for InternalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+type Handler = Arc<dyn Fn(&InternalError) + Send + Sync>;
+
+static ERROR_HANDLER: RwLock<Option<Handler>> = const_rwlock(None);
+static LAST_ERROR: Mutex<Option<InternalError>> = const_mutex(None);
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Registers a callback for internal errors of the logging pipeline, e.g.
+/// to report "logging is broken" through a health endpoint.
+///
+/// Replaces the previous handler. Without a handler, errors are printed to
+/// stderr. The handler must not block, it runs on the logging threads.
+pub fn set_error_handler<F>(handler: F)
+where
+    F: Fn(&InternalError) + Send + Sync + 'static,
+{
+    *ERROR_HANDLER.write() = Some(Arc::new(handler));
+}
+
+pub(crate) fn report<E: fmt::Display + ?Sized>(kind: InternalErrorKind, error: &E) {
+    let error = InternalError {
+        kind,
+        message: error.to_string(),
+        time: SystemTime::now(),
+    };
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+    *LAST_ERROR.lock() = Some(error.clone());
+    // Not under the lock, the handler may log or replace itself
+    let handler = ERROR_HANDLER.read().clone();
+    match handler {
+        Some(handler) => handler(&error),
+        None => eprintln!("cita-logger: {}", error),
+    }
+}
+
+// Number of internal errors so far.
+pub(crate) fn count() -> u64 {
+    ERRORS.load(Ordering::Relaxed)
+}
+
+pub(crate) fn last_error() -> Option<InternalError> {
+    LAST_ERROR.lock().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count, report, set_error_handler, InternalErrorKind};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn handler_replacing_itself() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        set_error_handler(move |error| {
+            counted.fetch_add(1, Ordering::Relaxed);
+            set_error_handler(|error| eprintln!("cita-logger: {}", error));
+            report(InternalErrorKind::Append, &format!("again after {}", error));
+        });
+        let errors = count();
+        report(InternalErrorKind::Append, "disk full");
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(count() >= errors + 2);
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Readable formatting of errors and their causes.
+
+use std::error::Error;
+use std::fmt;
+
+/// Displays an error followed by each of its causes (see
+/// `Error::source`) on an indented line.
+///
+/// ```text
+/// sync failed
+///   caused by: connection reset
+///   caused by: broken pipe
+/// ```
+pub struct ErrorChain<'a>(pub &'a (dyn Error + 'a));
+
+impl fmt::Display for ErrorChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(cause) = source {
+            write!(f, "\n  caused by: {}", cause)?;
+            source = cause.source();
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ErrorChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Logs an error and its causes at error level, each cause on an indented
+/// line, optionally after a message.
+///
+/// ```no_run
+/// # let e = std::io::Error::other("reset");
+/// cita_logger::log_error_chain!(e);
+/// cita_logger::log_error_chain!(e, "sync with peer {} failed", 3);
+/// ```
+#[macro_export]
+macro_rules! log_error_chain {
+    ($err:expr $(,)?) => {
+        $crate::error!("{}", $crate::ErrorChain(&$err))
+    };
+    ($err:expr, $($arg:tt)+) => {
+        $crate::error!("{}: {}", format_args!($($arg)+), $crate::ErrorChain(&$err))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorChain;
+    use std::error::Error;
+    use std::fmt;
+    use std::io;
+
+    #[derive(Debug)]
+    struct Sync(io::Error);
+
+    impl fmt::Display for Sync {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "sync failed")
+        }
+    }
+
+    impl Error for Sync {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn causes_on_indented_lines() {
+        let e = Sync(io::Error::other("connection reset"));
+        assert_eq!(
+            ErrorChain(&e).to_string(),
+            "sync failed\n  caused by: connection reset"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Structured events: a name and typed fields, kept as the key-values of the
+// record instead of formatted into its message.
+
+use log::kv::Value;
+use log::{logger, Level, Record};
+use std::fmt::Display;
+
+/// Logs an event with typed fields, which the JSON and logfmt encoders
+/// write as fields of their own and the text layouts after the name.
+///
+/// A field is `key = value` for numbers, booleans and strings, kept with
+/// their type, `key = %value` for its `Display` and `key = ?value` for its
+/// `Debug`.
+///
+/// ```no_run
+/// use log::Level;
+///
+/// let hash = "0x6a6b";
+/// cita_logger::event!(Level::Info, "block_committed", height = 1024u64, txs = 35, hash = %hash);
+/// ```
+#[macro_export]
+macro_rules! event {
+    // The fields, as an array of key-values
+    (@fields [$($out:expr,)*]) => {
+        [$($out,)*]
+    };
+    (@fields [$($out:expr,)*] $key:ident = %$value:expr $(, $($rest:tt)*)?) => {
+        $crate::event!(@fields [$($out,)* (stringify!($key), $crate::__kv::Value::from_display(&$value)),] $($($rest)*)?)
+    };
+    (@fields [$($out:expr,)*] $key:ident = ?$value:expr $(, $($rest:tt)*)?) => {
+        $crate::event!(@fields [$($out,)* (stringify!($key), $crate::__kv::Value::from_debug(&$value)),] $($($rest)*)?)
+    };
+    (@fields [$($out:expr,)*] $key:ident = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::event!(@fields [$($out,)* (stringify!($key), $crate::__kv::ToValue::to_value(&$value)),] $($($rest)*)?)
+    };
+    (target: $target:expr, $level:expr, $name:expr $(, $($fields:tt)*)?) => {{
+        let level = $level;
+        if $crate::log_enabled!(target: $target, level) {
+            $crate::__event(
+                level,
+                $target,
+                module_path!(),
+                file!(),
+                line!(),
+                &$name,
+                &$crate::event!(@fields [] $($($fields)*)?),
+            );
+        }
+    }};
+    ($level:expr, $name:expr $(, $($fields:tt)*)?) => {
+        $crate::event!(target: module_path!(), $level, $name $(, $($fields)*)?)
+    };
+}
+
+#[doc(hidden)]
+pub fn __event(
+    level: Level,
+    target: &str,
+    module_path: &'static str,
+    file: &'static str,
+    line: u32,
+    name: &dyn Display,
+    fields: &[(&str, Value)],
+) {
+    logger().log(
+        &Record::builder()
+            .level(level)
+            .target(target)
+            .module_path_static(Some(module_path))
+            .file_static(Some(file))
+            .line(Some(line))
+            .key_values(&fields)
+            .args(format_args!("{}", name))
+            .build(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn typed_fields() {
+        let hash = "0x6a6b";
+        let peers = vec![1, 2];
+        let fields = event!(@fields [] height = 1024u64, txs = 35, hash = %hash, peers = ?peers, sealed = true);
+        let keys: Vec<_> = fields.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, ["height", "txs", "hash", "peers", "sealed"]);
+        assert_eq!(fields[0].1.to_u64(), Some(1024));
+        assert_eq!(fields[1].1.to_i64(), Some(35));
+        assert_eq!(fields[2].1.to_string(), "0x6a6b");
+        assert_eq!(fields[2].1.to_u64(), None);
+        assert_eq!(fields[3].1.to_string(), "[1, 2]");
+        assert_eq!(fields[4].1.to_bool(), Some(true));
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Export of the spans of `span!` to Zipkin, or to Jaeger through its
+// Zipkin-compatible collector, so that their timings show in a tracing UI.
+//
+// Requires the `zipkin` feature.
+
+use crate::error::{self, InternalErrorKind};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// Spans queued for the collector, more are dropped
+const QUEUE_SIZE: usize = 4096;
+// Spans posted together at most
+const MAX_BATCH: usize = 500;
+
+static EXPORTER: OnceLock<Sender<FinishedSpan>> = OnceLock::new();
+
+/// Settings of the span export, see `Builder::span_exporter`.
+///
+/// ```no_run
+/// use cita_logger::{Builder, LogFavour, ZipkinExporter};
+///
+/// // Jaeger, started with COLLECTOR_ZIPKIN_HOST_PORT=:9411
+/// Builder::new(&LogFavour::File("chain"))
+///     .span_exporter(ZipkinExporter::new("http://localhost:9411/api/v2/spans"))
+///     .init();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ZipkinExporter {
+    url: String,
+    interval: Duration,
+}
+
+impl ZipkinExporter {
+    /// Posts the spans to the Zipkin v2 endpoint `url` every second.
+    pub fn new(url: &str) -> ZipkinExporter {
+        ZipkinExporter {
+            url: url.to_string(),
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Spans ended within `interval` are posted together.
+    pub fn interval(mut self, interval: Duration) -> ZipkinExporter {
+        self.interval = interval;
+        self
+    }
+
+    pub(crate) fn spawn(self, service_name: &str) -> std::io::Result<()> {
+        let (sender, receiver) = bounded(QUEUE_SIZE);
+        let worker = Worker {
+            exporter: self,
+            service_name: service_name.to_string(),
+            receiver,
+        };
+        thread::Builder::new()
+            .name("cita-logger-spans".to_string())
+            .spawn(move || worker.run())?;
+        let _ = EXPORTER.set(sender);
+        Ok(())
+    }
+}
+
+// A span which ended, as reported to the collector.
+pub(crate) struct FinishedSpan {
+    pub(crate) trace_id: u128,
+    pub(crate) id: u64,
+    pub(crate) parent_id: Option<u64>,
+    pub(crate) name: String,
+    pub(crate) start: SystemTime,
+    pub(crate) duration: Duration,
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+// Whether the spans are exported, so that they need trace ids.
+pub(crate) fn enabled() -> bool {
+    EXPORTER.get().is_some()
+}
+
+// Queues `span` for the collector, dropping it if the queue is full.
+pub(crate) fn export(span: FinishedSpan) {
+    if let Some(exporter) = EXPORTER.get() {
+        let _ = exporter.try_send(span);
+    }
+}
+
+struct Worker {
+    exporter: ZipkinExporter,
+    service_name: String,
+    receiver: Receiver<FinishedSpan>,
+}
+
+impl Worker {
+    fn run(self) {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(10)))
+            .build()
+            .into();
+
+        // Wait for the first span of a batch
+        while let Ok(span) = self.receiver.recv() {
+            let mut batch = vec![span];
+            let deadline = Instant::now() + self.exporter.interval;
+            while batch.len() < MAX_BATCH {
+                match self.receiver.recv_deadline(deadline) {
+                    Ok(span) => batch.push(span),
+                    Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+
+            let body = payload(&self.service_name, &batch);
+            if let Err(e) = agent
+                .post(&self.exporter.url)
+                .header("Content-Type", "application/json")
+                .send(body)
+            {
+                error::report(InternalErrorKind::Append, &format!("zipkin: {}", e));
+            }
+        }
+    }
+}
+
+// The spans in the Zipkin v2 JSON format.
+fn payload(service_name: &str, batch: &[FinishedSpan]) -> String {
+    let spans: Vec<_> = batch
+        .iter()
+        .map(|span| {
+            let mut value = serde_json::json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "id": format!("{:016x}", span.id),
+                "name": span.name,
+                "timestamp": micros(span.start),
+                // Zipkin drops spans lasting 0
+                "duration": (span.duration.as_micros() as u64).max(1),
+                "localEndpoint": { "serviceName": service_name },
+                "tags": span
+                    .tags
+                    .iter()
+                    .map(|(key, value)| (key.clone(), serde_json::Value::from(value.as_str())))
+                    .collect::<serde_json::Map<_, _>>(),
+            });
+            if let Some(parent_id) = span.parent_id {
+                value["parentId"] = format!("{:016x}", parent_id).into();
+            }
+            value
+        })
+        .collect();
+    serde_json::Value::from(spans).to_string()
+}
+
+fn micros(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{payload, FinishedSpan};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn zipkin_payload() {
+        let span = |id, parent_id| FinishedSpan {
+            trace_id: 0x4bf9_2f35_77b3_4da6_a3ce_929d_0e0e_4736,
+            id,
+            parent_id,
+            name: "execute_block".to_string(),
+            start: SystemTime::UNIX_EPOCH + Duration::from_secs(1_558_000_000),
+            duration: Duration::from_micros(1500),
+            tags: vec![("height".to_string(), "1024".to_string())],
+        };
+        let body = payload("chain", &[span(2, Some(1)), span(1, None)]);
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(value[0]["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(value[0]["id"], "0000000000000002");
+        assert_eq!(value[0]["parentId"], "0000000000000001");
+        assert_eq!(value[0]["timestamp"], 1_558_000_000_000_000u64);
+        assert_eq!(value[0]["duration"], 1500);
+        assert_eq!(value[0]["localEndpoint"]["serviceName"], "chain");
+        assert_eq!(value[0]["tags"]["height"], "1024");
+        assert!(value[1].get("parentId").is_none());
+    }
+}
@@ -0,0 +1,455 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// The level filter applied by the front-end logger, built from the
+// directives of RUST_LOG.
+//
+// A directive names a module, matching it and all its descendants
+// (`cita_network::*` is the same as `cita_network`), or a prefix ending in
+// `*`, matching every target starting with it (`cita_*`). When several
+// directives match a target, the longest name wins, a module wins over a
+// prefix of the same length, and a later directive wins over an earlier
+// one.
+//
+// Well-known chatty dependencies are capped at warn, unless a directive
+// matches them.
+//
+// A thread can raise its own level for a while with `with_thread_level`.
+//
+// An optional regex (after a `/` in RUST_LOG) drops every record whose
+// message doesn't match it.
+
+use crate::logger;
+use crate::sample;
+use crate::Directive;
+use arc_swap::ArcSwapOption;
+use log::{Level, LevelFilter};
+use regex::Regex;
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// The crates capped at warn by default, see `Builder::quiet_crates`.
+pub(crate) const NOISY_CRATES: &[&str] = &[
+    "amiquip",
+    "h2",
+    "hyper",
+    "mio",
+    "rustls",
+    "tokio_reactor",
+    "tokio_threadpool",
+    "ureq",
+    "want",
+];
+
+#[derive(Debug, Clone)]
+pub(crate) struct Filter {
+    root: LevelFilter,
+    // Most specific first
+    directives: Vec<(Pattern, Directive)>,
+    message: Option<Regex>,
+    // Capped at warn when no directive matches
+    quiet: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    name: String,
+    prefix: bool,
+}
+
+impl Pattern {
+    fn new(name: &str) -> Pattern {
+        match name.strip_suffix("::*") {
+            Some(module) => Pattern {
+                name: module.to_string(),
+                prefix: false,
+            },
+            None => match name.strip_suffix('*') {
+                Some(prefix) => Pattern {
+                    name: prefix.to_string(),
+                    prefix: true,
+                },
+                None => Pattern {
+                    name: name.to_string(),
+                    prefix: false,
+                },
+            },
+        }
+    }
+
+    // Ordering of the precedence
+    fn specificity(&self) -> (usize, bool) {
+        (self.name.len(), !self.prefix)
+    }
+
+    fn matches(&self, target: &str) -> bool {
+        if self.prefix {
+            return target.starts_with(&self.name);
+        }
+        target == self.name
+            || (target.starts_with(&self.name) && target[self.name.len()..].starts_with("::"))
+    }
+}
+
+impl Filter {
+    pub(crate) fn new(
+        root: LevelFilter,
+        directives: Vec<Directive>,
+        message: Option<Regex>,
+    ) -> Filter {
+        // A directive without a name sets the global level
+        let root = directives
+            .iter()
+            .rev()
+            .find(|d| d.name.is_empty())
+            .map_or(root, |d| d.level);
+        let mut directives: Vec<_> = directives
+            .into_iter()
+            .filter(|d| !d.name.is_empty())
+            .rev()
+            .map(|directive| (Pattern::new(&directive.name), directive))
+            .collect();
+        // Stable, so later directives stay ahead of earlier ones
+        directives.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.specificity()));
+        Filter {
+            root,
+            directives,
+            message,
+            quiet: Vec::new(),
+        }
+    }
+
+    // The global level and the directives, least specific first.
+    pub(crate) fn levels(&self) -> (LevelFilter, Vec<(String, LevelFilter)>) {
+        (
+            self.root,
+            self.directives
+                .iter()
+                .rev()
+                .map(|(_, d)| (d.name.clone(), d.level))
+                .collect(),
+        )
+    }
+
+    // Caps the modules `names` at warn, unless a directive matches them.
+    pub(crate) fn quiet<S: AsRef<str>>(mut self, names: &[S]) -> Filter {
+        self.quiet = names
+            .iter()
+            .map(|name| Pattern::new(name.as_ref()))
+            .collect();
+        self
+    }
+
+    // Replaces the directives of the same name, if any.
+    fn set(&mut self, directive: Directive) {
+        if directive.name.is_empty() {
+            self.root = directive.level;
+            return;
+        }
+        self.remove(&directive.name);
+        let pattern = Pattern::new(&directive.name);
+        let pos = self
+            .directives
+            .iter()
+            .position(|(p, _)| p.specificity() <= pattern.specificity())
+            .unwrap_or(self.directives.len());
+        self.directives.insert(pos, (pattern, directive));
+    }
+
+    // Removes the directives of `name`, e.g. `chain` and `chain::*`.
+    fn remove(&mut self, name: &str) {
+        let pattern = Pattern::new(name);
+        self.directives
+            .retain(|(p, _)| p.name != pattern.name || p.prefix != pattern.prefix);
+    }
+
+    // The most specific directive matching `target`.
+    fn directive(&self, target: &str) -> Option<&Directive> {
+        self.directives
+            .iter()
+            .find(|(pattern, _)| pattern.matches(target))
+            .map(|(_, directive)| directive)
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.directive(target)
+            .map_or_else(|| self.default_level(target), |d| d.level)
+    }
+
+    fn enabled(&self, level: Level, target: &str) -> bool {
+        level <= self.level(target)
+    }
+
+    fn sampled(&self, level: Level, target: &str) -> bool {
+        match self.directive(target) {
+            Some(directive) => sample::keep(level, directive.sample),
+            None => true,
+        }
+    }
+
+    // The level of a target no directive matches.
+    fn default_level(&self, target: &str) -> LevelFilter {
+        if self.quiet.iter().any(|pattern| pattern.matches(target)) {
+            self.root.min(LevelFilter::Warn)
+        } else {
+            self.root
+        }
+    }
+
+    fn matches(&self, message: &fmt::Arguments) -> bool {
+        match &self.message {
+            Some(regex) => match message.as_str() {
+                Some(message) => regex.is_match(message),
+                None => regex.is_match(&message.to_string()),
+            },
+            None => true,
+        }
+    }
+
+    // The most verbose level of any target.
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|(_, directive)| directive.level)
+            .fold(self.root, Ord::max)
+    }
+}
+
+static FILTER: ArcSwapOption<Filter> = ArcSwapOption::const_empty();
+// Threads within `with_thread_level`
+static THREAD_OVERRIDES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_LEVEL: Cell<LevelFilter> = const { Cell::new(LevelFilter::Off) };
+}
+
+/// Runs `f` with the records of the current thread logged up to `level`,
+/// whatever the directives, e.g. to trace a single consensus round.
+///
+/// Other threads keep their levels.
+pub fn with_thread_level<F: FnOnce() -> R, R>(level: LevelFilter, f: F) -> R {
+    // Restores the levels even if `f` panics
+    struct Reset(LevelFilter);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            THREAD_LEVEL.with(|l| l.set(self.0));
+            if THREAD_OVERRIDES.fetch_sub(1, Ordering::AcqRel) == 1 {
+                log::set_max_level(logger::max_level());
+            }
+        }
+    }
+
+    THREAD_OVERRIDES.fetch_add(1, Ordering::AcqRel);
+    let previous = THREAD_LEVEL.with(|l| l.replace(level.max(l.get())));
+    let _reset = Reset(previous);
+    if level > log::max_level() {
+        log::set_max_level(level);
+    }
+    f()
+}
+
+fn thread_level() -> LevelFilter {
+    THREAD_LEVEL.with(Cell::get)
+}
+
+pub(crate) fn install(filter: Filter) {
+    FILTER.store(Some(Arc::new(filter)));
+}
+
+// Without a filter nothing is logged.
+pub(crate) fn enabled(level: Level, target: &str) -> bool {
+    if THREAD_OVERRIDES.load(Ordering::Relaxed) > 0 && level <= thread_level() {
+        return true;
+    }
+    match &*FILTER.load() {
+        Some(filter) => filter.enabled(level, target),
+        None => false,
+    }
+}
+
+// Whether an enabled record survives the sampling of its directive, rolled
+// once per record: `log_enabled!` followed by `log!` checks `enabled` twice.
+pub(crate) fn sampled(level: Level, target: &str) -> bool {
+    if THREAD_OVERRIDES.load(Ordering::Relaxed) > 0 && level <= thread_level() {
+        return true;
+    }
+    match &*FILTER.load() {
+        Some(filter) => filter.sampled(level, target),
+        None => true,
+    }
+}
+
+// Whether a message passes the regex filter.
+pub(crate) fn matches(message: &fmt::Arguments) -> bool {
+    match &*FILTER.load() {
+        Some(filter) => filter.matches(message),
+        None => false,
+    }
+}
+
+/// Sets the level of a module (and its descendants) at runtime, leaving the
+/// other directives alone. An empty `name` sets the global level.
+pub fn set_module_level(name: &str, level: LevelFilter) {
+    update(|filter| {
+        filter.set(Directive {
+            name: name.to_string(),
+            level,
+            sample: None,
+        })
+    });
+}
+
+/// Removes the directive of a module set at startup or with
+/// `set_module_level`, so it inherits the level of its parents again.
+pub fn reset_module_level(name: &str) {
+    update(|filter| filter.remove(name));
+}
+
+fn update<F: Fn(&mut Filter)>(f: F) {
+    FILTER.rcu(|filter| {
+        filter.as_ref().map(|filter| {
+            let mut filter = (**filter).clone();
+            f(&mut filter);
+            Arc::new(filter)
+        })
+    });
+    log::set_max_level(logger::max_level());
+}
+
+/// The least severe level logged for `target`, `Off` before the logger is
+/// installed.
+///
+/// Cheap enough to guard the preparation of expensive diagnostics.
+pub fn level_for(target: &str) -> LevelFilter {
+    match &*FILTER.load() {
+        Some(filter) => filter
+            .level(target)
+            .max(thread_level())
+            .min(log::max_level()),
+        None => LevelFilter::Off,
+    }
+}
+
+// The global level and the directives, least specific first.
+pub(crate) fn levels() -> (LevelFilter, Vec<(String, LevelFilter)>) {
+    match &*FILTER.load() {
+        Some(filter) => filter.levels(),
+        None => (LevelFilter::Off, Vec::new()),
+    }
+}
+
+pub(crate) fn max_level() -> LevelFilter {
+    match &*FILTER.load() {
+        Some(filter) => filter.max_level(),
+        None => LevelFilter::Off,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+    use crate::{parse_env, parse_spec, Directive};
+    use log::LevelFilter;
+
+    #[test]
+    fn precedence() {
+        let filter = Filter::new(
+            LevelFilter::Info,
+            parse_env(
+                "cita_*=debug,cita_network::*=trace,cita_network::sync=warn,cita_chain=error",
+            ),
+            None,
+        );
+        assert_eq!(filter.level("cita_network::sync::peer"), LevelFilter::Warn);
+        assert_eq!(filter.level("cita_network::discovery"), LevelFilter::Trace);
+        assert_eq!(filter.level("cita_network"), LevelFilter::Trace);
+        assert_eq!(filter.level("cita_executor"), LevelFilter::Debug);
+        assert_eq!(filter.level("cita_chain::block"), LevelFilter::Error);
+        assert_eq!(filter.level("cita_chainx"), LevelFilter::Debug);
+        assert_eq!(filter.level("jsonrpc"), LevelFilter::Info);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn later_directive_wins() {
+        let filter = Filter::new(
+            LevelFilter::Info,
+            parse_env("chain=debug,chain::*=error"),
+            None,
+        );
+        assert_eq!(filter.level("chain"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn set_and_remove() {
+        let mut filter = Filter::new(
+            LevelFilter::Info,
+            parse_env("chain=debug,chain::*=error,network=warn"),
+            None,
+        );
+        filter.set(Directive {
+            name: "chain".to_string(),
+            level: LevelFilter::Trace,
+            sample: None,
+        });
+        assert_eq!(filter.level("chain::block"), LevelFilter::Trace);
+        assert_eq!(filter.level("network"), LevelFilter::Warn);
+
+        filter.remove("chain");
+        assert_eq!(filter.level("chain::block"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn global_level() {
+        let filter = Filter::new(LevelFilter::Info, parse_env("debug,chain=warn"), None);
+        assert_eq!(filter.level("network"), LevelFilter::Debug);
+        assert_eq!(filter.level("chain"), LevelFilter::Warn);
+
+        let filter = Filter::new(LevelFilter::Info, parse_env("off"), None);
+        assert_eq!(filter.max_level(), LevelFilter::Off);
+    }
+
+    #[test]
+    fn quiet_crates() {
+        let filter = Filter::new(
+            LevelFilter::Info,
+            parse_env("trace,hyper::client=debug"),
+            None,
+        )
+        .quiet(super::NOISY_CRATES);
+        assert_eq!(filter.level("mio::poll"), LevelFilter::Warn);
+        assert_eq!(filter.level("hyper::proto"), LevelFilter::Warn);
+        assert_eq!(filter.level("hyper::client::pool"), LevelFilter::Debug);
+        assert_eq!(filter.level("hyperledger"), LevelFilter::Trace);
+
+        // Capped, never raised
+        let filter = Filter::new(LevelFilter::Error, Vec::new(), None).quiet(&["mio"]);
+        assert_eq!(filter.level("mio"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn thread_level() {
+        super::with_thread_level(LevelFilter::Trace, || {
+            assert_eq!(super::thread_level(), LevelFilter::Trace);
+            std::thread::spawn(|| assert_eq!(super::thread_level(), LevelFilter::Off))
+                .join()
+                .unwrap();
+        });
+        assert_eq!(super::thread_level(), LevelFilter::Off);
+    }
+
+    #[test]
+    fn message_regex() {
+        let (directives, message) = parse_spec("chain=debug/^block");
+        let filter = Filter::new(LevelFilter::Info, directives, message);
+        assert!(filter.matches(&format_args!("block {}", 7)));
+        assert!(!filter.matches(&format_args!("new block")));
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// A gRPC server streaming the live records of the service to remote
+// clients, for the fleet-debugging tools attaching to running nodes.
+//
+// The service is `cita.logger.LogStream` of `proto/log_stream.proto`:
+// `StreamLogs` streams the records at or above a level (info if empty)
+// whose target starts with a prefix (any if empty), until the client
+// cancels it. The messages are written by hand, so that no build script
+// nor `protoc` is needed.
+//
+// Requires the `grpc` feature.
+
+use crate::error::{self, InternalErrorKind};
+use crate::live::{self, RecordFilter};
+use crate::ring::RecentRecord;
+use log::LevelFilter;
+use std::convert::Infallible;
+use std::future::{self, Future, Ready};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::SystemTime;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::tokio_stream::Stream;
+use tonic::codegen::{http, Body, Service, StdError};
+use tonic::server::{Grpc, NamedService, ServerStreamingService};
+use tonic::transport::server::TcpIncoming;
+use tonic::{Code, Request, Response, Status};
+
+const STREAM_LOGS: &str = "/cita.logger.LogStream/StreamLogs";
+// Records queued for a client, more are dropped while it lags behind
+const QUEUE_SIZE: usize = 1024;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct StreamLogsRequest {
+    #[prost(string, tag = "1")]
+    pub(crate) level: String,
+    #[prost(string, tag = "2")]
+    pub(crate) target: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct LogRecord {
+    // Microseconds since the Unix epoch
+    #[prost(int64, tag = "1")]
+    pub(crate) timestamp_us: i64,
+    #[prost(string, tag = "2")]
+    pub(crate) level: String,
+    #[prost(string, tag = "3")]
+    pub(crate) target: String,
+    #[prost(string, tag = "4")]
+    pub(crate) message: String,
+    #[prost(string, tag = "5")]
+    pub(crate) service: String,
+}
+
+// Serves `cita.logger.LogStream` on `addr` from a thread of its own.
+pub(crate) fn spawn(addr: SocketAddr, service_name: &str) -> io::Result<()> {
+    // Bound here, so that an address in use fails the startup
+    let listener = TcpListener::bind(addr)?;
+    serve(listener, service_name)
+}
+
+fn serve(listener: TcpListener, service_name: &str) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let server = LogStreamServer {
+        service_name: Arc::from(service_name),
+    };
+    thread::Builder::new()
+        .name("cita-logger-grpc".to_string())
+        .spawn(move || {
+            let result = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .build()
+                .map_err(StdError::from)
+                .and_then(|runtime| {
+                    runtime.block_on(async move {
+                        let listener = tokio::net::TcpListener::from_std(listener)?;
+                        let incoming = TcpIncoming::from_listener(listener, true, None)?;
+                        tonic::transport::Server::builder()
+                            .add_service(server)
+                            .serve_with_incoming(incoming)
+                            .await?;
+                        Ok(())
+                    })
+                });
+            if let Err(e) = result {
+                error::report(InternalErrorKind::Append, &format!("grpc: {}", e));
+            }
+        })?;
+    Ok(())
+}
+
+#[derive(Clone)]
+struct LogStreamServer {
+    service_name: Arc<str>,
+}
+
+impl NamedService for LogStreamServer {
+    const NAME: &'static str = "cita.logger.LogStream";
+}
+
+impl<B> Service<http::Request<B>> for LogStreamServer
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        if request.uri().path() != STREAM_LOGS {
+            return Box::pin(future::ready(Ok(unimplemented())));
+        }
+        let method = StreamLogs {
+            service_name: self.service_name.clone(),
+        };
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProstCodec::default());
+            Ok(grpc.server_streaming(method, request).await)
+        })
+    }
+}
+
+fn unimplemented() -> http::Response<BoxBody> {
+    let mut response = http::Response::new(tonic::body::empty_body());
+    let headers = response.headers_mut();
+    headers.insert("grpc-status", (Code::Unimplemented as i32).into());
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        tonic::metadata::GRPC_CONTENT_TYPE,
+    );
+    response
+}
+
+struct StreamLogs {
+    service_name: Arc<str>,
+}
+
+impl ServerStreamingService<StreamLogsRequest> for StreamLogs {
+    type Response = LogRecord;
+    type ResponseStream = RecordStream;
+    type Future = Ready<Result<Response<RecordStream>, Status>>;
+
+    fn call(&mut self, request: Request<StreamLogsRequest>) -> Self::Future {
+        let request = request.into_inner();
+        let level = match request.level.as_str() {
+            "" => LevelFilter::Info,
+            level => match LevelFilter::from_str(level) {
+                Ok(level) => level,
+                Err(_) => {
+                    let message = format!("invalid level '{}'", level);
+                    return future::ready(Err(Status::invalid_argument(message)));
+                }
+            },
+        };
+        future::ready(Ok(Response::new(self.subscribe(level, request.target))))
+    }
+}
+
+impl StreamLogs {
+    fn subscribe(&self, level: LevelFilter, target: String) -> RecordStream {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let service_name = self.service_name.clone();
+        live::subscribe_with(
+            RecordFilter { level, target },
+            Box::new(
+                move |record| match sender.try_send(Ok(log_record(&service_name, record))) {
+                    Ok(()) | Err(TrySendError::Full(_)) => true,
+                    Err(TrySendError::Closed(_)) => false,
+                },
+            ),
+        );
+        RecordStream(receiver)
+    }
+}
+
+fn log_record(service_name: &str, record: &RecentRecord) -> LogRecord {
+    LogRecord {
+        timestamp_us: record
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as i64),
+        level: record.level.to_string(),
+        target: record.target.clone(),
+        message: record.message.clone(),
+        service: service_name.to_string(),
+    }
+}
+
+// The records of a client, ending when it goes away.
+struct RecordStream(mpsc::Receiver<Result<LogRecord, Status>>);
+
+impl Stream for RecordStream {
+    type Item = Result<LogRecord, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{serve, LogRecord, StreamLogsRequest, STREAM_LOGS};
+    use crate::live;
+    use log::{Level, Record};
+    use std::net::TcpListener;
+    use tonic::codec::ProstCodec;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::transport::Endpoint;
+    use tonic::Request;
+
+    #[test]
+    fn stream_logs() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(listener, "chain").unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (first, error) = runtime.block_on(async {
+            let channel = Endpoint::from_shared(format!("http://{}", addr))
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            let mut client = tonic::client::Grpc::new(channel);
+            let request = |level: &str| {
+                Request::new(StreamLogsRequest {
+                    level: level.to_string(),
+                    target: "grpc_test::network".to_string(),
+                })
+            };
+            client.ready().await.unwrap();
+            let mut stream = client
+                .server_streaming(
+                    request("warn"),
+                    PathAndQuery::from_static(STREAM_LOGS),
+                    ProstCodec::<StreamLogsRequest, LogRecord>::default(),
+                )
+                .await
+                .unwrap()
+                .into_inner();
+
+            for (level, target) in [
+                (Level::Info, "grpc_test::network"),
+                (Level::Error, "grpc_test::chain"),
+                (Level::Warn, "grpc_test::network::sync"),
+            ] {
+                live::publish(
+                    &Record::builder()
+                        .level(level)
+                        .target(target)
+                        .args(format_args!("peer 3 lagging"))
+                        .build(),
+                );
+            }
+            let first = stream.message().await.unwrap().unwrap();
+
+            client.ready().await.unwrap();
+            let error = client
+                .server_streaming(
+                    request("loud"),
+                    PathAndQuery::from_static(STREAM_LOGS),
+                    ProstCodec::<StreamLogsRequest, LogRecord>::default(),
+                )
+                .await
+                .unwrap_err();
+            (first, error)
+        });
+        assert_eq!(first.level, "WARN");
+        assert_eq!(first.target, "grpc_test::network::sync");
+        assert_eq!(first.message, "peer 3 lagging");
+        assert_eq!(first.service, "chain");
+        assert!(first.timestamp_us > 0);
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+    }
+}
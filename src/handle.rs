@@ -0,0 +1,83 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Programmatic control of the installed logger.
+
+use crate::filter;
+use crate::stats::{self, Stats};
+use crossbeam_channel::Sender;
+use libc::c_int;
+use log::LevelFilter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+// Wakes up the rotation thread like a SIGUSR1
+static ROTATE: OnceLock<Sender<c_int>> = OnceLock::new();
+// The rounds of the rotation thread, whether they rotated the files or not
+static ROTATION_ROUNDS: AtomicU64 = AtomicU64::new(0);
+
+// The signal rotating the log files, also sent by `request_rotation`.
+#[cfg(unix)]
+pub(crate) const ROTATE_SIGNAL: c_int = signal_hook::consts::SIGUSR1;
+#[cfg(not(unix))]
+pub(crate) const ROTATE_SIGNAL: c_int = 0;
+
+/// Controls the logger installed by `Builder::init` or `init_config`.
+///
+/// Handles are cheap and all control the same logger.
+#[derive(Debug, Clone, Default)]
+pub struct LoggerHandle(());
+
+impl LoggerHandle {
+    pub(crate) fn new() -> LoggerHandle {
+        LoggerHandle(())
+    }
+
+    /// Rotates the log files as on a SIGUSR1, in the background.
+    ///
+    /// Returns false when logging to the console.
+    pub fn rotate(&self) -> bool {
+        request_rotation()
+    }
+
+    /// Sets the level of a module, or the global level if `name` is empty,
+    /// see `set_module_level`.
+    pub fn set_level(&self, name: &str, level: LevelFilter) {
+        filter::set_module_level(name, level);
+    }
+
+    /// Flushes the buffered records.
+    pub fn flush(&self) {
+        log::logger().flush();
+    }
+
+    /// The counts of the records logged so far, see `stats`.
+    pub fn stats(&self) -> Stats {
+        stats::stats()
+    }
+}
+
+pub(crate) fn install_rotation(rotate: Sender<c_int>) {
+    let _ = ROTATE.set(rotate);
+}
+
+// Whether the rotation thread got the request.
+pub(crate) fn request_rotation() -> bool {
+    match ROTATE.get() {
+        Some(rotate) => rotate.try_send(ROTATE_SIGNAL).is_ok(),
+        None => false,
+    }
+}
+
+// The round of the rotation thread a request would be served by.
+pub(crate) fn rotation_round() -> u64 {
+    ROTATION_ROUNDS.load(Ordering::SeqCst)
+}
+
+pub(crate) fn rotation_done() {
+    ROTATION_ROUNDS.fetch_add(1, Ordering::SeqCst);
+}
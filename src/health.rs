@@ -0,0 +1,70 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Health of the logging pipeline.
+
+use crate::append::{fallback, writer};
+use crate::error::{self, InternalError, InternalErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Milliseconds since the epoch, 0 if nothing was written yet.
+static LAST_WRITE: AtomicU64 = AtomicU64::new(0);
+
+/// The state of the logging pipeline, see `health()`.
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    /// Whether the configured appenders accept records. False once the file
+    /// appender failed over to the console.
+    pub writable: bool,
+    /// Records waiting for the writer threads of `Builder::async_writes`,
+    /// counting a batch of `Builder::batch` as one.
+    pub queue_depth: usize,
+    /// Time since a record was last written successfully.
+    pub since_last_write: Option<Duration>,
+    /// The most recent internal error, if any.
+    pub last_error: Option<InternalError>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.writable
+    }
+}
+
+pub(crate) fn mark_write() {
+    LAST_WRITE.store(millis(SystemTime::now()), Ordering::Relaxed);
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Describes whether the logging pipeline works, for inclusion in the
+/// health RPC of a service.
+pub fn health() -> HealthReport {
+    let last_write = LAST_WRITE.load(Ordering::Relaxed);
+    let last_error = error::last_error();
+    let append_failing = match &last_error {
+        Some(e) => e.kind() == InternalErrorKind::Append && millis(e.time()) > last_write,
+        None => false,
+    };
+
+    HealthReport {
+        writable: !fallback::failed_over() && !append_failing,
+        queue_depth: writer::queue_depth(),
+        since_last_write: match last_write {
+            0 => None,
+            ms => SystemTime::now()
+                .duration_since(UNIX_EPOCH + Duration::from_millis(ms))
+                .ok(),
+        },
+        last_error,
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Index sidecars of the log files (e.g: logs/chain.log.idx), so that the
+// reader seeks to a time instead of scanning a whole file.
+//
+// Every `every` records, the length of the file before the record and the
+// time it is appended at go to the index, as 16 bytes: the offset then the
+// microseconds since the Unix epoch, little-endian. The sidecar follows its
+// file through the rotations and goes away with the compression.
+
+use parking_lot::Mutex;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Records may reach the file a little out of order, from several threads
+// or batched, and the text layout is precise to the second
+const SLACK: Duration = Duration::from_secs(1);
+
+// The sidecar of the log file `path`.
+pub fn path(log: &Path) -> PathBuf {
+    let mut path = log.as_os_str().to_owned();
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+// Renames the sidecar of `from`, if any, with its file.
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(path(from), path(to)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+// Deletes the sidecar of `log`, if any.
+pub fn remove(log: &Path) {
+    let _ = fs::remove_file(path(log));
+}
+
+pub struct IndexWriter {
+    every: u64,
+    records: AtomicU64,
+    file: Mutex<File>,
+}
+
+impl IndexWriter {
+    // Appends to the sidecar of `log`, which holds `len` bytes already.
+    pub fn open(log: &Path, every: u64, len: u64) -> io::Result<IndexWriter> {
+        let mut options = OpenOptions::new();
+        // The entries of a file started over are stale
+        match len {
+            0 => options.write(true).truncate(true),
+            _ => options.append(true),
+        };
+        Ok(IndexWriter {
+            every: every.max(1),
+            records: AtomicU64::new(0),
+            file: Mutex::new(options.create(true).open(path(log))?),
+        })
+    }
+
+    // Notes a record appended at `offset`.
+    pub fn record(&self, offset: u64) -> io::Result<()> {
+        if !self
+            .records
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.every)
+        {
+            return Ok(());
+        }
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        let mut entry = [0u8; 16];
+        entry[..8].copy_from_slice(&offset.to_le_bytes());
+        entry[8..].copy_from_slice(&micros.to_le_bytes());
+        self.file.lock().write_all(&entry)
+    }
+}
+
+// An offset of `log` before every record appended at or after `time`, 0
+// without a sidecar.
+pub fn seek_offset(log: &Path, time: SystemTime) -> io::Result<u64> {
+    let mut entries = Vec::new();
+    match File::open(path(log)) {
+        Ok(mut file) => file.read_to_end(&mut entries)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let before = time
+        .checked_sub(SLACK)
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_micros() as u64);
+    let mut offset = 0;
+    for entry in entries.chunks_exact(16) {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&entry[8..]);
+        if u64::from_le_bytes(bytes) <= before {
+            bytes.copy_from_slice(&entry[..8]);
+            offset = offset.max(u64::from_le_bytes(bytes));
+        }
+    }
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{seek_offset, IndexWriter};
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn seeks_before_the_time() {
+        let dir = std::env::temp_dir().join(format!("cita-logger-index-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("chain.log");
+        let index = IndexWriter::open(&log, 2, 0).unwrap();
+        for offset in [0, 100, 200, 300] {
+            index.record(offset).unwrap();
+        }
+        assert_eq!(fs::metadata(super::path(&log)).unwrap().len(), 32);
+
+        let start = SystemTime::now();
+        assert_eq!(seek_offset(&log, start).unwrap(), 0);
+        let later = SystemTime::now() + Duration::from_secs(2);
+        assert_eq!(seek_offset(&log, later).unwrap(), 200);
+        assert_eq!(seek_offset(&dir.join("network.log"), later).unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Independent loggers of the components embedded in one process.
+
+use crate::append::file::SyncPolicy;
+use log::LevelFilter;
+
+/// A logger of its own for one component of the process, e.g. the executor
+/// embedded in the chain binary, set with `Builder::instance`.
+///
+/// Its records go to `logs/{name}.log` (or the console, prefixed with its
+/// name) instead of the log of the service, and rotate along with it.
+#[derive(Debug, Clone)]
+pub struct LoggerInstance {
+    pub(crate) name: String,
+    pub(crate) level: Option<LevelFilter>,
+    pub(crate) sync: Option<SyncPolicy>,
+}
+
+impl LoggerInstance {
+    pub fn new(name: &str) -> LoggerInstance {
+        LoggerInstance {
+            name: name.to_string(),
+            level: None,
+            sync: None,
+        }
+    }
+
+    /// Sets the level of the component, overridden by `RUST_LOG`.
+    pub fn level(mut self, level: LevelFilter) -> LoggerInstance {
+        self.level = Some(level);
+        self
+    }
+
+    /// Sets when its log file is synced to disk, instead of the policy of
+    /// the service (see `Builder::sync`).
+    pub fn sync(mut self, sync: SyncPolicy) -> LoggerInstance {
+        self.sync = Some(sync);
+        self
+    }
+}
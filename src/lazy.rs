@@ -0,0 +1,153 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Records whose message is only computed when they are logged, instead of
+// checking `log_enabled!` by hand around an expensive formatting.
+
+/// Logs the message returned by closure `f`, which is only called if the
+/// record passes the level filters.
+///
+/// The closure returns anything `Display`, usually a `String`.
+///
+/// ```no_run
+/// use log::Level;
+///
+/// # fn summary(state: &[u64]) -> String { format!("{:?}", state) }
+/// let state = vec![1024u64, 1025];
+/// cita_logger::lazy_log!(Level::Debug, || summary(&state));
+/// cita_logger::lazy_debug!(target: "chain::executor", || summary(&state));
+/// ```
+#[macro_export]
+macro_rules! lazy_log {
+    (target: $target:expr, $level:expr, $f:expr $(,)?) => {{
+        let level = $level;
+        if $crate::log_enabled!(target: $target, level) {
+            $crate::log!(target: $target, level, "{}", ($f)());
+        }
+    }};
+    ($level:expr, $f:expr $(,)?) => {
+        $crate::lazy_log!(target: module_path!(), $level, $f)
+    };
+}
+
+/// `lazy_log!` at error level.
+#[macro_export]
+macro_rules! lazy_error {
+    (target: $target:expr, $f:expr $(,)?) => {
+        $crate::lazy_log!(target: $target, $crate::__Level::Error, $f)
+    };
+    ($f:expr $(,)?) => {
+        $crate::lazy_log!($crate::__Level::Error, $f)
+    };
+}
+
+/// `lazy_log!` at warn level.
+#[macro_export]
+macro_rules! lazy_warn {
+    (target: $target:expr, $f:expr $(,)?) => {
+        $crate::lazy_log!(target: $target, $crate::__Level::Warn, $f)
+    };
+    ($f:expr $(,)?) => {
+        $crate::lazy_log!($crate::__Level::Warn, $f)
+    };
+}
+
+/// `lazy_log!` at info level.
+#[macro_export]
+macro_rules! lazy_info {
+    (target: $target:expr, $f:expr $(,)?) => {
+        $crate::lazy_log!(target: $target, $crate::__Level::Info, $f)
+    };
+    ($f:expr $(,)?) => {
+        $crate::lazy_log!($crate::__Level::Info, $f)
+    };
+}
+
+/// `lazy_log!` at debug level.
+#[macro_export]
+macro_rules! lazy_debug {
+    (target: $target:expr, $f:expr $(,)?) => {
+        $crate::lazy_log!(target: $target, $crate::__Level::Debug, $f)
+    };
+    ($f:expr $(,)?) => {
+        $crate::lazy_log!($crate::__Level::Debug, $f)
+    };
+}
+
+/// `lazy_log!` at trace level.
+#[macro_export]
+macro_rules! lazy_trace {
+    (target: $target:expr, $f:expr $(,)?) => {
+        $crate::lazy_log!(target: $target, $crate::__Level::Trace, $f)
+    };
+    ($f:expr $(,)?) => {
+        $crate::lazy_log!($crate::__Level::Trace, $f)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::{self, Filter};
+    use crate::{logger, parse_env, with_thread_level};
+    use log::{Level, LevelFilter, Record};
+    use log4rs::append::Append;
+    use log4rs::config::{Appender, Config, Root};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Count(Arc<AtomicUsize>);
+
+    impl Append for Count {
+        fn append(&self, record: &Record) -> anyhow::Result<()> {
+            if record.target() == "sampled" {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn called_only_when_enabled() {
+        let calls = Cell::new(0);
+        let summary = || {
+            calls.set(calls.get() + 1);
+            "state"
+        };
+        // Whether a logger is installed depends on the tests run before
+        let enabled = with_thread_level(LevelFilter::Trace, || {
+            lazy_trace!(summary);
+            lazy_log!(target: "chain::executor", Level::Debug, summary);
+            log::log_enabled!(Level::Trace)
+        });
+        assert_eq!(calls.get(), if enabled { 2 } else { 0 });
+    }
+
+    #[test]
+    fn sampled_once() {
+        let kept = Arc::new(AtomicUsize::new(0));
+        filter::install(Filter::new(
+            LevelFilter::Off,
+            parse_env("sampled=debug@0.5"),
+            None,
+        ));
+        let config = Config::builder()
+            .appender(Appender::builder().build("count", Box::new(Count(kept.clone()))))
+            .build(Root::builder().appender("count").build(LevelFilter::Trace))
+            .unwrap();
+        logger::init(config).unwrap();
+
+        for _ in 0..10_000 {
+            lazy_debug!(target: "sampled", || "block");
+        }
+        let kept = kept.load(Ordering::Relaxed);
+        assert!(kept > 4_500 && kept < 5_500, "{}", kept);
+    }
+}
@@ -5,22 +5,129 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms
 
+#[doc(hidden)]
+pub use log::kv as __kv;
+#[doc(hidden)]
+pub use log::Level as __Level;
 pub use log::{debug, error, info, log, log_enabled, trace, warn};
 
-use chrono::Local;
-use crossbeam_channel::{bounded, Receiver};
-use libc::c_int;
+mod alert;
+mod append;
+#[cfg(feature = "s3")]
+mod archive;
+mod audit;
+mod banner;
+mod builder;
+mod child;
+#[cfg(feature = "clap")]
+pub mod clap;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+mod compress;
+mod config;
+mod container;
+#[cfg(unix)]
+mod control;
+mod crash;
+mod describe;
+mod disk;
+pub mod encode;
+mod error;
+mod error_chain;
+mod event;
+#[cfg(feature = "zipkin")]
+mod export;
+mod filter;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod handle;
+mod health;
+mod index;
+mod instance;
+mod lazy;
+mod live;
+mod logger;
+mod manifest;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+pub mod preset;
+pub mod reader;
+mod ring;
+mod rotate;
+mod sample;
+mod slow;
+mod span;
+mod stats;
+mod target;
+#[cfg(feature = "tokio")]
+mod task;
+mod trace;
+#[cfg(feature = "websocket")]
+mod websocket;
+
+pub use crate::alert::{set_error_rate_alert, ErrorRateAlert};
+#[cfg(feature = "amqp")]
+pub use crate::append::amqp::Amqp;
+pub use crate::append::chain::{verify_chain, verify_chains, ChainAppender, ChainError};
+#[cfg(unix)]
+pub use crate::append::datagram::DatagramAppender;
+#[cfg(feature = "smtp")]
+pub use crate::append::email::Email;
+#[cfg(windows)]
+pub use crate::append::eventlog::EventLogAppender;
+pub use crate::append::fallback::lost_records;
+#[cfg(unix)]
+pub use crate::append::fifo::FifoAppender;
+pub use crate::append::file::SyncPolicy;
+#[cfg(target_os = "macos")]
+pub use crate::append::oslog::OsLogAppender;
+#[cfg(feature = "webhook")]
+pub use crate::append::webhook::{Webhook, WebhookFormat};
+pub use crate::append::{register_appender, Appender, AppenderContext};
+#[cfg(feature = "s3")]
+pub use crate::archive::S3Archive;
+#[doc(hidden)]
+pub use crate::audit::__audit;
+pub use crate::audit::Audit;
+pub use crate::banner::{log_startup_banner, shutdown};
+pub use crate::builder::Builder;
+pub use crate::child::ChildLogger;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub use crate::compress::Compression;
+pub use crate::config::{check, ConfigError, ConfigProblem, LoggerConfig};
+pub use crate::describe::{describe_config, ConfigSummary};
+pub use crate::disk::DiskAction;
+#[cfg(feature = "encryption")]
+pub use crate::encode::encrypt::EncryptionKey;
+pub use crate::encode::{Encoder, LogFormat, TimeFormat};
+pub use crate::error::{set_error_handler, InternalError, InternalErrorKind};
+pub use crate::error_chain::ErrorChain;
+#[doc(hidden)]
+pub use crate::event::__event;
+#[cfg(feature = "zipkin")]
+pub use crate::export::ZipkinExporter;
+pub use crate::filter::{level_for, reset_module_level, set_module_level, with_thread_level};
+pub use crate::handle::LoggerHandle;
+pub use crate::health::{health, HealthReport};
+pub use crate::instance::LoggerInstance;
+pub use crate::live::{subscribe, RecordFilter};
+pub use crate::preset::{init_preset, Preset, ServicePreset};
+pub use crate::ring::{query_recent, recent_records, RecentRecord};
+pub use crate::rotate::{LogLayout, RotationPolicy};
+#[doc(hidden)]
+pub use crate::slow::__slow_log;
+#[doc(hidden)]
+pub use crate::span::__span;
+pub use crate::span::Span;
+pub use crate::stats::{stats, LevelCounts, Stats};
+#[cfg(feature = "tokio")]
+pub use crate::task::{insert_task_mdc, remove_task_mdc, InstrumentLog, Instrumented, LogContext};
+pub use crate::trace::{TraceContext, TraceScope};
+
 use log::LevelFilter;
-use log4rs::append::console::ConsoleAppender;
-use log4rs::append::file::FileAppender;
-use log4rs::config::{Appender, Config, Logger, Root};
-use log4rs::encode::pattern::PatternEncoder;
-use std::env;
-use std::fs;
-use std::io::Error;
+use log4rs::config::{Config, Root};
+use regex::Regex;
 use std::str::FromStr;
 use std::sync::Once;
-use std::thread;
 use std::vec::Vec;
 
 pub enum LogFavour<'a> {
@@ -29,77 +136,36 @@ pub enum LogFavour<'a> {
 }
 
 #[derive(Debug, Clone)]
-struct Directive {
-    // Module name
-    name: String,
+pub(crate) struct Directive {
+    // Module name, empty for the global level
+    pub(crate) name: String,
     // Log level
-    level: LevelFilter,
+    pub(crate) level: LevelFilter,
+    // Fraction of the info, debug and trace records kept
+    pub(crate) sample: Option<f64>,
 }
 
-static INIT_LOG: Once = Once::new();
+pub(crate) static INIT_LOG: Once = Once::new();
 
-fn notify(signals: &[c_int]) -> Result<Receiver<c_int>, Error> {
-    let (s, r) = bounded(100);
-    let mut signals = signal_hook::iterator::Signals::new(signals)?;
-    thread::spawn(move || {
-        for signal in signals.forever() {
-            let _ = s.send(signal);
-        }
-    });
-    Ok(r)
+pub fn init_config(favour: &LogFavour) -> LoggerHandle {
+    Builder::new(favour).init()
 }
 
-pub fn init_config(favour: &LogFavour) {
-    INIT_LOG.call_once(|| {
-        // Parse RUST_LOG
-        let directives: Vec<Directive> = match env::var("RUST_LOG") {
-            Ok(s) => parse_env(&s),
-            Err(_) => Vec::new(),
-        };
-
-        match favour {
-            LogFavour::Stdout(service_name) => {
-                let config = config_console_appender(service_name, directives);
-                log4rs::init_config(config).unwrap();
-            }
-            LogFavour::File(service_name) => {
-                // The config of log4rs
-                let log_name = format!("logs/{}.log", service_name);
-                let directives_clone = directives.clone();
-                let config = config_file_appender(&log_name, directives_clone);
-                let handle = log4rs::init_config(config).unwrap();
-
-                // Log rotate via signal(USR1)
-                let signal = notify(&[signal_hook::consts::SIGUSR1]).unwrap();
-
-                // Any and all threads spawned must come after the first call to notify (or notify_on).
-                // This is so all spawned threads inherit the blocked status of signals.
-                // If a thread starts before notify is called, it will not have the correct signal mask.
-                // When a signal is delivered, the result is indeterminate.
-                let service_name_clone = service_name.to_string();
-                thread::spawn(move || {
-                    loop {
-                        // Blocks until this process is sent an USR1 signal.
-                        signal.recv().unwrap();
-
-                        // Rotate current log file
-                        let time_stamp = Local::now().format("_%Y-%m-%d_%H-%M-%S");
-                        let log_rotate_name =
-                            format!("logs/{}{}.log", &service_name_clone, time_stamp);
-                        if let Err(e) = fs::rename(&log_name, log_rotate_name) {
-                            warn!("logrotate failed because of {:?}", e.kind());
-                            continue;
-                        }
-
-                        // Reconfig
-                        let directives_clone = directives.clone();
-                        let new_config = config_file_appender(&log_name, directives_clone);
-                        handle.set_config(new_config);
-                    }
-                });
-            }
-        }
-    });
+/// Logs to the console at a level given by a verbosity count, e.g. the
+/// number of `-v` minus the number of `-q` flags of a command line tool.
+///
+/// -2 is off, -1 error, 0 warn, 1 info, 2 debug and 3 or more trace.
+/// `RUST_LOG` still takes precedence.
+pub fn init_with_verbosity(service_name: &str, verbosity: i8) {
+    Builder::new(&LogFavour::Stdout(service_name))
+        .directive("", verbosity_level(verbosity))
+        .init();
+}
+
+fn verbosity_level(verbosity: i8) -> LevelFilter {
+    LevelFilter::iter()
+        .nth((i32::from(verbosity) + 2).clamp(0, 5) as usize)
+        .unwrap_or(LevelFilter::Trace)
 }
 
 // Used in tests
@@ -117,8 +183,27 @@ pub fn silent() {
     });
 }
 
-// Simple parse env (e.g: crate1,crate2::mod=debug,crate3::mod=trace)
-fn parse_env(env: &str) -> Vec<Directive> {
+// Parse env with an optional message filter (e.g: chain=debug/block \d+)
+pub(crate) fn parse_spec(env: &str) -> (Vec<Directive>, Option<Regex>) {
+    let (directives, message) = match env.split_once('/') {
+        Some((directives, message)) => (directives, Some(message)),
+        None => (env, None),
+    };
+    let message = message.and_then(|message| match Regex::new(message) {
+        Ok(regex) => Some(regex),
+        Err(e) => {
+            println!(
+                "warning: invalid regex filter '{}', ignoring it: {}",
+                message, e
+            );
+            None
+        }
+    });
+    (parse_env(directives), message)
+}
+
+// Simple parse env (e.g: crate1,crate2::mod=debug,crate3::mod=trace@10%)
+pub(crate) fn parse_env(env: &str) -> Vec<Directive> {
     let mut directives = Vec::new();
 
     for s in env.split(',') {
@@ -126,43 +211,47 @@ fn parse_env(env: &str) -> Vec<Directive> {
             continue;
         }
         let mut parts = s.split('=');
-        let (log_level, name) = match (parts.next(), parts.next().map(str::trim), parts.next()) {
-            (Some(part0), None, None) => match LevelFilter::from_str(part0) {
-                Ok(num) => {
-                    println!(
-                        "warning: log level '{}' need explicit crate or module name.",
-                        num
-                    );
-                    continue;
-                }
-                Err(_) => (LevelFilter::Info, part0),
-            },
-            (Some(part0), Some(""), None) => (LevelFilter::Info, part0),
-            (Some(part0), Some(part1), None) => match LevelFilter::from_str(part1) {
-                Ok(num) => (num, part0),
+        let (log_level, name, sample) =
+            match (parts.next(), parts.next().map(str::trim), parts.next()) {
+                (Some(part0), None, None) => match parse_level(part0) {
+                    // The global level
+                    Some((num, sample)) => {
+                        directives.push(Directive {
+                            name: String::new(),
+                            level: num,
+                            sample,
+                        });
+                        continue;
+                    }
+                    None => (LevelFilter::Info, part0, None),
+                },
+                (Some(part0), Some(""), None) => (LevelFilter::Info, part0, None),
+                (Some(part0), Some(part1), None) => match parse_level(part1) {
+                    Some((num, sample)) => (num, part0, sample),
+                    None => {
+                        println!(
+                            "warning: invalid logging spec '{}', \
+                         ignoring it",
+                            part1
+                        );
+                        continue;
+                    }
+                },
                 _ => {
                     println!(
                         "warning: invalid logging spec '{}', \
-                         ignoring it",
-                        part1
+                     ignoring it",
+                        s
                     );
                     continue;
                 }
-            },
-            _ => {
-                println!(
-                    "warning: invalid logging spec '{}', \
-                     ignoring it",
-                    s
-                );
-                continue;
-            }
-        };
+            };
 
         if !name.is_empty() {
             directives.push(Directive {
                 name: name.to_string(),
                 level: log_level,
+                sample,
             });
         }
     }
@@ -170,82 +259,42 @@ fn parse_env(env: &str) -> Vec<Directive> {
     directives
 }
 
-fn create_loggers(directives: Vec<Directive>, appender: &str) -> Vec<Logger> {
-    let mut loggers = Vec::new();
-
-    if directives.is_empty() {
-        return loggers;
-    }
-
-    // Create loggers via module/crate and log level
-    for directive in directives {
-        let appender_clone = appender.to_string();
-        let logger = Logger::builder()
-            .appender(appender_clone)
-            .additive(false)
-            .build(directive.name, directive.level);
-        loggers.push(logger);
+// Parses a level name, or a number from 0 (off) to 5 (trace).
+pub(crate) fn level_from_str(level: &str) -> Option<LevelFilter> {
+    match level.parse::<usize>() {
+        Ok(n) => LevelFilter::iter().nth(n),
+        Err(_) => LevelFilter::from_str(level).ok(),
     }
-
-    loggers
 }
 
-// FileAppender config
-fn config_file_appender(file_path: &str, directives: Vec<Directive>) -> Config {
-    let requests = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d - %H:%M:%S)} | {t:20.20} - {L:5} | {l:5} - {m}{n}",
-        )))
-        .build(file_path)
-        .unwrap();
-
-    let mut config_builder =
-        Config::builder().appender(Appender::builder().build("requests", Box::new(requests)));
-
-    let loggers = create_loggers(directives, "requests");
-
-    // Config crate or module log level
-    if !loggers.is_empty() {
-        config_builder = config_builder.loggers(loggers.into_iter());
-    }
-
-    // Config global log level
-    config_builder
-        .build(
-            Root::builder()
-                .appender("requests")
-                .build(LevelFilter::Info),
-        )
-        .unwrap()
-}
-
-// ConsoleAppender config
-fn config_console_appender(service_name: &str, directives: Vec<Directive>) -> Config {
-    let pattern = format!("[{}]: ", service_name) + "{d} - {l} - {m}{n}";
-    let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(&pattern)))
-        .build();
-
-    let mut config_builder =
-        Config::builder().appender(Appender::builder().build("stdout", Box::new(stdout)));
-
-    let loggers = create_loggers(directives, "stdout");
-
-    // Config crate or module log level
-    if !loggers.is_empty() {
-        config_builder = config_builder.loggers(loggers.into_iter());
-    }
-
-    // Config global log level
-    config_builder
-        .build(Root::builder().appender("stdout").build(LevelFilter::Info))
-        .unwrap()
+// Parses a level with an optional sampling rate, e.g. `debug@1%` or
+// `debug@0.01`.
+pub(crate) fn parse_level(spec: &str) -> Option<(LevelFilter, Option<f64>)> {
+    let (level, sample) = match spec.split_once('@') {
+        Some((level, sample)) => (level, Some(sample.trim())),
+        None => (spec, None),
+    };
+    let level = level_from_str(level.trim())?;
+    let sample = match sample {
+        Some(sample) => {
+            let rate = match sample.strip_suffix('%') {
+                Some(percent) => f64::from_str(percent).ok()? / 100.0,
+                None => f64::from_str(sample).ok()?,
+            };
+            if !(0.0..=1.0).contains(&rate) {
+                return None;
+            }
+            Some(rate)
+        }
+        None => None,
+    };
+    Some((level, sample))
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::parse_env;
+    use super::{parse_env, parse_spec, verbosity_level};
     use log::LevelFilter;
 
     #[test]
@@ -278,6 +327,53 @@ mod tests {
         assert_eq!(directives[0].level, LevelFilter::Error);
     }
 
+    #[test]
+    fn parse_env_global() {
+        let directives = parse_env("debug,crate1=warn,off");
+        assert_eq!(directives.len(), 3);
+        assert_eq!(directives[0].name, "");
+        assert_eq!(directives[0].level, LevelFilter::Debug);
+        assert_eq!(directives[2].name, "");
+        assert_eq!(directives[2].level, LevelFilter::Off);
+    }
+
+    #[test]
+    fn parse_env_numeric() {
+        let directives = parse_env("crate1=4,crate2=0,crate3=6");
+        assert_eq!(directives.len(), 2);
+        assert_eq!(directives[0].level, LevelFilter::Debug);
+        assert_eq!(directives[1].level, LevelFilter::Off);
+    }
+
+    #[test]
+    fn parse_env_sample() {
+        let directives = parse_env("network::sync=debug@1%,chain=trace@0.5,auth=info@200%");
+        assert_eq!(directives.len(), 2);
+        assert_eq!(directives[0].level, LevelFilter::Debug);
+        assert_eq!(directives[0].sample, Some(0.01));
+        assert_eq!(directives[1].sample, Some(0.5));
+    }
+
+    #[test]
+    fn parse_spec_regex() {
+        let (directives, message) = parse_spec("chain=debug/block \\d+");
+        assert_eq!(directives.len(), 1);
+        assert!(message.unwrap().is_match("new block 42"));
+
+        let (directives, message) = parse_spec("chain=debug/block (");
+        assert_eq!(directives.len(), 1);
+        assert!(message.is_none());
+    }
+
+    #[test]
+    fn verbosity() {
+        assert_eq!(verbosity_level(-5), LevelFilter::Off);
+        assert_eq!(verbosity_level(-1), LevelFilter::Error);
+        assert_eq!(verbosity_level(0), LevelFilter::Warn);
+        assert_eq!(verbosity_level(3), LevelFilter::Trace);
+        assert_eq!(verbosity_level(9), LevelFilter::Trace);
+    }
+
     #[test]
     fn parse_env_empty() {
         let directives = parse_env("crate1::mod=,=trace");
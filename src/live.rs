@@ -0,0 +1,165 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// The records logged, as they are logged, for the subscribers of the
+// process and those streaming them to remote tools.
+//
+// Without subscribers a record costs an atomic load.
+
+use crate::ring::RecentRecord;
+use log::{Level, LevelFilter, Record};
+use parking_lot::{const_rwlock, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::time::SystemTime;
+
+// Records queued for a receiver of `subscribe`, more are dropped while it
+// lags behind
+const QUEUE_SIZE: usize = 1024;
+
+// Takes a record, false once the subscriber is gone
+pub(crate) type Sink = Box<dyn Fn(&RecentRecord) -> bool + Send + Sync>;
+
+/// The records wanted by a subscriber, see `subscribe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordFilter {
+    pub(crate) level: LevelFilter,
+    // A prefix of the targets, e.g. `cita_network` for it and its modules
+    pub(crate) target: String,
+}
+
+impl RecordFilter {
+    /// The records at or above `level`, of any target.
+    pub fn new(level: LevelFilter) -> RecordFilter {
+        RecordFilter {
+            level,
+            target: String::new(),
+        }
+    }
+
+    /// Only the records whose target starts with `prefix`, e.g.
+    /// `cita_network` for it and its modules.
+    pub fn target(mut self, prefix: &str) -> RecordFilter {
+        self.target = prefix.to_string();
+        self
+    }
+
+    pub(crate) fn matches(&self, level: Level, target: &str) -> bool {
+        level <= self.level && target.starts_with(&self.target)
+    }
+}
+
+struct Subscriber {
+    filter: RecordFilter,
+    sink: Sink,
+    gone: AtomicBool,
+}
+
+static SUBSCRIBERS: RwLock<Vec<Subscriber>> = const_rwlock(Vec::new());
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Receives the records matching `filter` logged from now on, e.g. for the
+/// admin module of the jsonrpc service to publish them as events.
+///
+/// The records are those the level filters let through. Up to 1024 wait
+/// in the receiver, more are dropped, and dropping the receiver ends the
+/// subscription.
+///
+/// ```no_run
+/// use cita_logger::{subscribe, RecordFilter};
+/// use log::LevelFilter;
+///
+/// let records = subscribe(RecordFilter::new(LevelFilter::Warn).target("cita_network"));
+/// for record in records {
+///     println!("{}", record);
+/// }
+/// ```
+pub fn subscribe(filter: RecordFilter) -> Receiver<RecentRecord> {
+    let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+    subscribe_with(
+        filter,
+        Box::new(move |record| match sender.try_send(record.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        }),
+    );
+    receiver
+}
+
+// Passes the records matching `filter` to `sink` until it returns false.
+pub(crate) fn subscribe_with(filter: RecordFilter, sink: Sink) {
+    let mut subscribers = SUBSCRIBERS.write();
+    subscribers.push(Subscriber {
+        filter,
+        sink,
+        gone: AtomicBool::new(false),
+    });
+    COUNT.store(subscribers.len(), Ordering::Relaxed);
+}
+
+pub(crate) fn publish(record: &Record) {
+    if COUNT.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let mut entry = None;
+    let mut gone = false;
+    for subscriber in SUBSCRIBERS.read().iter() {
+        if subscriber.filter.matches(record.level(), record.target())
+            && !subscriber.gone.load(Ordering::Relaxed)
+        {
+            let entry = entry.get_or_insert_with(|| RecentRecord {
+                time: SystemTime::now(),
+                level: record.level(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+            if !(subscriber.sink)(entry) {
+                subscriber.gone.store(true, Ordering::Relaxed);
+                gone = true;
+            }
+        }
+    }
+    if gone {
+        let mut subscribers = SUBSCRIBERS.write();
+        subscribers.retain(|subscriber| !subscriber.gone.load(Ordering::Relaxed));
+        COUNT.store(subscribers.len(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{publish, subscribe, RecordFilter, SUBSCRIBERS};
+    use log::{Level, LevelFilter, Record};
+
+    fn log(level: Level, target: &str, message: &str) {
+        publish(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn filtered_subscribers() {
+        let receiver = subscribe(RecordFilter::new(LevelFilter::Info).target("live_test::network"));
+        log(Level::Info, "live_test::network::sync", "synced");
+        log(Level::Debug, "live_test::network", "too verbose");
+        log(Level::Error, "live_test::chain", "other target");
+        let messages: Vec<_> = receiver.try_iter().map(|record| record.message).collect();
+        assert_eq!(messages, ["synced"]);
+
+        // Gone once the receiver is dropped
+        drop(receiver);
+        log(Level::Warn, "live_test::network", "dropped");
+        assert!(!SUBSCRIBERS
+            .read()
+            .iter()
+            .any(|subscriber| subscriber.filter.target == "live_test::network"));
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// The logger installed into `log`, wrapping the log4rs one.
+//
+// Levels are filtered here (see `filter`), the log4rs config only routes
+// records to the appenders.
+//
+// log4rs resets the error handler whenever its config is replaced, so the
+// config is replaced here by swapping in a whole new log4rs logger instead.
+
+use crate::alert;
+use crate::error::{self, InternalErrorKind};
+use crate::filter;
+use crate::health;
+use crate::live;
+use crate::ring;
+use crate::stats;
+use arc_swap::ArcSwap;
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use log4rs::config::Config;
+use std::io;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+pub struct Logger {
+    inner: ArcSwap<log4rs::Logger>,
+}
+
+static LOGGER: OnceLock<&'static Logger> = OnceLock::new();
+
+impl Logger {
+    // Replaces the running config, e.g. after a log rotation.
+    pub fn set_config(&self, config: Config) {
+        let inner = new_inner(config);
+        log::set_max_level(max_level());
+        self.inner.store(Arc::new(inner));
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= ring::level() || filter::enabled(metadata.level(), metadata.target())
+    }
+
+    // Records filtered out cost a few atomic loads and the directive lookup,
+    // without formatting or allocating anything: the message, the MDC and
+    // the log4rs logger are only touched past the filter.
+    fn log(&self, record: &Record) {
+        ring::push(record);
+        if !filter::enabled(record.level(), record.target())
+            || !filter::sampled(record.level(), record.target())
+            || !filter::matches(record.args())
+        {
+            return;
+        }
+        stats::count(record.level(), record.target());
+        if record.level() == Level::Error {
+            alert::count_error();
+        }
+        live::publish(record);
+        let errors = error::count();
+        self.inner.load().log(record);
+        if error::count() == errors {
+            health::mark_write();
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.load().flush()
+    }
+}
+
+fn new_inner(config: Config) -> log4rs::Logger {
+    log4rs::Logger::new_with_err_handler(
+        config,
+        Box::new(|e: &anyhow::Error| error::report(InternalErrorKind::Append, e)),
+    )
+}
+
+// Writes `record` to the appenders past the filters, e.g. an audit event.
+// False if the logger of `log` is not this one.
+pub(crate) fn log_unfiltered(record: &Record) -> bool {
+    match LOGGER.get() {
+        Some(logger) => {
+            logger.inner.load().log(record);
+            true
+        }
+        None => false,
+    }
+}
+
+// The ring buffer may keep records which the filter drops.
+pub(crate) fn max_level() -> LevelFilter {
+    filter::max_level().max(ring::level())
+}
+
+// Flushes all appenders every `interval`, bounding what a crash loses.
+pub(crate) fn spawn_flush(interval: Duration) -> io::Result<()> {
+    thread::Builder::new()
+        .name("cita-logger-timer".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            log::logger().flush();
+        })?;
+    Ok(())
+}
+
+pub fn init(config: Config) -> Result<&'static Logger, SetLoggerError> {
+    let inner = new_inner(config);
+    let max_level = max_level();
+    let logger: &'static Logger = Box::leak(Box::new(Logger {
+        inner: ArcSwap::from_pointee(inner),
+    }));
+    log::set_logger(logger)?;
+    let _ = LOGGER.set(logger);
+    log::set_max_level(max_level);
+    Ok(logger)
+}
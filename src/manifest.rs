@@ -0,0 +1,104 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// A checksum manifest of the rotated files of a service (e.g:
+// logs/chain.manifest), for the archival pipelines to verify them.
+//
+// A line per rotated file still in the directory, oldest first: its
+// SHA-256 in hex, its length in bytes and its path relative to the
+// directory, separated by a space. The manifest is replaced atomically.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub fn path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.manifest", name))
+}
+
+// Adds the files of `rotated` to the manifest of `name` in `dir`, and drops
+// those gone, e.g. pruned.
+pub fn update(dir: &Path, name: &str, rotated: &[&Path]) -> io::Result<()> {
+    let manifest = path(dir, name);
+    let mut lines: Vec<String> = match fs::read_to_string(&manifest) {
+        Ok(text) => text.lines().map(str::to_string).collect(),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let mut added = HashSet::new();
+    for file in rotated {
+        let relative = file.strip_prefix(dir).unwrap_or(file);
+        let (digest, len) = digest(file)?;
+        lines.push(format!("{} {} {}", digest, len, relative.display()));
+        added.insert(relative.to_path_buf());
+    }
+    // Rotated again under the same name, or gone
+    let mut seen = HashSet::new();
+    let mut kept: Vec<String> = lines
+        .into_iter()
+        .rev()
+        .filter(|line| {
+            let relative = match line.splitn(3, ' ').nth(2) {
+                Some(relative) => PathBuf::from(relative),
+                None => return false,
+            };
+            dir.join(&relative).is_file() && seen.insert(relative)
+        })
+        .collect();
+    kept.reverse();
+
+    let tmp = manifest.with_extension("manifest.tmp");
+    let mut file = File::create(&tmp)?;
+    for line in &kept {
+        writeln!(file, "{}", line)?;
+    }
+    file.sync_data()?;
+    fs::rename(&tmp, &manifest)
+}
+
+fn digest(path: &Path) -> io::Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let len = io::copy(&mut File::open(path)?, &mut hasher)?;
+    let digest = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok((digest, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::update;
+    use std::fs;
+
+    #[test]
+    fn updated_after_rotations() {
+        let dir = std::env::temp_dir().join(format!("cita-logger-manifest-{}", std::process::id()));
+        fs::create_dir_all(dir.join("chain/2019/05/16")).unwrap();
+        let first = dir.join("chain_2019-05-16_10-00-00.log");
+        let second = dir.join("chain/2019/05/16/chain_2019-05-16_11-00-00.log");
+        fs::write(&first, "abc").unwrap();
+        fs::write(&second, "").unwrap();
+        update(&dir, "chain", &[&first]).unwrap();
+        update(&dir, "chain", &[&second]).unwrap();
+        let manifest = fs::read_to_string(dir.join("chain.manifest")).unwrap();
+        assert_eq!(
+            manifest,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad 3 chain_2019-05-16_10-00-00.log\n\
+             e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 0 chain/2019/05/16/chain_2019-05-16_11-00-00.log\n"
+        );
+
+        fs::remove_file(&first).unwrap();
+        update(&dir, "chain", &[]).unwrap();
+        let manifest = fs::read_to_string(dir.join("chain.manifest")).unwrap();
+        assert_eq!(manifest.lines().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! Prometheus metrics of the logging pipeline.
+//!
+//! Requires the `prometheus` feature.
+
+use crate::append::fallback;
+use crate::stats;
+use log::Level;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{IntCounter, IntCounterVec, Opts, Registry};
+
+const LEVELS: [Level; 5] = [
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+    Level::Trace,
+];
+
+/// Registers `log_records_total{level,target}`, `log_dropped_total` and
+/// `log_rotations_total` with `registry`.
+///
+/// The values are read from the crate's own counters on every scrape, so
+/// logging itself pays nothing for the metrics.
+pub fn register(registry: &Registry) -> prometheus::Result<()> {
+    registry.register(Box::new(LogCollector::new()?))
+}
+
+struct LogCollector {
+    descs: Vec<Desc>,
+}
+
+impl LogCollector {
+    fn new() -> prometheus::Result<LogCollector> {
+        let (records, dropped, rotations) = new_metrics()?;
+        let descs = records
+            .desc()
+            .into_iter()
+            .chain(dropped.desc())
+            .chain(rotations.desc())
+            .cloned()
+            .collect();
+        Ok(LogCollector { descs })
+    }
+}
+
+fn new_metrics() -> prometheus::Result<(IntCounterVec, IntCounter, IntCounter)> {
+    let records = IntCounterVec::new(
+        Opts::new("log_records_total", "Number of emitted log records."),
+        &["level", "target"],
+    )?;
+    let dropped = IntCounter::new(
+        "log_dropped_total",
+        "Number of log records which could not be written.",
+    )?;
+    let rotations = IntCounter::new("log_rotations_total", "Number of log file rotations.")?;
+    Ok((records, dropped, rotations))
+}
+
+impl Collector for LogCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        // The descriptors were checked in `new`.
+        let (records, dropped, rotations) = new_metrics().unwrap();
+        let stats = stats::stats();
+        for (target, counts) in &stats.targets {
+            for level in &LEVELS {
+                records
+                    .with_label_values(&[level.as_str(), target.as_str()])
+                    .inc_by(counts.get(*level));
+            }
+        }
+        dropped.inc_by(fallback::lost_records());
+        rotations.inc_by(stats.rotations);
+
+        let mut families = records.collect();
+        families.extend(dropped.collect());
+        families.extend(rotations.collect());
+        families
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::register;
+    use prometheus::Registry;
+
+    #[test]
+    fn register_collector() {
+        crate::stats::count(log::Level::Warn, "metrics_test");
+        let registry = Registry::new();
+        register(&registry).unwrap();
+
+        let families = registry.gather();
+        let names: Vec<_> = families.iter().map(|f| f.name().to_string()).collect();
+        assert!(names.contains(&"log_records_total".to_string()));
+        assert!(names.contains(&"log_dropped_total".to_string()));
+        assert!(names.contains(&"log_rotations_total".to_string()));
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! Sensible combinations of the builder settings, and the module levels
+//! recommended for each CITA service.
+
+use crate::encode::{BinaryEncoder, JsonEncoder};
+use crate::{Builder, LogFavour};
+use log::LevelFilter;
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Colored level, time with milliseconds and the source location
+const DEV_PATTERN: &str = "{h({l:5})} {d(%H:%M:%S%.3f)} {f}:{L} - {m}{n}";
+
+// The members added by the Kubernetes preset, and their variables
+const K8S_FIELDS: [(&str, &str); 3] = [
+    ("pod", "POD_NAME"),
+    ("namespace", "POD_NAMESPACE"),
+    ("node", "NODE_NAME"),
+];
+
+// The batches of the high-throughput preset, and how long its records may
+// stay in memory
+const HIGH_THROUGHPUT_BATCH: usize = 256;
+const HIGH_THROUGHPUT_LATENCY: Duration = Duration::from_millis(50);
+const HIGH_THROUGHPUT_FLUSH: Duration = Duration::from_secs(1);
+
+/// A set of builder settings for a kind of environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Colored console output with source locations, at debug level.
+    Dev,
+    /// JSON lines in the log file at info level, keeping 10 rotated files.
+    Prod,
+    /// Errors only, to the console.
+    Bench,
+    /// JSON lines on stdout at info level, with the `pod`, `namespace` and
+    /// `node` of the `POD_NAME`, `POD_NAMESPACE` and `NODE_NAME` variables
+    /// (set with the downward API).
+    Kubernetes,
+    /// Binary records (see `encode::binary`) in the log file at info level,
+    /// for nodes logging tens of thousands of records per second.
+    ///
+    /// The logging threads encode their records into batches of 256, which
+    /// a writer thread writes to the file (see `Builder::async_writes` and
+    /// `Builder::batch`), so that they do not contend for the file. A
+    /// thread thus spends less time per record than with `Prod`, which
+    /// formats JSON and writes it under the lock of the file, and the
+    /// threads scale better together (compare with `cargo bench --features
+    /// bench --bench throughput`). In exchange records reach the file up to
+    /// 50 ms late, and the file is flushed every second: a crash loses up
+    /// to a second of records, but those at warn level and above, which are
+    /// flushed at once.
+    HighThroughput,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Preset, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "dev" => Ok(Preset::Dev),
+            "prod" => Ok(Preset::Prod),
+            "bench" => Ok(Preset::Bench),
+            "k8s" | "kubernetes" => Ok(Preset::Kubernetes),
+            "high-throughput" | "throughput" => Ok(Preset::HighThroughput),
+            _ => Err(format!("unknown log preset '{}'", s)),
+        }
+    }
+}
+
+impl Preset {
+    /// The preset named by `CITA_LOG_PRESET`, if it is set and valid.
+    pub fn from_env() -> Option<Preset> {
+        let preset = env::var("CITA_LOG_PRESET").ok()?;
+        match Preset::from_str(&preset) {
+            Ok(preset) => Some(preset),
+            Err(e) => {
+                println!("warning: {}, ignoring it", e);
+                None
+            }
+        }
+    }
+
+    /// A builder with the settings of the preset, for further
+    /// configuration.
+    pub fn builder(self, service_name: &str) -> Builder {
+        match self {
+            Preset::Dev => Builder::new(&LogFavour::Stdout(service_name))
+                .directive("", LevelFilter::Debug)
+                .console_pattern(DEV_PATTERN),
+            Preset::Prod => Builder::new(&LogFavour::File(service_name))
+                .directive("", LevelFilter::Info)
+                .file_encoder(Box::new(JsonEncoder::new()))
+                .retention(10),
+            Preset::Bench => {
+                Builder::new(&LogFavour::Stdout(service_name)).directive("", LevelFilter::Error)
+            }
+            Preset::Kubernetes => {
+                let mut encoder = JsonEncoder::new();
+                for (key, var) in K8S_FIELDS {
+                    if let Ok(value) = env::var(var) {
+                        encoder = encoder.field(key, &value);
+                    }
+                }
+                Builder::new(&LogFavour::Stdout(service_name))
+                    .directive("", LevelFilter::Info)
+                    .console_encoder(Box::new(encoder))
+            }
+            Preset::HighThroughput => Builder::new(&LogFavour::File(service_name))
+                .directive("", LevelFilter::Info)
+                .file_encoder(Box::new(BinaryEncoder::new()))
+                .async_writes(true)
+                .batch(HIGH_THROUGHPUT_BATCH, HIGH_THROUGHPUT_LATENCY)
+                .flush_interval(HIGH_THROUGHPUT_FLUSH),
+        }
+    }
+}
+
+/// The module levels recommended for a CITA service, applied with
+/// `Builder::service_preset`.
+///
+/// They are directives like those of `Builder::directive`, so `RUST_LOG`
+/// still overrides them.
+///
+/// ```no_run
+/// use cita_logger::{preset, Builder, LogFavour};
+///
+/// Builder::new(&LogFavour::File("cita-bft"))
+///     .service_preset(preset::consensus())
+///     .init();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServicePreset {
+    name: &'static str,
+    directives: &'static [(&'static str, LevelFilter)],
+}
+
+impl ServicePreset {
+    /// The service, e.g. `consensus`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The modules and their levels, an empty name for the global level.
+    pub fn directives(&self) -> impl Iterator<Item = (&'static str, LevelFilter)> {
+        self.directives.iter().copied()
+    }
+}
+
+/// cita-auth: the transaction pool only when it rejects transactions.
+pub fn auth() -> ServicePreset {
+    ServicePreset {
+        name: "auth",
+        directives: &[
+            ("", LevelFilter::Info),
+            ("cita_auth", LevelFilter::Info),
+            ("tx_pool", LevelFilter::Warn),
+            ("pubsub", LevelFilter::Warn),
+        ],
+    }
+}
+
+/// cita-chain: the committed blocks, without the proof checks.
+pub fn chain() -> ServicePreset {
+    ServicePreset {
+        name: "chain",
+        directives: &[
+            ("", LevelFilter::Info),
+            ("cita_chain", LevelFilter::Info),
+            ("core::libchain", LevelFilter::Info),
+            ("proof", LevelFilter::Warn),
+            ("pubsub", LevelFilter::Warn),
+        ],
+    }
+}
+
+/// cita-bft: the rounds and the votes, the write-ahead log only when it
+/// fails.
+pub fn consensus() -> ServicePreset {
+    ServicePreset {
+        name: "consensus",
+        directives: &[
+            ("", LevelFilter::Info),
+            ("cita_bft", LevelFilter::Debug),
+            ("cita_bft::wal", LevelFilter::Warn),
+            ("authority_manage", LevelFilter::Info),
+            ("pubsub", LevelFilter::Warn),
+        ],
+    }
+}
+
+/// cita-executor: the executed blocks, not every contract call.
+pub fn executor() -> ServicePreset {
+    ServicePreset {
+        name: "executor",
+        directives: &[
+            ("", LevelFilter::Info),
+            ("cita_executor", LevelFilter::Info),
+            ("core_executor", LevelFilter::Info),
+            ("evm", LevelFilter::Warn),
+            ("state", LevelFilter::Warn),
+            ("pubsub", LevelFilter::Warn),
+        ],
+    }
+}
+
+/// cita-jsonrpc: the failed requests, not every one of them.
+pub fn jsonrpc() -> ServicePreset {
+    ServicePreset {
+        name: "jsonrpc",
+        directives: &[
+            ("", LevelFilter::Info),
+            ("cita_jsonrpc", LevelFilter::Info),
+            ("cita_jsonrpc::http_server", LevelFilter::Warn),
+            ("cita_jsonrpc::ws_handler", LevelFilter::Warn),
+            ("jsonrpc_types", LevelFilter::Warn),
+            ("ws", LevelFilter::Warn),
+            ("pubsub", LevelFilter::Warn),
+        ],
+    }
+}
+
+/// cita-network: the peers and the synchronization, not every message.
+pub fn network() -> ServicePreset {
+    ServicePreset {
+        name: "network",
+        directives: &[
+            ("", LevelFilter::Info),
+            ("cita_network", LevelFilter::Info),
+            ("cita_network::synchronizer", LevelFilter::Debug),
+            ("cita_network::node_manager", LevelFilter::Info),
+            ("discovery", LevelFilter::Warn),
+            ("tentacle", LevelFilter::Warn),
+            ("pubsub", LevelFilter::Warn),
+        ],
+    }
+}
+
+/// Installs the logger with the settings of `preset`, or of the preset
+/// named by `CITA_LOG_PRESET` (`dev`, `prod`, `bench`, `k8s` or
+/// `high-throughput`) if it is set.
+pub fn init_preset(service_name: &str, preset: Preset) {
+    Preset::from_env()
+        .unwrap_or(preset)
+        .builder(service_name)
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Preset, ServicePreset};
+    use crate::append::file::SyncPolicy;
+    use crate::encode::binary::BinaryReader;
+    use log::{Level, Record};
+    use log4rs::append::Append;
+    use std::fs::{self, File};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn from_str() {
+        assert_eq!("Prod".parse::<Preset>(), Ok(Preset::Prod));
+        assert_eq!("k8s".parse::<Preset>(), Ok(Preset::Kubernetes));
+        assert!("staging".parse::<Preset>().is_err());
+    }
+
+    #[test]
+    fn service_presets() {
+        let presets: [fn() -> ServicePreset; 6] = [
+            super::auth,
+            super::chain,
+            super::consensus,
+            super::executor,
+            super::jsonrpc,
+            super::network,
+        ];
+        for preset in presets.iter().map(|preset| preset()) {
+            let names: Vec<_> = preset.directives().map(|(name, _)| name).collect();
+            assert_eq!(names[0], "", "{}", preset.name());
+            for (i, name) in names.iter().enumerate() {
+                assert!(!names[..i].contains(name), "{} {}", preset.name(), name);
+            }
+        }
+        assert!(super::consensus()
+            .directives()
+            .any(|directive| directive == ("cita_bft", log::LevelFilter::Debug)));
+    }
+
+    #[test]
+    fn high_throughput() {
+        let dir =
+            std::env::temp_dir().join(format!("cita-logger-throughput-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let builder = Preset::HighThroughput.builder("chain");
+        // A rotation starts a new file, which defines the targets again
+        for file in ["chain.log", "chain_rotated.log"] {
+            let path = dir.join(file);
+            let appender = Arc::new(
+                builder
+                    .file_appender(path.to_str().unwrap(), "chain", SyncPolicy::Never)
+                    .unwrap(),
+            );
+            let threads: Vec<_> = (0..4)
+                .map(|thread| {
+                    let appender = appender.clone();
+                    thread::spawn(move || {
+                        for height in 0..1000 {
+                            appender
+                                .append(
+                                    &Record::builder()
+                                        .level(Level::Info)
+                                        .target(if height % 2 == 0 { "executor" } else { "auth" })
+                                        .args(format_args!("thread {} block {}", thread, height))
+                                        .build(),
+                                )
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            drop(appender);
+
+            let records = BinaryReader::new(File::open(&path).unwrap())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(records.len(), 4000);
+            for thread in 0..4 {
+                let heights: Vec<_> = records
+                    .iter()
+                    .filter_map(|record| {
+                        let message = record
+                            .message
+                            .strip_prefix(&format!("thread {} block ", thread))?;
+                        assert_eq!(
+                            record.target == "executor",
+                            message.parse::<u32>().unwrap() % 2 == 0
+                        );
+                        message.parse::<u32>().ok()
+                    })
+                    .collect();
+                assert_eq!(heights, (0..1000).collect::<Vec<_>>());
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! Reads the log files of a service back into records, for `cita-logctl
+//! grep` and post-mortem tooling.
+//!
+//! The files are those of the built-in text layout and of `JsonEncoder`,
+//! rotated ones included. Compressed rotations are read with the `gzip`
+//! and the `zstd` features, JSON lines with any feature depending on
+//! `serde_json` (e.g. `cli`).
+//!
+//! The text lines are read with the level names of the query (see
+//! `LogQuery::level_names`), with or without the `[{service}] ` prefix.
+//! Lines of other layouts, e.g. a custom `file_encoder`, are not records
+//! and continue the message of the record before.
+
+use crate::encode::LevelNames;
+use crate::index;
+use crate::rotate;
+use chrono::{DateTime, Local, NaiveDateTime};
+use log::{Level, LevelFilter};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "serde_json")]
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::vec;
+
+/// A record read back from a log file.
+///
+/// The targets of the text layout are truncated to 20 characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub time: SystemTime,
+    pub level: Level,
+    pub target: String,
+    /// The lines of the message, without the last newline.
+    pub message: String,
+}
+
+impl fmt::Display for LogEntry {
+    // Same layout as the text log file
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time: DateTime<Local> = self.time.into();
+        write!(
+            f,
+            "{} | {:20.20} | {:5} - {}",
+            time.format("%Y-%m-%d - %H:%M:%S"),
+            self.target,
+            self.level,
+            self.message
+        )
+    }
+}
+
+/// The records wanted from the log files, see `read_logs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogQuery {
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+    level: LevelFilter,
+    target: String,
+    level_names: LevelNames,
+}
+
+impl LogQuery {
+    /// Every record.
+    pub fn new() -> LogQuery {
+        LogQuery {
+            since: None,
+            until: None,
+            level: LevelFilter::Trace,
+            target: String::new(),
+            level_names: LevelNames::new(),
+        }
+    }
+
+    /// Only the records logged at or after `time`.
+    pub fn since(mut self, time: SystemTime) -> LogQuery {
+        self.since = Some(time);
+        self
+    }
+
+    /// Only the records logged at or before `time`.
+    pub fn until(mut self, time: SystemTime) -> LogQuery {
+        self.until = Some(time);
+        self
+    }
+
+    /// Only the records at or above `level`.
+    pub fn level(mut self, level: LevelFilter) -> LogQuery {
+        self.level = level;
+        self
+    }
+
+    /// Only the records whose target starts with `prefix`, e.g.
+    /// `cita_network` for it and its modules.
+    pub fn target(mut self, prefix: &str) -> LogQuery {
+        self.target = prefix.to_string();
+        self
+    }
+
+    /// Reads the levels of the text lines as `names`, those of
+    /// `Builder::level_names`, besides the usual names.
+    pub fn level_names(mut self, names: LevelNames) -> LogQuery {
+        self.level_names = names;
+        self
+    }
+
+    fn matches(&self, entry: &LogEntry) -> bool {
+        entry.level <= self.level
+            && entry.target.starts_with(&self.target)
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time <= until)
+    }
+
+    // Whether a file with the records from `start` to `end` may have some
+    // of those wanted.
+    fn covers(&self, start: Option<SystemTime>, end: Option<SystemTime>) -> bool {
+        // The file names and the text layout are precise to the second
+        let ended_before = match (end, self.since) {
+            (Some(end), Some(since)) => end + Duration::from_secs(1) < since,
+            _ => false,
+        };
+        let started_after = match (start, self.until) {
+            (Some(start), Some(until)) => start > until,
+            _ => false,
+        };
+        !ended_before && !started_after
+    }
+}
+
+impl Default for LogQuery {
+    fn default() -> LogQuery {
+        LogQuery::new()
+    }
+}
+
+/// Reads the records matching `query` from the log files of a service in
+/// `dir`, oldest first: the rotated files in either layout, then the
+/// current file.
+///
+/// The rotated files out of the time range of the query are skipped, and
+/// those with an index sidecar (see `Builder::index`) are read from the
+/// start of the range. Lines which are not records continue the message of
+/// the record before.
+///
+/// ```no_run
+/// use cita_logger::reader::{read_logs, LogQuery};
+/// use log::LevelFilter;
+///
+/// let query = LogQuery::new().level(LevelFilter::Warn).target("cita_network");
+/// for entry in read_logs("logs", "network", query)? {
+///     println!("{}", entry?);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn read_logs<P: AsRef<Path>>(
+    dir: P,
+    service_name: &str,
+    query: LogQuery,
+) -> io::Result<LogEntries> {
+    let dir = dir.as_ref();
+    let mut files = Vec::new();
+    // A rotated file has the records up to its rotation, from the one before
+    let mut start = None;
+    for path in rotate::rotated_files(dir, service_name)? {
+        let end = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| rotate::rotated_time(name, service_name))
+            .map(SystemTime::from);
+        if query.covers(start, end) {
+            files.push(path);
+        }
+        start = end;
+    }
+    let current = dir.join(format!("{}.log", service_name));
+    if current.is_file() && query.covers(start, None) {
+        files.push(current);
+    }
+    Ok(LogEntries {
+        query,
+        prefix: format!("[{}] ", service_name),
+        files: files.into_iter(),
+        reader: None,
+        pending: None,
+        line: Vec::new(),
+    })
+}
+
+/// The records read by `read_logs`.
+pub struct LogEntries {
+    query: LogQuery,
+    // Of the text lines, `[{service}] `
+    prefix: String,
+    files: vec::IntoIter<PathBuf>,
+    reader: Option<Box<dyn BufRead>>,
+    // The record read last, until the line starting the next one
+    pending: Option<LogEntry>,
+    line: Vec<u8>,
+}
+
+impl LogEntries {
+    fn next_entry(&mut self) -> io::Result<Option<LogEntry>> {
+        loop {
+            let reader = match &mut self.reader {
+                Some(reader) => reader,
+                None => match self.files.next() {
+                    Some(path) => {
+                        self.reader = Some(open(&path, self.query.since)?);
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+            };
+            self.line.clear();
+            let read = reader.read_until(b'\n', &mut self.line);
+            if !matches!(read, Ok(n) if n > 0) {
+                // The rest of a corrupted file is skipped
+                self.reader = None;
+                read?;
+                // A record does not span files
+                match self.pending.take() {
+                    Some(entry) if self.query.matches(&entry) => return Ok(Some(entry)),
+                    _ => continue,
+                }
+            }
+
+            let line = String::from_utf8_lossy(&self.line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            match parse(line, &self.prefix, &self.query.level_names) {
+                Some(entry) => match self.pending.replace(entry) {
+                    Some(entry) if self.query.matches(&entry) => return Ok(Some(entry)),
+                    _ => {}
+                },
+                None => {
+                    if let Some(pending) = &mut self.pending {
+                        pending.message.push('\n');
+                        pending.message.push_str(line);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for LogEntries {
+    type Item = io::Result<LogEntry>;
+
+    fn next(&mut self) -> Option<io::Result<LogEntry>> {
+        self.next_entry().transpose()
+    }
+}
+
+impl fmt::Debug for LogEntries {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LogEntries")
+            .field("query", &self.query)
+            .field("files", &self.files.as_slice())
+            .finish()
+    }
+}
+
+// Opens `path`, at the records of `since` where indexed.
+fn open(path: &Path, since: Option<SystemTime>) -> io::Result<Box<dyn BufRead>> {
+    let mut file = File::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        #[cfg(feature = "gzip")]
+        Some("gz") => Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+            file,
+        )))),
+        #[cfg(not(feature = "gzip"))]
+        Some("gz") => Err(unsupported(path, "gzip")),
+        #[cfg(feature = "zstd")]
+        Some("zst") => Ok(Box::new(BufReader::new(zstd::stream::read::Decoder::new(
+            file,
+        )?))),
+        #[cfg(not(feature = "zstd"))]
+        Some("zst") => Err(unsupported(path, "zstd")),
+        _ => {
+            if let Some(since) = since {
+                file.seek(SeekFrom::Start(index::seek_offset(path, since)?))?;
+            }
+            Ok(Box::new(BufReader::new(file)))
+        }
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn unsupported(path: &Path, feature: &str) -> io::Error {
+    let message = format!("{}: built without the {} feature", path.display(), feature);
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+fn parse(line: &str, prefix: &str, names: &LevelNames) -> Option<LogEntry> {
+    #[cfg(feature = "serde_json")]
+    {
+        if line.starts_with('{') {
+            return parse_json(line);
+        }
+    }
+    parse_text(line.strip_prefix(prefix).unwrap_or(line), names)
+}
+
+// `{time} | {target:20} - {line:5} | {level:5} - {message}`
+fn parse_text(line: &str, names: &LevelNames) -> Option<LogEntry> {
+    let (time, rest) = line.split_once(" | ")?;
+    let time = parse_time(time)?;
+    let (target, rest) = rest.split_once(" - ")?;
+    let (_, rest) = rest.split_once(" | ")?;
+    let (level, message) = rest.split_once(" - ")?;
+    Some(LogEntry {
+        time,
+        level: names.level(level)?,
+        target: target.trim_end().to_string(),
+        message: message.to_string(),
+    })
+}
+
+// In any `TimeFormat`.
+fn parse_time(time: &str) -> Option<SystemTime> {
+    if let Ok(time) = NaiveDateTime::parse_from_str(time, "%Y-%m-%d - %H:%M:%S") {
+        return Some(time.and_local_timezone(Local).earliest()?.into());
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+        return Some(time.into());
+    }
+    let elapsed = match time.split_once('.') {
+        Some((secs, micros)) if micros.len() == 6 => {
+            Duration::new(secs.parse().ok()?, micros.parse::<u32>().ok()? * 1000)
+        }
+        Some(_) => return None,
+        None => Duration::from_millis(time.parse().ok()?),
+    };
+    UNIX_EPOCH.checked_add(elapsed)
+}
+
+#[cfg(feature = "serde_json")]
+fn parse_json(line: &str) -> Option<LogEntry> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    let time = DateTime::parse_from_rfc3339(value["time"].as_str()?).ok()?;
+    Some(LogEntry {
+        time: time.into(),
+        level: Level::from_str(value["level"].as_str()?).ok()?,
+        target: value["target"].as_str()?.to_string(),
+        message: value["message"].as_str()?.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_logs, LogQuery};
+    use crate::encode::LevelNames;
+    use chrono::{Duration, Local, TimeZone, Utc};
+    use log::{Level, LevelFilter};
+    use std::fs;
+    use std::time::SystemTime;
+
+    #[test]
+    fn rotated_and_current() {
+        let dir = std::env::temp_dir().join(format!("cita-logger-reader-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("chain_2019-05-16_10-00-00.log"),
+            "[chain] 2019-05-16 - 09:59:58 | chain::executor      - 42    | INFO  - block 1023\n\
+             [chain] 2019-05-16 - 09:59:59 | cita_network         - 7     | WARN  - peer 3 lost\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("chain.log"),
+            "[chain] 2019-05-16 - 10:20:30 | chain::executor      - 42    | ERROR - invalid block\n\
+             stack backtrace:\n\
+             [chain] 2019-05-16 - 10:20:31 | chain::executor      - 42    | DEBUG - block 1024\n",
+        )
+        .unwrap();
+        let at = |h, m, s| SystemTime::from(Local.with_ymd_and_hms(2019, 5, 16, h, m, s).unwrap());
+
+        let read = |query| -> Vec<_> {
+            read_logs(&dir, "chain", query)
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .collect()
+        };
+        let entries = read(LogQuery::new().level(LevelFilter::Info));
+        let messages: Vec<_> = entries.iter().map(|entry| entry.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "block 1023",
+                "peer 3 lost",
+                "invalid block\nstack backtrace:"
+            ]
+        );
+        assert_eq!(entries[2].level, Level::Error);
+        assert_eq!(entries[2].target, "chain::executor");
+        assert_eq!(entries[2].time, at(10, 20, 30));
+
+        let entries = read(LogQuery::new().since(at(10, 0, 0)).target("chain"));
+        assert_eq!(entries.len(), 2);
+        let entries = read(LogQuery::new().until(at(10, 0, 0)).target("cita_network"));
+        assert_eq!(entries.len(), 1);
+
+        // Seeks past the error with an index sidecar
+        let offset = fs::read_to_string(dir.join("chain.log"))
+            .unwrap()
+            .find("[chain] 2019-05-16 - 10:20:31")
+            .unwrap() as u64;
+        let micros = at(10, 20, 29)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        let entry = [offset.to_le_bytes(), micros.to_le_bytes()].concat();
+        fs::write(dir.join("chain.log.idx"), entry).unwrap();
+        let entries = read(LogQuery::new().since(at(10, 20, 30)));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].message, "block 1024");
+
+        fs::write(
+            dir.join("network.log"),
+            "[network] 2019-05-16T02:00:00.250000+00:00 | cita_network         - 7     | INFO  - peer 3 joined\n",
+        )
+        .unwrap();
+        let entries: Vec<_> = read_logs(&dir, "network", LogQuery::new())
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect();
+        let time =
+            Utc.with_ymd_and_hms(2019, 5, 16, 2, 0, 0).unwrap() + Duration::milliseconds(250);
+        assert_eq!(entries[0].time, SystemTime::from(time));
+
+        fs::write(
+            dir.join("network.log"),
+            "[network] 1557972000250 | cita_network         - 7     | INFO  - peer 3 joined\n\
+             [network] 12.000250 | cita_network         - 7     | INFO  - peer 4 joined\n",
+        )
+        .unwrap();
+        let entries: Vec<_> = read_logs(&dir, "network", LogQuery::new())
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect();
+        assert_eq!(entries[0].time, SystemTime::from(time));
+        let elapsed = Duration::seconds(12) + Duration::microseconds(250);
+        assert_eq!(
+            entries[1].time,
+            SystemTime::UNIX_EPOCH + elapsed.to_std().unwrap()
+        );
+
+        // Unknown level names are not records
+        fs::write(
+            dir.join("executor.log"),
+            "[executor] 2019-05-16 - 10:20:29 | executor             - 42    | INFO  - started\n\
+             [executor] 2019-05-16 - 10:20:30 | executor             - 42    | ⚠ - slow block\n\
+             2019-05-16 - 10:20:31 | executor             - 42    | ✖ - invalid block\n",
+        )
+        .unwrap();
+        let read = |query| -> Vec<_> {
+            read_logs(&dir, "executor", query)
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .collect()
+        };
+        let entries = read(LogQuery::new());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].message.lines().count(), 3);
+        let entries = read(LogQuery::new().level_names(LevelNames::symbols()));
+        let levels: Vec<_> = entries.iter().map(|entry| entry.level).collect();
+        assert_eq!(levels, [Level::Info, Level::Warn, Level::Error]);
+        assert_eq!(entries[2].time, at(10, 20, 31));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// An in-memory ring buffer of the most recent records.
+
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, Local};
+use log::{Level, LevelFilter, Record};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A record kept in memory, see `recent_records()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentRecord {
+    pub time: SystemTime,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for RecentRecord {
+    // Same layout as the text log file
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time: DateTime<Local> = self.time.into();
+        write!(
+            f,
+            "{} | {:20.20} | {:5} - {}",
+            time.format("%Y-%m-%d - %H:%M:%S"),
+            self.target,
+            self.level,
+            self.message
+        )
+    }
+}
+
+pub(crate) struct Ring {
+    level: LevelFilter,
+    slots: Vec<ArcSwapOption<RecentRecord>>,
+    // Total number of records pushed
+    next: AtomicUsize,
+}
+
+impl Ring {
+    fn new(capacity: usize, level: LevelFilter) -> Ring {
+        Ring {
+            level,
+            slots: (0..capacity).map(|_| ArcSwapOption::empty()).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, record: &Record) {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        self.slots[n % self.slots.len()].store(Some(Arc::new(RecentRecord {
+            time: SystemTime::now(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        })));
+    }
+
+    // Oldest first
+    fn records(&self) -> Vec<RecentRecord> {
+        let next = self.next.load(Ordering::Relaxed);
+        let len = self.slots.len();
+        (0..len)
+            .filter_map(|i| self.slots[(next + i) % len].load_full())
+            .map(|record| (*record).clone())
+            .collect()
+    }
+
+    // The last `limit` records matching, oldest first
+    fn query(&self, level: LevelFilter, target_prefix: &str, limit: usize) -> Vec<RecentRecord> {
+        let next = self.next.load(Ordering::Relaxed);
+        let len = self.slots.len();
+        let mut records: Vec<_> = (0..len)
+            .rev()
+            .filter_map(|i| self.slots[(next + i) % len].load_full())
+            .filter(|record| record.level <= level && record.target.starts_with(target_prefix))
+            .take(limit)
+            .map(|record| (*record).clone())
+            .collect();
+        records.reverse();
+        records
+    }
+}
+
+static RING: ArcSwapOption<Ring> = ArcSwapOption::const_empty();
+// The level of the ring buffer, checked first by `push`
+static LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+// Keeps the last `capacity` records at or above `level`.
+pub(crate) fn install(capacity: usize, level: LevelFilter) {
+    if capacity > 0 {
+        RING.store(Some(Arc::new(Ring::new(capacity, level))));
+        LEVEL.store(level as usize, Ordering::Relaxed);
+    }
+}
+
+// The least severe level which is kept, `Off` without a ring buffer.
+pub(crate) fn level() -> LevelFilter {
+    match &*RING.load() {
+        Some(ring) => ring.level,
+        None => LevelFilter::Off,
+    }
+}
+
+pub(crate) fn push(record: &Record) {
+    if record.level() as usize > LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(ring) = &*RING.load() {
+        if record.level() <= ring.level {
+            ring.push(record);
+        }
+    }
+}
+
+/// The most recent records, oldest first, kept by `Builder::ring_buffer`.
+///
+/// Records are kept whatever the level filters of the log file, so this
+/// may include debug records which were never written anywhere.
+pub fn recent_records() -> Vec<RecentRecord> {
+    match &*RING.load() {
+        Some(ring) => ring.records(),
+        None => Vec::new(),
+    }
+}
+
+/// The last `limit` records kept by `Builder::ring_buffer` at or above
+/// `level` whose target starts with `target_prefix`, oldest first, e.g.
+/// for a `getNodeLogs` admin RPC.
+///
+/// ```no_run
+/// use log::LevelFilter;
+///
+/// for record in cita_logger::query_recent(LevelFilter::Warn, "cita_network", 100) {
+///     println!("{} {} {}", record.level, record.target, record.message);
+/// }
+/// ```
+pub fn query_recent(level: LevelFilter, target_prefix: &str, limit: usize) -> Vec<RecentRecord> {
+    match &*RING.load() {
+        Some(ring) => ring.query(level, target_prefix, limit),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ring;
+    use log::{Level, LevelFilter, Record};
+
+    #[test]
+    fn keeps_the_last_records() {
+        let ring = Ring::new(3, LevelFilter::Debug);
+        for i in 0..5 {
+            ring.push(
+                &Record::builder()
+                    .level(Level::Debug)
+                    .args(format_args!("{}", i))
+                    .build(),
+            );
+        }
+        let messages: Vec<_> = ring.records().into_iter().map(|r| r.message).collect();
+        assert_eq!(messages, vec!["2", "3", "4"]);
+    }
+
+    #[test]
+    fn query() {
+        let ring = Ring::new(8, LevelFilter::Debug);
+        for (i, (level, target)) in [
+            (Level::Warn, "cita_network::sync"),
+            (Level::Debug, "cita_network"),
+            (Level::Error, "cita_chain"),
+            (Level::Warn, "cita_network"),
+            (Level::Error, "cita_network::node"),
+        ]
+        .iter()
+        .enumerate()
+        {
+            ring.push(
+                &Record::builder()
+                    .level(*level)
+                    .target(target)
+                    .args(format_args!("{}", i))
+                    .build(),
+            );
+        }
+        let messages = |level, prefix, limit| -> Vec<_> {
+            ring.query(level, prefix, limit)
+                .into_iter()
+                .map(|r| r.message)
+                .collect()
+        };
+        assert_eq!(
+            messages(LevelFilter::Warn, "cita_network", 10),
+            ["0", "3", "4"]
+        );
+        assert_eq!(messages(LevelFilter::Warn, "cita_network", 2), ["3", "4"]);
+        assert_eq!(messages(LevelFilter::Error, "", 10), ["2", "4"]);
+        assert!(messages(LevelFilter::Trace, "jsonrpc", 10).is_empty());
+    }
+}
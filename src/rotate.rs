@@ -0,0 +1,342 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Naming of rotated log files (e.g: logs/chain_2019-05-16_10-20-30.log, or
+// logs/chain/2019/05/16/chain_2019-05-16_10-20-30.log in the dated layout),
+// with a .gz or .zst extension once compressed.
+
+use chrono::{DateTime, Days, Local, NaiveDateTime};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+const TIME_STAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// Where rotated log files go, see `Builder::layout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum LogLayout {
+    /// Next to the current file, as `logs/{service}_{time}.log`.
+    Flat,
+    /// In a directory per day, as
+    /// `logs/{service}/%Y/%m/%d/{service}_{time}.log`.
+    Dated,
+}
+
+/// When the log files rotate, see `Builder::rotation`.
+///
+/// The triggers combine: the files rotate on whichever fires first. By
+/// default they rotate on SIGUSR1 only.
+///
+/// ```no_run
+/// use cita_logger::{Builder, LogFavour, RotationPolicy};
+///
+/// let policy = RotationPolicy::new().max_size(100 << 20).daily(true);
+/// Builder::new(&LogFavour::File("chain")).rotation(policy).init();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationPolicy {
+    pub(crate) max_size: Option<u64>,
+    pub(crate) daily: bool,
+    pub(crate) signal: bool,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> RotationPolicy {
+        RotationPolicy {
+            max_size: None,
+            daily: false,
+            signal: true,
+        }
+    }
+}
+
+impl RotationPolicy {
+    pub fn new() -> RotationPolicy {
+        RotationPolicy::default()
+    }
+
+    /// Rotates once a file grows beyond `bytes`.
+    pub fn max_size(mut self, bytes: u64) -> RotationPolicy {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotates at local midnight.
+    pub fn daily(mut self, enabled: bool) -> RotationPolicy {
+        self.daily = enabled;
+        self
+    }
+
+    /// Rotates on SIGUSR1, enabled by default.
+    pub fn signal(mut self, enabled: bool) -> RotationPolicy {
+        self.signal = enabled;
+        self
+    }
+}
+
+impl fmt::Display for RotationPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut triggers = Vec::new();
+        if self.signal {
+            triggers.push("on SIGUSR1".to_string());
+        }
+        if let Some(max_size) = self.max_size {
+            triggers.push(format!("over {} bytes", max_size));
+        }
+        if self.daily {
+            triggers.push("daily".to_string());
+        }
+        if triggers.is_empty() {
+            triggers.push("on request".to_string());
+        }
+        f.write_str(&triggers.join(" or "))
+    }
+}
+
+// The first local midnight after `now`.
+pub fn next_midnight(now: DateTime<Local>) -> Option<DateTime<Local>> {
+    now.date_naive()
+        .checked_add_days(Days::new(1))?
+        .and_hms_opt(0, 0, 0)?
+        .and_local_timezone(Local)
+        .earliest()
+}
+
+pub fn rotated_name(service_name: &str, time: DateTime<Local>) -> String {
+    format!("{}_{}.log", service_name, time.format(TIME_STAMP_FORMAT))
+}
+
+// The path of a file of `dir` rotated at `time`.
+pub fn rotated_path(
+    dir: &Path,
+    service_name: &str,
+    time: DateTime<Local>,
+    layout: LogLayout,
+) -> PathBuf {
+    let name = rotated_name(service_name, time);
+    match layout {
+        LogLayout::Flat => dir.join(name),
+        LogLayout::Dated => dir
+            .join(service_name)
+            .join(time.format("%Y/%m/%d").to_string())
+            .join(name),
+    }
+}
+
+// Rotated files of a service in `dir` in either layout, oldest first.
+pub fn rotated_files<P: AsRef<Path>>(dir: P, service_name: &str) -> io::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    let mut files = Vec::new();
+    collect_rotated(dir, service_name, false, &mut files)?;
+    let dated = dir.join(service_name);
+    if dated.is_dir() {
+        collect_rotated(&dated, service_name, true, &mut files)?;
+    }
+    // The time stamp format sorts chronologically.
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    Ok(files)
+}
+
+fn collect_rotated(
+    dir: &Path,
+    service_name: &str,
+    recursive: bool,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if recursive && entry.file_type()?.is_dir() {
+            collect_rotated(&path, service_name, true, files)?;
+            continue;
+        }
+        let is_rotated = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| is_rotated_name(name, service_name));
+        if is_rotated {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Like `rotated_path`, numbered (`{service}_{time}_1.log`, ...) after the
+// files rotated within the same second, compressed or not.
+pub fn unused_rotated_path(
+    dir: &Path,
+    service_name: &str,
+    time: DateTime<Local>,
+    layout: LogLayout,
+) -> PathBuf {
+    let path = rotated_path(dir, service_name, time, layout);
+    let stem = format!("{}_{}", service_name, time.format(TIME_STAMP_FORMAT));
+    let mut candidate = path.clone();
+    for n in 1.. {
+        let taken = ["", ".gz", ".zst"].iter().any(|extension| {
+            let mut name = candidate.clone().into_os_string();
+            name.push(extension);
+            Path::new(&name).exists()
+        });
+        if !taken {
+            break;
+        }
+        candidate = path.with_file_name(format!("{}_{}.log", stem, n));
+    }
+    candidate
+}
+
+type Callback = dyn Fn(&Path, &Path) + Send + Sync;
+
+// What to run after a rotation, with the rotated and the current file.
+#[derive(Clone)]
+pub enum Hook {
+    Callback(Arc<Callback>),
+    Command(String),
+}
+
+impl fmt::Debug for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hook::Callback(_) => f.write_str("Callback"),
+            Hook::Command(command) => f.debug_tuple("Command").field(command).finish(),
+        }
+    }
+}
+
+impl Hook {
+    // A command gets the paths as `$1` and `$2`; it is waited for.
+    pub fn run(&self, rotated: &Path, current: &Path) -> io::Result<()> {
+        match self {
+            Hook::Callback(callback) => callback(rotated, current),
+            Hook::Command(command) => {
+                let status = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .arg("sh")
+                    .arg(rotated)
+                    .arg(current)
+                    .status()?;
+                if !status.success() {
+                    return Err(io::Error::other(format!("'{}' {}", command, status)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_rotated_name(file_name: &str, service_name: &str) -> bool {
+    rotated_time(file_name, service_name).is_some()
+}
+
+// When a file named `file_name` was rotated, `None` if it is not a rotated
+// file of the service.
+pub fn rotated_time(file_name: &str, service_name: &str) -> Option<DateTime<Local>> {
+    let time_stamp = file_name
+        .strip_prefix(service_name)
+        .and_then(|rest| rest.strip_prefix('_'))
+        .map(|rest| rest.trim_end_matches(".gz").trim_end_matches(".zst"))
+        .and_then(|rest| rest.strip_suffix(".log"))?;
+    let parse = |time_stamp| NaiveDateTime::parse_from_str(time_stamp, TIME_STAMP_FORMAT).ok();
+    let time = match parse(time_stamp) {
+        Some(time) => time,
+        // Numbered after a file rotated within the same second
+        None => time_stamp.rsplit_once('_').and_then(|(time_stamp, n)| {
+            if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) {
+                parse(time_stamp)
+            } else {
+                None
+            }
+        })?,
+    };
+    time.and_local_timezone(Local).earliest()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_rotated_name, next_midnight, rotated_files, rotated_path, unused_rotated_path, Hook,
+        LogLayout, RotationPolicy,
+    };
+    use chrono::{Local, TimeZone};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn rotated_name_matches_service() {
+        assert!(is_rotated_name("chain_2019-05-16_10-20-30.log", "chain"));
+        assert!(is_rotated_name(
+            "chain_2019-05-16_10-20-30.log.zst",
+            "chain"
+        ));
+        assert!(is_rotated_name("chain_2019-05-16_10-20-30_2.log", "chain"));
+        assert!(!is_rotated_name("chain_2019-05-16_10-20-30_.log", "chain"));
+        assert!(!is_rotated_name("chain.log", "chain"));
+        assert!(!is_rotated_name("chain_x_2019-05-16_10-20-30.log", "chain"));
+        assert!(!is_rotated_name("chain_x.log", "chain"));
+        assert!(!is_rotated_name("auth_2019-05-16_10-20-30.log", "chain"));
+    }
+
+    #[test]
+    fn dated_layout() {
+        let dir = std::env::temp_dir().join(format!("cita-logger-dated-{}", std::process::id()));
+        let old = Local.with_ymd_and_hms(2019, 5, 16, 10, 20, 30).unwrap();
+        let new = Local.with_ymd_and_hms(2019, 6, 1, 0, 0, 0).unwrap();
+        let dated = rotated_path(&dir, "chain", old, LogLayout::Dated);
+        assert_eq!(
+            dated,
+            dir.join("chain/2019/05/16/chain_2019-05-16_10-20-30.log")
+        );
+        let flat = rotated_path(&dir, "chain", new, LogLayout::Flat);
+        fs::create_dir_all(dated.parent().unwrap()).unwrap();
+        fs::write(&dated, "").unwrap();
+        fs::write(&flat, "").unwrap();
+        let again = unused_rotated_path(&dir, "chain", new, LogLayout::Flat);
+        assert_eq!(again, dir.join("chain_2019-06-01_00-00-00_1.log"));
+        fs::write(&again, "").unwrap();
+        assert_eq!(
+            rotated_files(&dir, "chain").unwrap(),
+            vec![dated, flat, again]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn policy() {
+        let now = Local.with_ymd_and_hms(2019, 5, 31, 23, 59, 59).unwrap();
+        assert_eq!(
+            next_midnight(now),
+            Some(Local.with_ymd_and_hms(2019, 6, 1, 0, 0, 0).unwrap())
+        );
+        let policy = RotationPolicy::new().max_size(1024).daily(true);
+        assert_eq!(policy.to_string(), "on SIGUSR1 or over 1024 bytes or daily");
+        assert_eq!(
+            RotationPolicy::new().signal(false).to_string(),
+            "on request"
+        );
+    }
+
+    #[test]
+    fn hook_command() {
+        let hook =
+            Hook::Command("test \"$1\" = logs/chain_1.log -a \"$2\" = logs/chain.log".into());
+        hook.run(Path::new("logs/chain_1.log"), Path::new("logs/chain.log"))
+            .unwrap();
+        assert!(Hook::Command("exit 3".into())
+            .run(Path::new("a"), Path::new("b"))
+            .is_err());
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Probabilistic sampling of records, set by directives like
+// `network::sync=debug@1%`.
+//
+// Only info, debug and trace records are sampled, warnings and errors are
+// always kept.
+
+use log::Level;
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Whether a record at `level` passes a directive sampling at `rate`.
+pub(crate) fn keep(level: Level, rate: Option<f64>) -> bool {
+    match rate {
+        Some(rate) if level > Level::Warn => random() < rate,
+        _ => true,
+    }
+}
+
+// A uniform number in [0, 1).
+fn random() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// A random number from a per thread xorshift generator, e.g. for ids.
+pub(crate) fn random_u64() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = const { Cell::new(0) };
+    }
+    STATE.with(|state| {
+        let mut x = state.get();
+        if x == 0 {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0);
+            x = (nanos ^ (state as *const _ as u64)) | 1;
+        }
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{keep, random};
+    use log::Level;
+
+    #[test]
+    fn never_samples_warnings() {
+        assert!(keep(Level::Warn, Some(0.0)));
+        assert!(!keep(Level::Debug, Some(0.0)));
+        assert!(keep(Level::Debug, None));
+    }
+
+    #[test]
+    fn random_is_uniform() {
+        let kept = (0..10_000).filter(|_| random() < 0.1).count();
+        assert!(kept > 700 && kept < 1300, "{}", kept);
+    }
+}
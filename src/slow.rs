@@ -0,0 +1,72 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Slow operations, logged by `slow_log!` like the slow query log of a
+// database.
+
+use crate::logger;
+use log::{Level, Record};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// The target of slow operation records, routed to `logs/{service}.slow.log`
+// by `Builder::slow_log`.
+pub(crate) const TARGET: &str = "cita_logger::slow";
+
+// Whether the slow log takes the records, whatever the level filters
+static ROUTED: AtomicBool = AtomicBool::new(false);
+
+// Set when the logger is configured.
+pub(crate) fn route(routed: bool) {
+    ROUTED.store(routed, Ordering::Relaxed);
+}
+
+/// Logs that operation `op` took `duration` if that exceeds `threshold`.
+///
+/// The records are warnings, written to `logs/{service}.slow.log` instead
+/// of the regular log when `Builder::slow_log` is set, whatever the levels
+/// of the modules.
+///
+/// ```no_run
+/// use std::time::{Duration, Instant};
+///
+/// let start = Instant::now();
+/// // execute the block
+/// cita_logger::slow_log!(Duration::from_millis(500), "execute block 42", start.elapsed());
+/// ```
+#[macro_export]
+macro_rules! slow_log {
+    ($threshold:expr, $op:expr, $duration:expr $(,)?) => {{
+        let threshold: ::std::time::Duration = $threshold;
+        let duration: ::std::time::Duration = $duration;
+        if duration > threshold {
+            $crate::__slow_log(&$op, duration, threshold);
+        }
+    }};
+}
+
+#[doc(hidden)]
+pub fn __slow_log(op: &dyn Display, duration: Duration, threshold: Duration) {
+    let log = |record: &Record| {
+        if ROUTED.load(Ordering::Relaxed) && logger::log_unfiltered(record) {
+            return;
+        }
+        if log::log_enabled!(target: TARGET, Level::Warn) {
+            log::logger().log(record);
+        }
+    };
+    log(&Record::builder()
+        .level(Level::Warn)
+        .target(TARGET)
+        .module_path_static(Some(module_path!()))
+        .args(format_args!(
+            "{} took {:?} (threshold {:?})",
+            op, duration, threshold
+        ))
+        .build());
+}
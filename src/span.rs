@@ -0,0 +1,223 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Spans: a named stretch of work on a thread, whose entry and exit are
+// logged and whose fields are in the MDC of the records logged meanwhile.
+
+#[cfg(feature = "zipkin")]
+use crate::export::{self, FinishedSpan};
+#[cfg(feature = "zipkin")]
+use crate::trace::TraceContext;
+use log::kv::Value;
+use log::{logger, Level, Record};
+use log_mdc::ExtendGuard;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::time::Instant;
+#[cfg(feature = "zipkin")]
+use std::time::SystemTime;
+
+// The MDC key of the names of the spans entered, outermost first
+const SPAN_KEY: &str = "span";
+
+/// Enters a span, which ends when the returned guard is dropped.
+///
+/// The entry and the exit of the span are logged at debug level (or at
+/// `level:`), the exit with the `elapsed_us` it took. Until then its
+/// fields, written as in `event!`, are in the MDC of the records of the
+/// thread, along with `span`, the names of the spans entered separated by
+/// `/`.
+///
+/// When the spans are exported (see `Builder::span_exporter`), a span is a
+/// child of the `TraceContext` of the thread, or starts a trace, and its
+/// `trace_id` and `span_id` are in the MDC as well.
+///
+/// ```no_run
+/// use log::Level;
+///
+/// let height = 1024u64;
+/// let _span = cita_logger::span!("execute_block", height = height);
+/// for tx in 0..3 {
+///     let _span = cita_logger::span!(level: Level::Trace, "execute_tx", tx = tx);
+///     // records here have span=execute_block/execute_tx, height=1024, tx
+/// }
+/// ```
+#[macro_export]
+macro_rules! span {
+    (level: $level:expr, $name:expr $(, $($fields:tt)*)?) => {
+        $crate::__span(
+            $level,
+            module_path!(),
+            file!(),
+            line!(),
+            &$name,
+            &$crate::event!(@fields [] $($($fields)*)?),
+        )
+    };
+    ($name:expr $(, $($fields:tt)*)?) => {
+        $crate::span!(level: $crate::__Level::Debug, $name $(, $($fields)*)?)
+    };
+}
+
+/// A span entered with `span!`, ended when dropped.
+///
+/// It must be dropped on the thread which entered it.
+#[must_use = "the span ends as soon as the guard is dropped"]
+pub struct Span {
+    level: Level,
+    target: &'static str,
+    file: &'static str,
+    line: u32,
+    name: String,
+    start: Instant,
+    mdc: Option<ExtendGuard>,
+    #[cfg(feature = "zipkin")]
+    exported: Option<Exported>,
+    // The MDC is per thread
+    _thread: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "zipkin")]
+struct Exported {
+    context: TraceContext,
+    parent_id: Option<u64>,
+    start: SystemTime,
+    tags: Vec<(String, String)>,
+}
+
+impl Span {
+    /// The name of the span.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn log(&self, message: &str, fields: &[(&str, Value)]) {
+        if log::log_enabled!(target: self.target, self.level) {
+            logger().log(
+                &Record::builder()
+                    .level(self.level)
+                    .target(self.target)
+                    .module_path_static(Some(self.target))
+                    .file_static(Some(self.file))
+                    .line(Some(self.line))
+                    .key_values(&fields)
+                    .args(format_args!("{} {}", message, self.name))
+                    .build(),
+            );
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        self.log(
+            "exit",
+            &[("elapsed_us", Value::from(elapsed.as_micros() as u64))],
+        );
+        #[cfg(feature = "zipkin")]
+        if let Some(exported) = self.exported.take() {
+            export::export(FinishedSpan {
+                trace_id: exported.context.trace_id,
+                id: exported.context.span_id,
+                parent_id: exported.parent_id,
+                name: self.name.clone(),
+                start: exported.start,
+                duration: elapsed,
+                tags: exported.tags,
+            });
+        }
+        // Restore the MDC of the enclosing span
+        self.mdc.take();
+    }
+}
+
+#[doc(hidden)]
+pub fn __span(
+    level: Level,
+    target: &'static str,
+    file: &'static str,
+    line: u32,
+    name: &dyn Display,
+    fields: &[(&str, Value)],
+) -> Span {
+    let name = name.to_string();
+    let path = log_mdc::get(SPAN_KEY, |outer| match outer {
+        Some(outer) => format!("{}/{}", outer, name),
+        None => name.clone(),
+    });
+    let mut entries: Vec<(String, String)> = fields
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    #[cfg(feature = "zipkin")]
+    let exported = export::enabled().then(|| {
+        let parent = TraceContext::current();
+        let context = parent.map_or_else(TraceContext::new_root, |parent| parent.child());
+        Exported {
+            context,
+            parent_id: parent.map(|parent| parent.span_id),
+            start: SystemTime::now(),
+            tags: entries.clone(),
+        }
+    });
+    #[cfg(feature = "zipkin")]
+    if let Some(exported) = &exported {
+        entries.extend(
+            exported
+                .context
+                .mdc()
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone())),
+        );
+    }
+    entries.push((SPAN_KEY.to_string(), path));
+    let mdc = log_mdc::extend_scoped(entries);
+    let span = Span {
+        level,
+        target,
+        file,
+        line,
+        name,
+        start: Instant::now(),
+        mdc: Some(mdc),
+        #[cfg(feature = "zipkin")]
+        exported,
+        _thread: PhantomData,
+    };
+    span.log("enter", fields);
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SPAN_KEY;
+
+    fn mdc(key: &str) -> Option<String> {
+        log_mdc::get(key, |value| value.map(str::to_string))
+    }
+
+    #[test]
+    fn nested_mdc() {
+        {
+            let block = span!("execute_block", height = 1024u64);
+            assert_eq!(block.name(), "execute_block");
+            assert_eq!(mdc("height").as_deref(), Some("1024"));
+            {
+                let _tx = span!("execute_tx", height = 1025u64, hash = %"0x6a6b");
+                assert_eq!(mdc(SPAN_KEY).as_deref(), Some("execute_block/execute_tx"));
+                assert_eq!(mdc("height").as_deref(), Some("1025"));
+                assert_eq!(mdc("hash").as_deref(), Some("0x6a6b"));
+            }
+            assert_eq!(mdc(SPAN_KEY).as_deref(), Some("execute_block"));
+            assert_eq!(mdc("height").as_deref(), Some("1024"));
+            assert_eq!(mdc("hash"), None);
+        }
+        assert_eq!(mdc(SPAN_KEY), None);
+        assert_eq!(mdc("height"), None);
+    }
+}
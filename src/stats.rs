@@ -0,0 +1,166 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Counters of emitted records.
+
+use crate::info;
+use log::Level;
+use parking_lot::{const_rwlock, RwLock};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Number of emitted records per level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LevelCounts {
+    pub error: u64,
+    pub warn: u64,
+    pub info: u64,
+    pub debug: u64,
+    pub trace: u64,
+}
+
+impl LevelCounts {
+    pub fn total(&self) -> u64 {
+        self.error + self.warn + self.info + self.debug + self.trace
+    }
+
+    pub fn get(&self, level: Level) -> u64 {
+        match level {
+            Level::Error => self.error,
+            Level::Warn => self.warn,
+            Level::Info => self.info,
+            Level::Debug => self.debug,
+            Level::Trace => self.trace,
+        }
+    }
+}
+
+impl fmt::Display for LevelCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "error={} warn={} info={} debug={} trace={}",
+            self.error, self.warn, self.info, self.debug, self.trace
+        )
+    }
+}
+
+/// A snapshot of the record counters, see `stats()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Records of all targets.
+    pub levels: LevelCounts,
+    /// Records per top-level target, i.e. the crate name.
+    pub targets: BTreeMap<String, LevelCounts>,
+    /// Log file rotations.
+    pub rotations: u64,
+}
+
+#[derive(Default)]
+struct Counters([AtomicU64; 5]);
+
+impl Counters {
+    const fn new() -> Counters {
+        Counters([
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+        ])
+    }
+
+    fn count(&self, level: Level) {
+        // Level::Error is 1
+        self.0[level as usize - 1].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LevelCounts {
+        let get = |level: Level| self.0[level as usize - 1].load(Ordering::Relaxed);
+        LevelCounts {
+            error: get(Level::Error),
+            warn: get(Level::Warn),
+            info: get(Level::Info),
+            debug: get(Level::Debug),
+            trace: get(Level::Trace),
+        }
+    }
+}
+
+static TOTAL: Counters = Counters::new();
+static TARGETS: RwLock<BTreeMap<String, Arc<Counters>>> = const_rwlock(BTreeMap::new());
+static ROTATIONS: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn count(level: Level, target: &str) {
+    TOTAL.count(level);
+
+    let top_level = target.split("::").next().unwrap_or(target);
+    if let Some(counters) = TARGETS.read().get(top_level) {
+        counters.count(level);
+        return;
+    }
+    TARGETS
+        .write()
+        .entry(top_level.to_string())
+        .or_default()
+        .count(level);
+}
+
+pub(crate) fn count_rotation() {
+    ROTATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of records emitted so far, per level and per target.
+///
+/// Only records which passed the level filters are counted.
+pub fn stats() -> Stats {
+    Stats {
+        levels: TOTAL.snapshot(),
+        targets: TARGETS
+            .read()
+            .iter()
+            .map(|(target, counters)| (target.clone(), counters.snapshot()))
+            .collect(),
+        rotations: ROTATIONS.load(Ordering::Relaxed),
+    }
+}
+
+// Logs a summary of the counters every `interval`.
+pub(crate) fn spawn_summary(interval: Duration) -> io::Result<()> {
+    thread::Builder::new()
+        .name("cita-logger-stats".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            info!("log stats: {}", stats().levels);
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count, stats};
+    use log::Level;
+
+    #[test]
+    fn count_per_level_and_target() {
+        count(Level::Error, "stats_test::a");
+        count(Level::Error, "stats_test::b::c");
+        count(Level::Debug, "stats_test");
+
+        let stats = stats();
+        let target = stats.targets["stats_test"];
+        assert_eq!(target.error, 2);
+        assert_eq!(target.debug, 1);
+        assert_eq!(target.total(), 3);
+        assert!(stats.levels.error >= 2);
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// Records under the logical subsystem of a module, e.g. `consensus::round`,
+// rather than its Rust module path, so that the directives name what the
+// operators know.
+
+/// Logs at error level under `target`, e.g. `consensus::round`, as
+/// `error!(target: ...)` does.
+///
+/// ```no_run
+/// let round = 3;
+/// cita_logger::info_target!("consensus::round", "round {} started", round);
+/// // shown by RUST_LOG=consensus::round=info whatever the module
+/// ```
+#[macro_export]
+macro_rules! error_target {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::__Level::Error, $($arg)+)
+    };
+}
+
+/// Logs at warn level under `target`, see `error_target!`.
+#[macro_export]
+macro_rules! warn_target {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::__Level::Warn, $($arg)+)
+    };
+}
+
+/// Logs at info level under `target`, see `error_target!`.
+#[macro_export]
+macro_rules! info_target {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::__Level::Info, $($arg)+)
+    };
+}
+
+/// Logs at debug level under `target`, see `error_target!`.
+#[macro_export]
+macro_rules! debug_target {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::__Level::Debug, $($arg)+)
+    };
+}
+
+/// Logs at trace level under `target`, see `error_target!`.
+#[macro_export]
+macro_rules! trace_target {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::__Level::Trace, $($arg)+)
+    };
+}
@@ -0,0 +1,232 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// An MDC per async task: the MDC is per thread, and the tasks of a tokio
+// runtime move between its worker threads, so the context of a task is
+// kept with its future and put in the MDC of the thread polling it.
+//
+// Requires the `tokio` feature.
+
+use crate::trace::TraceContext;
+use parking_lot::Mutex;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+tokio::task_local! {
+    static CONTEXT: Arc<Mutex<LogContext>>;
+}
+
+/// The MDC entries of an async task, see `InstrumentLog::instrument_log`.
+///
+/// ```no_run
+/// use cita_logger::{InstrumentLog, LogContext};
+///
+/// # async fn handle(request: u64) {}
+/// # async fn serve(request: u64) {
+/// let context = LogContext::current().with("request", request);
+/// tokio::spawn(handle(request).instrument_log(context));
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogContext {
+    entries: Vec<(String, String)>,
+}
+
+impl LogContext {
+    /// An empty context.
+    pub fn new() -> LogContext {
+        LogContext::default()
+    }
+
+    /// The MDC of the thread, e.g. to pass it on to a spawned task.
+    pub fn current() -> LogContext {
+        let mut context = LogContext::new();
+        log_mdc::iter(|key, value| context.insert(key, value));
+        context
+    }
+
+    /// Adds or replaces the entry `key`.
+    pub fn with<V: ToString>(mut self, key: &str, value: V) -> LogContext {
+        self.insert(key, &value.to_string());
+        self
+    }
+
+    /// The value of the entry `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn insert(&mut self, key: &str, value: &str) {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.entries.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.entries.retain(|(k, _)| k != key);
+    }
+}
+
+impl From<TraceContext> for LogContext {
+    // `trace_id` and `span_id`, as `TraceContext::enter` puts them
+    fn from(trace: TraceContext) -> LogContext {
+        let mut context = LogContext::new();
+        for (key, value) in trace.mdc().iter() {
+            context.insert(key, value);
+        }
+        context
+    }
+}
+
+/// Adds `key` to the context of the current task, which keeps it for
+/// its remaining polls, and to the MDC of the thread.
+///
+/// Returns false outside a task instrumented with `instrument_log`, where
+/// only the MDC of the thread has the entry, as with `log_mdc::insert`.
+pub fn insert_task_mdc<V: ToString>(key: &str, value: V) -> bool {
+    let value = value.to_string();
+    log_mdc::insert(key, value.as_str());
+    CONTEXT
+        .try_with(|context| context.lock().insert(key, &value))
+        .is_ok()
+}
+
+/// Removes `key` from the context of the current task and from the MDC
+/// of the thread.
+pub fn remove_task_mdc(key: &str) -> bool {
+    log_mdc::remove(key);
+    CONTEXT
+        .try_with(|context| context.lock().remove(key))
+        .is_ok()
+}
+
+/// Runs futures with a `LogContext`.
+pub trait InstrumentLog: Future + Sized {
+    /// Puts `context` in the MDC of whichever thread polls the future,
+    /// and only while it polls it.
+    fn instrument_log(self, context: LogContext) -> Instrumented<Self> {
+        Instrumented {
+            inner: Box::pin(self),
+            context: Arc::new(Mutex::new(context)),
+        }
+    }
+}
+
+impl<F: Future> InstrumentLog for F {}
+
+/// A future run with a `LogContext`, see `InstrumentLog::instrument_log`.
+#[must_use = "futures do nothing unless polled"]
+pub struct Instrumented<F> {
+    inner: Pin<Box<F>>,
+    context: Arc<Mutex<LogContext>>,
+}
+
+impl<F> Instrumented<F> {
+    /// The context of the task, with the entries it has added.
+    pub fn context(&self) -> LogContext {
+        self.context.lock().clone()
+    }
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        let context = self.context.clone();
+        let _mdc = ThreadMdc::enter(&context.lock());
+        CONTEXT.sync_scope(context, || self.inner.as_mut().poll(cx))
+    }
+}
+
+// The MDC of the thread before a poll, restored after it, which drops
+// the entries the task added meanwhile.
+struct ThreadMdc(Vec<(String, String)>);
+
+impl ThreadMdc {
+    fn enter(context: &LogContext) -> ThreadMdc {
+        let mut saved = Vec::new();
+        log_mdc::iter(|key, value| saved.push((key.to_string(), value.to_string())));
+        log_mdc::extend(context.entries.iter().cloned());
+        ThreadMdc(saved)
+    }
+}
+
+impl Drop for ThreadMdc {
+    fn drop(&mut self) {
+        log_mdc::clear();
+        log_mdc::extend(self.0.drain(..));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insert_task_mdc, InstrumentLog, LogContext};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+
+    fn mdc(key: &str) -> Option<String> {
+        log_mdc::get(key, |value| value.map(str::to_string))
+    }
+
+    // Pending once, so that the task can move to another thread
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn across_threads() {
+        let mut task = async {
+            assert_eq!(mdc("request").as_deref(), Some("7"));
+            assert!(insert_task_mdc("peer", 3));
+            YieldOnce(false).await;
+            (mdc("request"), mdc("peer"))
+        }
+        .instrument_log(LogContext::new().with("request", 7));
+
+        log_mdc::insert("thread", "first");
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(Pin::new(&mut task).poll(&mut cx).is_pending());
+        assert_eq!(mdc("thread").as_deref(), Some("first"));
+        assert_eq!(mdc("request"), None);
+        assert_eq!(mdc("peer"), None);
+        assert_eq!(task.context().get("peer"), Some("3"));
+
+        let output = thread::spawn(move || {
+            let mut cx = Context::from_waker(Waker::noop());
+            let output = match Pin::new(&mut task).poll(&mut cx) {
+                Poll::Ready(output) => output,
+                Poll::Pending => panic!("pending twice"),
+            };
+            assert_eq!(mdc("request"), None);
+            output
+        })
+        .join()
+        .unwrap();
+        assert_eq!(output, (Some("7".to_string()), Some("3".to_string())));
+        assert!(!insert_task_mdc("peer", 4));
+        log_mdc::clear();
+    }
+}
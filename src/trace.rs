@@ -0,0 +1,232 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// W3C trace context (https://www.w3.org/TR/trace-context/), correlating
+// the records of a request with the distributed trace it belongs to.
+
+use crate::sample;
+use log_mdc::ExtendGuard;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+// The MDC keys of the ids
+const TRACE_ID_KEY: &str = "trace_id";
+const SPAN_ID_KEY: &str = "span_id";
+
+const SAMPLED: u8 = 0x01;
+
+/// The trace and the span of a request, as in a `traceparent` header.
+///
+/// ```
+/// use cita_logger::TraceContext;
+///
+/// let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+/// let context: TraceContext = header.parse().unwrap();
+/// let _trace = context.enter();
+/// // records logged here have trace_id and span_id in their MDC
+/// assert_eq!(context.to_string(), header);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    pub(crate) trace_id: u128,
+    pub(crate) span_id: u64,
+    flags: u8,
+}
+
+impl TraceContext {
+    /// Starts a new sampled trace, for requests coming without a
+    /// `traceparent`.
+    pub fn new_root() -> TraceContext {
+        let trace_id = u128::from(sample::random_u64()) << 64 | u128::from(sample::random_u64());
+        TraceContext {
+            trace_id: trace_id.max(1),
+            span_id: new_span_id(),
+            flags: SAMPLED,
+        }
+    }
+
+    /// A span of the same trace, e.g. for the `traceparent` of an outgoing
+    /// request.
+    pub fn child(&self) -> TraceContext {
+        TraceContext {
+            span_id: new_span_id(),
+            ..*self
+        }
+    }
+
+    /// The context in the MDC of the thread, set by `enter`, taken as
+    /// sampled.
+    pub fn current() -> Option<TraceContext> {
+        let trace_id = log_mdc::get(TRACE_ID_KEY, |id| id.and_then(|id| parse_id(id, 32)))?;
+        let span_id = log_mdc::get(SPAN_ID_KEY, |id| id.and_then(|id| parse_id(id, 16)))?;
+        Some(TraceContext {
+            trace_id,
+            span_id: span_id as u64,
+            flags: SAMPLED,
+        })
+    }
+
+    /// The trace id, 32 hexadecimal digits.
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    /// The span id, 16 hexadecimal digits.
+    pub fn span_id(&self) -> String {
+        format!("{:016x}", self.span_id)
+    }
+
+    /// Whether the caller may have recorded the trace.
+    pub fn sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+
+    /// Puts `trace_id` and `span_id` in the MDC of the thread, until the
+    /// returned guard is dropped.
+    pub fn enter(&self) -> TraceScope {
+        TraceScope {
+            _mdc: log_mdc::extend_scoped(self.mdc()),
+            _thread: PhantomData,
+        }
+    }
+
+    pub(crate) fn mdc(&self) -> [(&'static str, String); 2] {
+        [
+            (TRACE_ID_KEY, self.trace_id()),
+            (SPAN_ID_KEY, self.span_id()),
+        ]
+    }
+}
+
+impl fmt::Display for TraceContext {
+    // The `traceparent` header
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.span_id, self.flags
+        )
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = String;
+
+    // Accepts the fields of later versions after the flags.
+    fn from_str(header: &str) -> Result<TraceContext, String> {
+        let invalid = || format!("invalid traceparent '{}'", header);
+        let header = header.trim();
+        let mut fields = header.splitn(5, '-');
+        let mut next = |len| {
+            fields
+                .next()
+                .filter(|field| field.len() == len)
+                .and_then(|field| parse_id(field, len))
+                .ok_or_else(invalid)
+        };
+        let version = next(2)?;
+        let trace_id = next(32)?;
+        let span_id = next(16)? as u64;
+        let flags = next(2)? as u8;
+        let rest = fields.next();
+        if version == 0xff || (version == 0 && rest.is_some()) || trace_id == 0 || span_id == 0 {
+            return Err(invalid());
+        }
+        Ok(TraceContext {
+            trace_id,
+            span_id,
+            flags,
+        })
+    }
+}
+
+/// Keeps a trace context in the MDC, see `TraceContext::enter`.
+///
+/// It must be dropped on the thread which entered it.
+#[must_use = "the context leaves the MDC as soon as the guard is dropped"]
+pub struct TraceScope {
+    _mdc: ExtendGuard,
+    _thread: PhantomData<*const ()>,
+}
+
+impl fmt::Debug for TraceScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TraceScope").finish()
+    }
+}
+
+fn new_span_id() -> u64 {
+    sample::random_u64().max(1)
+}
+
+// Lowercase hexadecimal digits only, as the specification requires.
+fn parse_id(id: &str, len: usize) -> Option<u128> {
+    if id.len() != len
+        || !id
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    {
+        return None;
+    }
+    u128::from_str_radix(id, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceContext;
+
+    const HEADER: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn parse_and_format() {
+        let context: TraceContext = HEADER.parse().unwrap();
+        assert_eq!(context.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id(), "00f067aa0ba902b7");
+        assert!(context.sampled());
+        assert_eq!(context.to_string(), HEADER);
+
+        let child = context.child();
+        assert_eq!(child.trace_id(), context.trace_id());
+        assert_ne!(child.span_id(), context.span_id());
+        assert!(TraceContext::new_root()
+            .to_string()
+            .parse::<TraceContext>()
+            .is_ok());
+
+        // A later version may add fields
+        assert!(format!("01{}-what-next", &HEADER[2..])
+            .parse::<TraceContext>()
+            .is_ok());
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-600f067aa0ba902b7-01",
+        ] {
+            assert!(invalid.parse::<TraceContext>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn mdc() {
+        let context: TraceContext = HEADER.parse().unwrap();
+        assert_eq!(TraceContext::current(), None);
+        {
+            let _trace = context.enter();
+            assert_eq!(TraceContext::current(), Some(context));
+            log_mdc::get("trace_id", |id| {
+                assert_eq!(id, Some("4bf92f3577b34da6a3ce929d0e0e4736"))
+            });
+        }
+        assert_eq!(TraceContext::current(), None);
+    }
+}
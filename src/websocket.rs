@@ -0,0 +1,253 @@
+// Copyright 2016-2019 Cryptape Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0>.
+// This file may not be copied, modified, or distributed
+// except according to those terms
+
+// A WebSocket server pushing the live records, e.g. to the log view of a
+// node dashboard in a browser.
+//
+// The query of the URL selects the records and their layout:
+// `ws://node:9100/?level=debug&target=cita_network&format=json` pushes
+// the debug records of `cita_network` and its modules as JSON objects.
+// The level defaults to info, the target to any, the format to the text
+// layout of the log file.
+//
+// Requires the `websocket` feature.
+
+use crate::error::{self, InternalErrorKind};
+use crate::live::{self, RecordFilter};
+use crate::ring::RecentRecord;
+use chrono::{DateTime, Local};
+use crossbeam_channel::{bounded, RecvTimeoutError, TrySendError};
+use log::LevelFilter;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::Message;
+
+// Records queued for a client, more are dropped while it lags behind
+const QUEUE_SIZE: usize = 1024;
+// Idle connections are pinged, which finds the clients gone
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
+// Serves the live records on `addr` from threads of their own.
+pub(crate) fn spawn(addr: SocketAddr) -> io::Result<()> {
+    // Bound here, so that an address in use fails the startup
+    let listener = TcpListener::bind(addr)?;
+    serve(listener)
+}
+
+fn serve(listener: TcpListener) -> io::Result<()> {
+    thread::Builder::new()
+        .name("cita-logger-websocket".to_string())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let spawned = thread::Builder::new()
+                    .name("cita-logger-websocket".to_string())
+                    .spawn(move || push(stream));
+                if let Err(e) = spawned {
+                    error::report(InternalErrorKind::Append, &format!("websocket: {}", e));
+                }
+            }
+        })?;
+    Ok(())
+}
+
+// Pushes the records wanted by a client until it goes away.
+fn push(stream: TcpStream) {
+    let mut selected = None;
+    // The error response is the one of tungstenite
+    #[allow(clippy::result_large_err)]
+    let accept = |request: &Request, response: Response| match parse_query(
+        request.uri().query().unwrap_or(""),
+    ) {
+        Ok(query) => {
+            selected = Some(query);
+            Ok(response)
+        }
+        Err(e) => {
+            let mut response = ErrorResponse::new(Some(e));
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            Err(response)
+        }
+    };
+    let mut socket = match tungstenite::accept_hdr(stream, accept) {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    let (filter, format) = match selected {
+        Some(selected) => selected,
+        None => return,
+    };
+
+    let (sender, receiver) = bounded(QUEUE_SIZE);
+    live::subscribe_with(
+        filter,
+        Box::new(move |record| match sender.try_send(record.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        }),
+    );
+    loop {
+        let message = match receiver.recv_timeout(PING_INTERVAL) {
+            Ok(record) => Message::text(encode(&record, format)),
+            Err(RecvTimeoutError::Timeout) => Message::Ping(Default::default()),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if socket.send(message).is_err() {
+            break;
+        }
+    }
+}
+
+// `level=...&target=...&format=...`
+fn parse_query(query: &str) -> Result<(RecordFilter, Format), String> {
+    let mut filter = RecordFilter {
+        level: LevelFilter::Info,
+        target: String::new(),
+    };
+    let mut format = Format::Text;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        match key {
+            "level" => {
+                filter.level = LevelFilter::from_str(&value)
+                    .map_err(|_| format!("invalid level '{}'", value))?
+            }
+            "target" => filter.target = value,
+            "format" => {
+                format = match value.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    _ => return Err(format!("invalid format '{}'", value)),
+                }
+            }
+            _ => return Err(format!("unknown parameter '{}'", key)),
+        }
+    }
+    Ok((filter, format))
+}
+
+// Browsers encode the `::` of the targets as `%3A%3A`.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn encode(record: &RecentRecord, format: Format) -> String {
+    match format {
+        Format::Text => record.to_string(),
+        Format::Json => {
+            let time: DateTime<Local> = record.time.into();
+            serde_json::json!({
+                "time": time.format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string(),
+                "level": record.level.to_string(),
+                "target": record.target,
+                "message": record.message,
+            })
+            .to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_query, percent_decode, serve, Format};
+    use crate::live;
+    use log::{Level, LevelFilter, Record};
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::Message;
+
+    #[test]
+    fn query() {
+        let (filter, format) = parse_query("level=debug&target=cita_network%3A%3Async").unwrap();
+        assert_eq!(filter.level, LevelFilter::Debug);
+        assert_eq!(filter.target, "cita_network::sync");
+        assert_eq!(format, Format::Text);
+        assert_eq!(parse_query("").unwrap().0.level, LevelFilter::Info);
+        assert_eq!(parse_query("format=json").unwrap().1, Format::Json);
+        assert!(parse_query("level=loud").is_err());
+        assert!(parse_query("lines=10").is_err());
+        assert_eq!(percent_decode("a%2"), "a%2");
+    }
+
+    #[test]
+    fn live_tail() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(listener).unwrap();
+
+        let url = format!(
+            "ws://{}/?level=warn&target=ws_test::network&format=json",
+            addr
+        );
+        let (mut socket, _) = tungstenite::connect(url).unwrap();
+        if let MaybeTlsStream::Plain(stream) = socket.get_mut() {
+            stream
+                .set_read_timeout(Some(Duration::from_millis(50)))
+                .unwrap();
+        }
+        // Until the server has subscribed
+        let message = loop {
+            for (level, target) in [
+                (Level::Info, "ws_test::network"),
+                (Level::Error, "ws_test::chain"),
+                (Level::Warn, "ws_test::network::sync"),
+            ] {
+                live::publish(
+                    &Record::builder()
+                        .level(level)
+                        .target(target)
+                        .args(format_args!("peer 3 lagging"))
+                        .build(),
+                );
+            }
+            match socket.read() {
+                Ok(Message::Text(text)) => break text.to_string(),
+                Err(tungstenite::Error::Io(_)) => continue,
+                message => panic!("unexpected {:?}", message),
+            }
+        };
+        let value: serde_json::Value = serde_json::from_str(&message).unwrap();
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["target"], "ws_test::network::sync");
+        assert_eq!(value["message"], "peer 3 lagging");
+    }
+}